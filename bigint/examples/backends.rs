@@ -0,0 +1,75 @@
+extern crate bigint;
+
+use std::time::{Duration, Instant};
+use bigint::U256;
+use bigint::backend::{Backend, Portable, Limb64};
+
+const RUNS: usize = 200000;
+
+fn operands() -> Vec<U256> {
+    let mut ret = Vec::new();
+    let mut seed = U256::from(0x9e3779b97f4a7c15u64);
+    for _ in 0..64 {
+        seed = Portable::overflowing_mul(seed, U256::from(0x5851f42d4c957f2du64)).0;
+        seed = Portable::overflowing_add(seed, U256::from(0x14057b7ef767814fu64)).0;
+        ret.push(seed);
+    }
+    ret
+}
+
+/// Wrapping exponentiation, as done by the `EXP` opcode.
+fn exp<B: Backend>(mut base: U256, mut exponent: U256) -> U256 {
+    let mut ret = U256::one();
+    while exponent != U256::zero() {
+        if exponent & U256::one() == U256::one() {
+            ret = B::overflowing_mul(ret, base).0;
+        }
+        base = B::overflowing_mul(base, base).0;
+        exponent = exponent >> 1;
+    }
+    ret
+}
+
+fn bench<B: Backend>(values: &[U256]) -> (Duration, Duration, Duration, Duration, U256) {
+    let mut acc = U256::zero();
+
+    let start = Instant::now();
+    for i in 0..RUNS {
+        acc = B::overflowing_add(acc, values[i % values.len()]).0;
+    }
+    let add = start.elapsed();
+
+    let start = Instant::now();
+    for i in 0..RUNS {
+        acc = B::overflowing_mul(acc | U256::one(), values[i % values.len()]).0;
+    }
+    let mul = start.elapsed();
+
+    let start = Instant::now();
+    for i in 0..RUNS / 100 {
+        acc = exp::<B>(acc, values[i % values.len()]);
+    }
+    let exp = start.elapsed();
+
+    let start = Instant::now();
+    for i in 0..RUNS {
+        let divisor = values[i % values.len()] >> (i % 256);
+        if divisor != U256::zero() {
+            acc = B::overflowing_add(acc, B::div_rem(values[(i + 1) % values.len()], divisor).1).0;
+        }
+    }
+    let div = start.elapsed();
+
+    (add, mul, exp, div, acc)
+}
+
+/// Compare the throughput of `ADD`, `MUL`, `EXP` and `DIV` on the
+/// arithmetic backends of `U256`.
+fn main() {
+    let values = operands();
+    let (add, mul, exp, div, portable) = bench::<Portable>(&values);
+    println!("portable: add {:?}, mul {:?}, exp {:?}, div {:?}", add, mul, exp, div);
+    let (add, mul, exp, div, limb64) = bench::<Limb64>(&values);
+    println!("limb64:   add {:?}, mul {:?}, exp {:?}, div {:?}", add, mul, exp, div);
+    assert_eq!(portable, limb64);
+}
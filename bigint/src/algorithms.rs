@@ -94,15 +94,11 @@ pub fn mac_with_carry(a: BigDigit, b: BigDigit, c: BigDigit, carry: &mut BigDigi
 }
 
 pub fn inc(a: &mut [BigDigit]) -> BigDigit {
-    let mut added = false;
-    let mut carry = 0;
+    let mut carry = 1;
 
     for a in a.iter_mut().rev() {
         let a: &mut BigDigit = a;
-        if !added {
-            *a = adc(*a, 1, &mut carry);
-            added = true;
-        }
+        *a = adc(*a, 0, &mut carry);
     }
 
     carry
@@ -0,0 +1,276 @@
+//! Arithmetic backends of `U256`
+//!
+//! `U256` and the types built on it (`M256`, `MI256`) send their
+//! addition, subtraction, multiplication and division through the
+//! `Backend` selected at compile time. `Portable` is the default. The
+//! `u64-backend` feature selects `Limb64`, which works on 64-bit limbs
+//! with 128-bit intermediates.
+
+use super::{U256, Sign};
+use super::algorithms::{add2, mac3, from_signed, sub2_sign};
+
+/// Wrapping 256-bit arithmetic. Every operation must give the same
+/// result as `Portable`.
+pub trait Backend {
+    /// Add, returning whether the result overflowed.
+    fn overflowing_add(a: U256, b: U256) -> (U256, bool);
+    /// Subtract, returning whether the result underflowed.
+    fn underflowing_sub(a: U256, b: U256) -> (U256, bool);
+    /// Multiply, returning whether the result overflowed.
+    fn overflowing_mul(a: U256, b: U256) -> (U256, bool);
+    /// Quotient and remainder. Panics if `b` is zero.
+    fn div_rem(a: U256, b: U256) -> (U256, U256);
+}
+
+#[cfg(not(feature = "u64-backend"))]
+/// The backend used by `U256`.
+pub type Selected = Portable;
+
+#[cfg(feature = "u64-backend")]
+/// The backend used by `U256`.
+pub type Selected = Limb64;
+
+/// Backend on 32-bit limbs, with bitwise long division.
+pub struct Portable;
+
+impl Backend for Portable {
+    fn overflowing_add(a: U256, b: U256) -> (U256, bool) {
+        let mut a: [u32; 8] = a.into();
+        let b: [u32; 8] = b.into();
+
+        let carry = add2(&mut a, &b);
+        (a.into(), carry > 0)
+    }
+
+    fn underflowing_sub(a: U256, b: U256) -> (U256, bool) {
+        let mut a: [u32; 8] = a.into();
+        let b: [u32; 8] = b.into();
+
+        let sign = sub2_sign(&mut a, &b);
+        from_signed(sign, &mut a);
+        (a.into(), sign == Sign::Minus)
+    }
+
+    fn overflowing_mul(a: U256, b: U256) -> (U256, bool) {
+        let mut ret = [0u32; 8];
+        let a: [u32; 8] = a.into();
+        let b: [u32; 8] = b.into();
+
+        let mut overflow = false;
+
+        for (i, bi) in b.iter().rev().enumerate() {
+            // Only the lower `8 - i` limbs of `a` fit into the result
+            // for this limb of `b`; the others overflow if non-zero.
+            let carry = mac3(&mut ret[0..(8-i)], &a, *bi);
+            if carry > 0 || (*bi != 0 && a[0..i].iter().any(|&v| v != 0)) {
+                overflow = true;
+            }
+        }
+
+        (ret.into(), overflow)
+    }
+
+    fn div_rem(a: U256, b: U256) -> (U256, U256) {
+        let mut sub_copy = a;
+        let mut shift_copy = b;
+        let mut ret = [0u32; 8];
+
+        let my_bits = a.bits();
+        let your_bits = b.bits();
+
+        // Check for division by 0
+        assert!(your_bits != 0);
+
+        // Early return in case we are dividing by a larger number than us
+        if my_bits < your_bits {
+            return (ret.into(), a);
+        }
+
+        // Bitwise long division
+        let mut shift = my_bits - your_bits;
+        shift_copy = shift_copy << shift;
+        loop {
+            if sub_copy >= shift_copy {
+                ret[7 - shift / 32] |= 1 << (shift % 32);
+                sub_copy = sub_copy - shift_copy;
+            }
+            shift_copy = shift_copy >> 1;
+            if shift == 0 { break; }
+            shift -= 1;
+        }
+
+        (ret.into(), sub_copy)
+    }
+}
+
+/// Backend on 64-bit limbs, with 128-bit intermediates and short
+/// division for divisors that fit into a limb.
+pub struct Limb64;
+
+/// Limbs from the least significant one.
+fn to_limbs(value: U256) -> [u64; 4] {
+    let arr: [u32; 8] = value.into();
+    let mut ret = [0u64; 4];
+    for i in 0..4 {
+        ret[i] = ((arr[6 - 2 * i] as u64) << 32) | (arr[7 - 2 * i] as u64);
+    }
+    ret
+}
+
+fn from_limbs(limbs: [u64; 4]) -> U256 {
+    let mut ret = [0u32; 8];
+    for i in 0..4 {
+        ret[6 - 2 * i] = (limbs[i] >> 32) as u32;
+        ret[7 - 2 * i] = limbs[i] as u32;
+    }
+    ret.into()
+}
+
+fn limbs_ge(a: &[u64; 4], b: &[u64; 4]) -> bool {
+    for i in (0..4).rev() {
+        if a[i] != b[i] {
+            return a[i] > b[i];
+        }
+    }
+    true
+}
+
+fn limbs_sub(a: &mut [u64; 4], b: &[u64; 4]) -> bool {
+    let mut borrow = false;
+    for i in 0..4 {
+        let (v, o1) = a[i].overflowing_sub(b[i]);
+        let (v, o2) = v.overflowing_sub(borrow as u64);
+        a[i] = v;
+        borrow = o1 || o2;
+    }
+    borrow
+}
+
+impl Backend for Limb64 {
+    fn overflowing_add(a: U256, b: U256) -> (U256, bool) {
+        let mut a = to_limbs(a);
+        let b = to_limbs(b);
+
+        let mut carry = false;
+        for i in 0..4 {
+            let (v, o1) = a[i].overflowing_add(b[i]);
+            let (v, o2) = v.overflowing_add(carry as u64);
+            a[i] = v;
+            carry = o1 || o2;
+        }
+        (from_limbs(a), carry)
+    }
+
+    fn underflowing_sub(a: U256, b: U256) -> (U256, bool) {
+        let mut a = to_limbs(a);
+        let b = to_limbs(b);
+
+        let borrow = limbs_sub(&mut a, &b);
+        (from_limbs(a), borrow)
+    }
+
+    fn overflowing_mul(a: U256, b: U256) -> (U256, bool) {
+        let a = to_limbs(a);
+        let b = to_limbs(b);
+        let mut ret = [0u64; 4];
+        let mut overflow = false;
+
+        for i in 0..4 {
+            if a[i] == 0 {
+                continue;
+            }
+            let mut carry = 0u128;
+            for j in 0..4 {
+                if i + j < 4 {
+                    let v = ret[i + j] as u128 + (a[i] as u128) * (b[j] as u128) + carry;
+                    ret[i + j] = v as u64;
+                    carry = v >> 64;
+                } else if b[j] != 0 {
+                    overflow = true;
+                }
+            }
+            if carry != 0 {
+                overflow = true;
+            }
+        }
+        (from_limbs(ret), overflow)
+    }
+
+    fn div_rem(a: U256, b: U256) -> (U256, U256) {
+        let bits = a.bits();
+        let a = to_limbs(a);
+        let b = to_limbs(b);
+        assert!(b.iter().any(|&v| v != 0));
+
+        if b[1] == 0 && b[2] == 0 && b[3] == 0 {
+            let divisor = b[0] as u128;
+            let mut quotient = [0u64; 4];
+            let mut rem = 0u128;
+            for i in (0..4).rev() {
+                let cur = (rem << 64) | (a[i] as u128);
+                quotient[i] = (cur / divisor) as u64;
+                rem = cur % divisor;
+            }
+            return (from_limbs(quotient), from_limbs([rem as u64, 0, 0, 0]));
+        }
+
+        let mut quotient = [0u64; 4];
+        let mut rem = [0u64; 4];
+        for i in (0..bits).rev() {
+            // Shift the next bit of `a` into the remainder. The bit
+            // shifted out only matters when the divisor is above 2^255.
+            let out = rem[3] >> 63;
+            for j in (1..4).rev() {
+                rem[j] = (rem[j] << 1) | (rem[j - 1] >> 63);
+            }
+            rem[0] = (rem[0] << 1) | ((a[i / 64] >> (i % 64)) & 1);
+            if out == 1 || limbs_ge(&rem, &b) {
+                limbs_sub(&mut rem, &b);
+                quotient[i / 64] |= 1 << (i % 64);
+            }
+        }
+        (from_limbs(quotient), from_limbs(rem))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use U256;
+    use super::{Backend, Portable, Limb64};
+
+    fn samples() -> Vec<U256> {
+        let mut ret = vec![U256::zero(), U256::one(), U256::from(2u64), U256::from(0xffffffffu64),
+                           U256::from(u64::max_value()), U256::max_value(),
+                           U256::max_value() - U256::one(), U256::one() << 255, U256::one() << 128];
+        let mut seed = U256::from(0x9e3779b97f4a7c15u64);
+        for i in 0..24 {
+            seed = Portable::overflowing_mul(seed, U256::from(0x5851f42d4c957f2du64)).0;
+            seed = Portable::overflowing_add(seed, U256::from(0x14057b7ef767814fu64)).0;
+            ret.push(seed >> (i * 10));
+        }
+        ret
+    }
+
+    #[test]
+    fn mul_overflow() {
+        let high = U256::one() << 200;
+        assert!(Portable::overflowing_mul(high, high).1);
+        assert!(Limb64::overflowing_mul(high, high).1);
+        assert!(!Portable::overflowing_mul(U256::one() << 128, U256::max_value() >> 128).1);
+    }
+
+    #[test]
+    fn limb64_matches_portable() {
+        let samples = samples();
+        for &a in samples.iter() {
+            for &b in samples.iter() {
+                assert_eq!(Limb64::overflowing_add(a, b), Portable::overflowing_add(a, b));
+                assert_eq!(Limb64::underflowing_sub(a, b), Portable::underflowing_sub(a, b));
+                assert_eq!(Limb64::overflowing_mul(a, b), Portable::overflowing_mul(a, b));
+                if b != U256::zero() {
+                    assert_eq!(Limb64::div_rem(a, b), Portable::div_rem(a, b));
+                }
+            }
+        }
+    }
+}
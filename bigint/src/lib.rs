@@ -1,7 +1,24 @@
-mod algorithms;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
 extern crate rlp;
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+
+/// Stands in for `std` without the `std` feature, so that `std::`
+/// paths resolve to `core`.
+#[cfg(not(feature = "std"))]
+mod std {
+    pub use core::*;
+}
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+mod algorithms;
 
 mod m256;
+pub mod backend;
 mod mi256;
 mod u256;
 mod u512;
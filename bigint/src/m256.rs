@@ -7,6 +7,7 @@ use std::cmp::Ordering;
 use std::fmt;
 
 use super::{ParseHexError, U512, U256};
+#[cfg(feature = "std")]
 use rlp::{Encodable, RlpStream};
 
 #[derive(Eq, PartialEq, Debug, Copy, Clone, Hash)]
@@ -38,6 +39,7 @@ impl FromStr for M256 {
     }
 }
 
+#[cfg(feature = "std")]
 impl Encodable for M256 {
     fn rlp_append(&self, s: &mut RlpStream) {
         let leading_empty_bytes = 32 - (self.bits() + 7) / 8;
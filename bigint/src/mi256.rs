@@ -72,7 +72,9 @@ impl Div for MI256 {
     type Output = MI256;
 
     fn div(self, other: MI256) -> MI256 {
-        let d = (self.1 / other.1) & SIGN_BIT_MASK.into();
+        // Not masked: `MIN / -1` has magnitude 2^255, which converts
+        // back to `MIN`.
+        let d = self.1 / other.1;
 
         if d == M256::zero() {
             return MI256::zero();
@@ -40,8 +40,9 @@ use std::ops::{Add, Sub, Not, Mul, Div, Shr, Shl, BitAnd, BitOr, BitXor, Rem};
 use std::cmp::Ordering;
 use std::fmt;
 
-use super::{Sign, ParseHexError, read_hex};
-use super::algorithms::{add2, mac3, from_signed, sub2_sign, big_digit};
+use super::{ParseHexError, read_hex};
+use super::algorithms::big_digit;
+use super::backend::{Backend, Selected};
 
 pub const SIGN_BIT_MASK: U256 = U256([0b01111111111111111111111111111111u32,
                                       0xffffffffu32, 0xffffffffu32, 0xffffffffu32,
@@ -68,37 +69,18 @@ impl U256 {
     }
 
     /// Add two U256 with overflowing. The same as M256::add.
-    pub fn overflowing_add(mut self, other: U256) -> (U256, bool) {
-        let U256(ref mut a) = self;
-        let U256(ref b) = other;
-
-        let carry = add2(a, b);
-        (U256(*a), if carry > 0 { true } else { false })
+    pub fn overflowing_add(self, other: U256) -> (U256, bool) {
+        Selected::overflowing_add(self, other)
     }
 
     /// Substract two U256 with underflowing. The same as M256::sub.
-    pub fn underflowing_sub(mut self, other: U256) -> (U256, bool) {
-        let U256(ref mut a) = self;
-        let U256(ref b) = other;
-
-        let sign = sub2_sign(a, b);
-        from_signed(sign, a);
-        (U256(*a), if sign == Sign::Minus { true } else { false })
+    pub fn underflowing_sub(self, other: U256) -> (U256, bool) {
+        Selected::underflowing_sub(self, other)
     }
 
     /// Multiply two U256 with overflowing. The same as M256::mul.
-    pub fn overflowing_mul(mut self, other: U256) -> (U256, bool) {
-        let mut ret = [0u32; 8];
-        let U256(ref mut a) = self;
-        let U256(ref b) = other;
-
-        let mut carry = 0;
-
-        for (i, bi) in b.iter().rev().enumerate() {
-            carry = mac3(&mut ret[0..(8-i)], a, *bi);
-        }
-
-        (U256(ret), if carry > 0 { true } else { false })
+    pub fn overflowing_mul(self, other: U256) -> (U256, bool) {
+        Selected::overflowing_mul(self, other)
     }
 
     /// Bits needed to represent this value.
@@ -378,35 +360,7 @@ impl Div for U256 {
     type Output = U256;
 
     fn div(self, other: U256) -> U256 {
-        let mut sub_copy = self;
-        let mut shift_copy = other;
-        let mut ret = [0u32; 8];
-
-        let my_bits = self.bits();
-        let your_bits = other.bits();
-
-        // Check for division by 0
-        assert!(your_bits != 0);
-
-        // Early return in case we are dividing by a larger number than us
-        if my_bits < your_bits {
-            return U256(ret);
-        }
-
-        // Bitwise long division
-        let mut shift = my_bits - your_bits;
-        shift_copy = shift_copy << shift;
-        loop {
-            if sub_copy >= shift_copy {
-                ret[7 - shift / 32] |= 1 << (shift % 32);
-                sub_copy = sub_copy - shift_copy;
-            }
-            shift_copy = shift_copy >> 1;
-            if shift == 0 { break; }
-            shift -= 1;
-        }
-
-        U256(ret)
+        Selected::div_rem(self, other).0
     }
 }
 
@@ -414,8 +368,7 @@ impl Rem for U256 {
     type Output = U256;
 
     fn rem(self, other: U256) -> U256 {
-        let d = self / other;
-        self - (other * d)
+        Selected::div_rem(self, other).1
     }
 }
 
@@ -1,11 +1,14 @@
-use sputnikvm::{Gas, M256, U256, Address, read_hex};
+use sputnikvm::{Gas, M256, U256, Address, read_hex, sec_trie_root, storage_root, account_rlp};
 use sputnikvm::vm::{Machine, Log, Context,
                     Account, HashMapStorage, AccountCommitment,
                     BlockHeader};
 
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
+use crypto::sha3::Sha3;
+use crypto::digest::Digest;
+use rlp::RlpStream;
 
 pub struct JSONBlock {
     codes: HashMap<Address, Vec<u8>>,
@@ -24,6 +27,22 @@ pub struct JSONBlock {
 
 static EMPTY: [u8; 0] = [];
 
+pub fn keccak(data: &[u8]) -> [u8; 32] {
+    let mut sha3 = Sha3::keccak256();
+    sha3.input(data);
+    let mut ret = [0u8; 32];
+    sha3.result(&mut ret);
+    ret
+}
+
+/// Append a hash as a 32 bytes string. `M256` itself is encoded as
+/// an integer, without leading zeros.
+fn append_hash(stream: &mut RlpStream, hash: M256) {
+    let hash: [u8; 32] = hash.into();
+    let hash: &[u8] = &hash;
+    stream.append(&hash);
+}
+
 impl JSONBlock {
     pub fn block_header(&self) -> BlockHeader {
         BlockHeader {
@@ -32,6 +51,8 @@ impl JSONBlock {
             number: self.number,
             difficulty: self.difficulty,
             gas_limit: self.gas_limit,
+            base_fee: Gas::zero(),
+            blob_base_fee: Gas::zero(),
         }
     }
 
@@ -155,6 +176,55 @@ impl JSONBlock {
         v.insert(index, val);
     }
 
+    pub fn remove_account(&mut self, address: Address) {
+        self.codes.remove(&address);
+        self.balances.remove(&address);
+        self.storages.remove(&address);
+        self.nonces.remove(&address);
+    }
+
+    pub fn is_empty_account(&self, address: Address) -> bool {
+        self.balance(address) == U256::zero() && self.account_nonce(address) == M256::zero() &&
+            self.account_code(address).len() == 0
+    }
+
+    /// Root hash of the world state, where every address with a
+    /// balance, nonce, code or storage entry is an account.
+    pub fn state_root(&self) -> M256 {
+        let mut addresses = HashSet::new();
+        addresses.extend(self.codes.keys());
+        addresses.extend(self.balances.keys());
+        addresses.extend(self.storages.keys());
+        addresses.extend(self.nonces.keys());
+
+        let accounts = addresses.into_iter().map(|address| {
+            let storage = match self.storages.get(&address) {
+                Some(storage) => storage_root(storage),
+                None => storage_root(&HashMap::new()),
+            };
+            let account = account_rlp(self.account_nonce(address), self.balance(address),
+                                      storage, self.account_code(address));
+            let address: [u8; 20] = address.into();
+            (address.to_vec(), account)
+        }).collect();
+        sec_trie_root(accounts)
+    }
+
+    /// Hash of the RLP list of all logs.
+    pub fn logs_hash(&self) -> M256 {
+        let mut stream = RlpStream::new_list(self.logs.len());
+        for log in &self.logs {
+            stream.begin_list(3);
+            stream.append(&log.address);
+            stream.begin_list(log.topics.len());
+            for topic in &log.topics {
+                append_hash(&mut stream, *topic);
+            }
+            stream.append(&log.data);
+        }
+        M256::from(keccak(&stream.out()))
+    }
+
     pub fn find_log(&self, address: Address, data: &[u8], topics: &[M256]) -> bool {
         for log in &self.logs {
             if log.address == address && log.data.as_slice() == data && log.topics.as_slice() == topics {
@@ -231,5 +301,8 @@ pub fn create_context(v: &Value) -> Context {
         gas_price: gas_price,
         origin: origin,
         value: value,
+        is_static: false,
+        is_create: false,
+        blob_versioned_hashes: Vec::new(),
     }
 }
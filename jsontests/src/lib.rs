@@ -1,51 +1,81 @@
 extern crate sputnikvm;
 extern crate serde_json;
+extern crate rlp;
+extern crate crypto;
+extern crate libsecp256k1;
 
 mod blockchain;
+mod state;
 
 pub use self::blockchain::{JSONBlock, create_block, create_context};
+pub use self::state::test_state;
 
 use serde_json::Value;
 use std::str::FromStr;
 use sputnikvm::{Gas, M256, U256, Address, read_hex};
 use sputnikvm::vm::errors::RequireError;
-use sputnikvm::vm::{VM, SeqVM, AccountCommitment, Context, Account, HashMapStorage, Patch, VMStatus};
+use sputnikvm::vm::{VM, SeqVM, AccountCommitment, Context, Account, HashMapStorage, Patch, Fork, VMStatus};
 
 pub fn fire_with_block(machine: &mut SeqVM, block: &JSONBlock) {
     loop {
         match machine.fire() {
-            Err(RequireError::Account(address)) => {
-                let account = block.request_account(address);
-                machine.commit_account(account);
-            },
-            Err(RequireError::AccountCode(address)) => {
-                let account = block.request_account_code(address);
-                machine.commit_account(account);
-            },
-            Err(RequireError::Blockhash(number)) => {
-                // The test JSON file doesn't expose any block
-                // information. So those numbers are crafted by hand.
-                let hash1 = M256::from_str("0xc89efdaa54c0f20c7adf612882df0950f5a951637e0307cdcb4c672f298b8bc6").unwrap();
-                let hash2 = M256::from_str("0xad7c5bef027816a800da1736444fb58a807ef4c9603b7848673f7e3a68eb14a5").unwrap();
-                let hash256 = M256::from_str("0x6ca54da2c4784ea43fd88b3402de07ae4bced597cbb19f323b7595857a6720ae").unwrap();
-
-                let hash = if number == M256::from(1u64) {
-                    hash1
-                } else if number == M256::from(2u64) {
-                    hash2
-                } else if number == M256::from(256u64) {
-                    hash256
-                } else {
-                    panic!();
-                };
+            Err(require) => commit_require(machine, block, require),
+            Ok(()) => return,
+        }
+    }
+}
 
-                machine.commit_blockhash(number, hash);
+/// Run the VM without running any sub runtimes. VM tests only record
+/// sub calls in `callcreates`, and expect them to leave the caller as
+/// if they had succeeded without using any gas.
+pub fn fire_skipping_invokes(machine: &mut SeqVM, block: &JSONBlock) {
+    loop {
+        if machine.pending_invoke().is_some() {
+            machine.skip_invoke();
+            continue;
+        }
+        match machine.status() {
+            VMStatus::Running => match machine.step() {
+                Err(require) => commit_require(machine, block, require),
+                Ok(()) => (),
             },
-            Ok(()) => return,
+            VMStatus::ExitedOk | VMStatus::ExitedErr(_) => return,
         }
     }
 }
 
+fn commit_require(machine: &mut SeqVM, block: &JSONBlock, require: RequireError) {
+    match require {
+        RequireError::Account(address) => {
+            let account = block.request_account(address);
+            machine.commit_account(account);
+        },
+        RequireError::AccountCode(address) => {
+            let account = block.request_account_code(address);
+            machine.commit_account(account);
+        },
+        RequireError::Blockhash(number) => {
+            // The test JSON file doesn't expose any block
+            // information. So those numbers are crafted by hand.
+            let hash1 = M256::from_str("0xc89efdaa54c0f20c7adf612882df0950f5a951637e0307cdcb4c672f298b8bc6").unwrap();
+            let hash2 = M256::from_str("0xad7c5bef027816a800da1736444fb58a807ef4c9603b7848673f7e3a68eb14a5").unwrap();
+            let hash256 = M256::from_str("0x6ca54da2c4784ea43fd88b3402de07ae4bced597cbb19f323b7595857a6720ae").unwrap();
+
+            let hash = if number == M256::from(1u64) {
+                hash1
+            } else if number == M256::from(2u64) {
+                hash2
+            } else if number == M256::from(256u64) {
+                hash256
+            } else {
+                panic!();
+            };
+
+            machine.commit_blockhash(number, hash);
+        },
+    }
+}
+
 pub fn apply_to_block(machine: &SeqVM, block: &mut JSONBlock) {
     for account in machine.accounts() {
         let account = (*account).clone();
@@ -57,10 +87,36 @@ pub fn apply_to_block(machine: &SeqVM, block: &mut JSONBlock) {
     }
 }
 
+/// Hard fork of the given name, as used by the post states of general
+/// state tests and the optional `fork` field of VM tests.
+pub fn fork_by_name(name: &str) -> Option<Fork> {
+    match name {
+        "Frontier" => Some(Fork::Frontier),
+        "Homestead" => Some(Fork::Homestead),
+        "EIP150" => Some(Fork::EIP150),
+        "EIP158" => Some(Fork::EIP161),
+        "Byzantium" => Some(Fork::Byzantium),
+        "Constantinople" | "ConstantinopleFix" => Some(Fork::Constantinople),
+        "Istanbul" => Some(Fork::Istanbul),
+        "Berlin" => Some(Fork::Berlin),
+        "London" => Some(Fork::London),
+        "Merge" | "Paris" => Some(Fork::Merge),
+        "Shanghai" => Some(Fork::Shanghai),
+        "Cancun" => Some(Fork::Cancun),
+        _ => None,
+    }
+}
+
+/// Create the VM of a VM test. The test runs on Frontier unless it
+/// names another fork in its `fork` field.
 pub fn create_machine(v: &Value, block: &JSONBlock) -> SeqVM {
     let transaction = create_context(v);
+    let fork = match v["fork"].as_str() {
+        Some(name) => fork_by_name(name).unwrap(),
+        None => Fork::Frontier,
+    };
 
-    SeqVM::new(transaction, block.block_header(), Patch::None)
+    SeqVM::new(transaction, block.block_header(), Patch::new(fork))
 }
 
 pub fn test_machine(v: &Value, machine: &SeqVM, block: &JSONBlock, debug: bool) -> bool {
@@ -246,7 +302,7 @@ fn is_ok(status: VMStatus) -> bool {
 pub fn test_transaction(name: &str, v: &Value, debug: bool) -> bool {
     let mut block = create_block(v);
     let mut machine = create_machine(v, &block);
-    fire_with_block(&mut machine, &block);
+    fire_skipping_invokes(&mut machine, &block);
     apply_to_block(&machine, &mut block);
 
     println!("status: {:?}", machine.status());
@@ -0,0 +1,166 @@
+use sputnikvm::{Gas, M256, U256, Address, read_hex};
+use sputnikvm::vm::{VM, SeqVM, Context, Patch, Fork, VMStatus, intrinsic_gas};
+use libsecp256k1::{SecretKey, PublicKey};
+
+use serde_json::Value;
+use std::str::FromStr;
+
+use super::{JSONBlock, create_block, fire_with_block, apply_to_block, fork_by_name};
+use super::blockchain::keccak;
+
+/// Address of the account controlled by the 32-byte secret key
+/// `secret`, or `None` if it is not a valid key.
+fn secret_to_address(secret: &[u8]) -> Option<Address> {
+    if secret.len() != 32 {
+        return None;
+    }
+    let mut key = [0u8; 32];
+    key.copy_from_slice(secret);
+    let secret = match SecretKey::parse(&key) {
+        Ok(secret) => secret,
+        Err(_) => return None,
+    };
+
+    let public = PublicKey::from_secret_key(&secret);
+    let digest = keccak(&public.serialize()[1..65]);
+    Some(Address::from(M256::from(&digest[12..32])))
+}
+
+/// Run the transaction selected by `indexes` on the pre state, and
+/// return the post state. Panics on contract creation transactions,
+/// which are not supported.
+fn run_transaction(v: &Value, fork: Fork, indexes: &Value) -> JSONBlock {
+    let mut block = create_block(v);
+    for (address, account) in v["pre"].as_object().unwrap() {
+        let address = Address::from_str(address.as_str()).unwrap();
+        let nonce = M256::from_str(account["nonce"].as_str().unwrap()).unwrap();
+        block.set_account_nonce(address, nonce);
+    }
+
+    let ref transaction = v["transaction"];
+    let secret = read_hex(transaction["secretKey"].as_str().unwrap()).unwrap();
+    let sender = secret_to_address(&secret).unwrap();
+    let to = match transaction["to"].as_str().unwrap() {
+        "" => panic!("contract creation transactions are not supported"),
+        to => Address::from_str(to).unwrap(),
+    };
+    let index = |name: &str| indexes[name].as_u64().unwrap() as usize;
+    let data = read_hex(transaction["data"][index("data")].as_str().unwrap()).unwrap();
+    let gas_limit = Gas::from_str(transaction["gasLimit"][index("gas")].as_str().unwrap()).unwrap();
+    let value = U256::from_str(transaction["value"][index("value")].as_str().unwrap()).unwrap();
+    let gas_price = Gas::from_str(transaction["gasPrice"].as_str().unwrap()).unwrap();
+
+    // Buy the gas, bump the nonce and transfer the value.
+    let patch = Patch::new(fork);
+    let upfront: U256 = (gas_limit * gas_price).into();
+    let nonce = block.account_nonce(sender);
+    block.set_account_nonce(sender, nonce + M256::from(1u64));
+    let balance = block.balance(sender);
+    block.set_balance(sender, balance - upfront - value);
+    let balance = block.balance(to);
+    block.set_balance(to, balance + value);
+
+    let context = Context {
+        address: to,
+        caller: sender,
+        code: block.account_code(to).into(),
+        data: data.clone(),
+        gas_limit: gas_limit - intrinsic_gas(&patch.gas, &data),
+        gas_price: gas_price,
+        origin: sender,
+        value: value,
+        is_static: false,
+        is_create: false,
+        blob_versioned_hashes: Vec::new(),
+    };
+    let mut machine = SeqVM::new(context, block.block_header(), patch);
+    fire_with_block(&mut machine, &block);
+    match machine.status() {
+        VMStatus::ExitedOk => {
+            apply_to_block(&machine, &mut block);
+            for address in machine.state_diff().deleted {
+                block.remove_account(address);
+            }
+        },
+        _ => {
+            let balance = block.balance(to);
+            block.set_balance(to, balance - value);
+            let balance = block.balance(sender);
+            block.set_balance(sender, balance + value);
+        },
+    }
+
+    // Refund the remaining gas and pay the miner.
+    let gas_remaining = machine.gas_remaining();
+    let refund: U256 = (gas_remaining * gas_price).into();
+    let balance = block.balance(sender);
+    block.set_balance(sender, balance + refund);
+    let fee: U256 = ((gas_limit - gas_remaining) * gas_price).into();
+    let coinbase = block.coinbase();
+    let balance = block.balance(coinbase);
+    block.set_balance(coinbase, balance + fee);
+
+    if fork >= Fork::EIP161 {
+        for address in vec![to, coinbase] {
+            if block.is_empty_account(address) {
+                block.remove_account(address);
+            }
+        }
+    }
+
+    block
+}
+
+/// Run a general state test, and check the state root and logs hash
+/// of every post state whose fork is supported. Forks from London on
+/// are skipped, because the fee market is not implemented by the
+/// runner.
+pub fn test_state(name: &str, v: &Value, debug: bool) -> bool {
+    for (fork_name, posts) in v["post"].as_object().unwrap() {
+        let fork = match fork_by_name(fork_name.as_str()) {
+            Some(fork) if fork <= Fork::Berlin => fork,
+            _ => continue,
+        };
+
+        for post in posts.as_array().unwrap() {
+            let block = run_transaction(v, fork, &post["indexes"]);
+
+            let hash = M256::from_str(post["hash"].as_str().unwrap()).unwrap();
+            if block.state_root() != hash {
+                if debug {
+                    println!("\n{} ({}): state root mismatch.", name, fork_name);
+                    println!("Expected: 0x{:x}", hash);
+                    println!("Actual:   0x{:x}", block.state_root());
+                }
+                return false;
+            }
+
+            let logs = M256::from_str(post["logs"].as_str().unwrap()).unwrap();
+            if block.logs_hash() != logs {
+                if debug {
+                    println!("\n{} ({}): logs hash mismatch.", name, fork_name);
+                    println!("Expected: 0x{:x}", logs);
+                    println!("Actual:   0x{:x}", block.logs_hash());
+                }
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use sputnikvm::{M256, Address, read_hex};
+    use super::secret_to_address;
+
+    #[test]
+    fn address_of_secret() {
+        let secret = read_hex("0x45a915e4d060149eb4365960e6a7a45f334393093061116b197e3240065ff2d8").unwrap();
+        assert_eq!(secret_to_address(&secret),
+                   Some(Address::from(M256::from(read_hex("0xa94f5374fce5edbc8e2a8697c15331677e6ebf0b")
+                                                 .unwrap().as_slice()))));
+        assert_eq!(secret_to_address(&[0u8; 32]), None);
+    }
+}
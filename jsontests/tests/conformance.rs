@@ -0,0 +1,33 @@
+#![allow(non_snake_case)]
+
+extern crate jsontests;
+extern crate serde_json;
+#[macro_use]
+extern crate lazy_static;
+
+use serde_json::Value;
+use jsontests::test_transaction;
+
+lazy_static! {
+    static ref TESTS: Value =
+        serde_json::from_str(include_str!("files/vmConformanceTest.json")).unwrap();
+}
+
+#[test] fn modByZero() { assert_eq!(test_transaction("modByZero", &TESTS["modByZero"], true), true); }
+#[test] fn modZeroByZero() { assert_eq!(test_transaction("modZeroByZero", &TESTS["modZeroByZero"], true), true); }
+#[test] fn smodByZero() { assert_eq!(test_transaction("smodByZero", &TESTS["smodByZero"], true), true); }
+#[test] fn divByZero() { assert_eq!(test_transaction("divByZero", &TESTS["divByZero"], true), true); }
+#[test] fn divZeroByZero() { assert_eq!(test_transaction("divZeroByZero", &TESTS["divZeroByZero"], true), true); }
+#[test] fn sdivByZero() { assert_eq!(test_transaction("sdivByZero", &TESTS["sdivByZero"], true), true); }
+#[test] fn sdivMinByMinusOne() { assert_eq!(test_transaction("sdivMinByMinusOne", &TESTS["sdivMinByMinusOne"], true), true); }
+#[test] fn swap1() { assert_eq!(test_transaction("swap1", &TESTS["swap1"], true), true); }
+#[test] fn push0() { assert_eq!(test_transaction("push0", &TESTS["push0"], true), true); }
+#[test] fn push0BeforeShanghai() { assert_eq!(test_transaction("push0BeforeShanghai", &TESTS["push0BeforeShanghai"], true), true); }
+#[test] fn gasStop() { assert_eq!(test_transaction("gasStop", &TESTS["gasStop"], true), true); }
+#[test] fn gasReturn() { assert_eq!(test_transaction("gasReturn", &TESTS["gasReturn"], true), true); }
+#[test] fn gasJumpdest() { assert_eq!(test_transaction("gasJumpdest", &TESTS["gasJumpdest"], true), true); }
+#[test] fn gasAddress() { assert_eq!(test_transaction("gasAddress", &TESTS["gasAddress"], true), true); }
+#[test] fn gasAdd() { assert_eq!(test_transaction("gasAdd", &TESTS["gasAdd"], true), true); }
+#[test] fn gasMul() { assert_eq!(test_transaction("gasMul", &TESTS["gasMul"], true), true); }
+#[test] fn gasAddmod() { assert_eq!(test_transaction("gasAddmod", &TESTS["gasAddmod"], true), true); }
+#[test] fn gasJumpi() { assert_eq!(test_transaction("gasJumpi", &TESTS["gasJumpi"], true), true); }
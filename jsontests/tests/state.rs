@@ -0,0 +1,18 @@
+#![allow(non_snake_case)]
+
+extern crate jsontests;
+extern crate serde_json;
+#[macro_use]
+extern crate lazy_static;
+
+use serde_json::Value;
+use jsontests::test_state;
+
+lazy_static! {
+    static ref TESTS: Value =
+        serde_json::from_str(include_str!("files/stBasicTest.json")).unwrap();
+}
+
+#[test] fn add() { assert_eq!(test_state("add", &TESTS["add"], true), true); }
+#[test] fn call() { assert_eq!(test_state("call", &TESTS["call"], true), true); }
+#[test] fn sstore() { assert_eq!(test_state("sstore", &TESTS["sstore"], true), true); }
@@ -0,0 +1,94 @@
+extern crate sputnikvm;
+
+use std::time::Instant;
+use sputnikvm::{Gas, M256, U256, Address, read_hex};
+use sputnikvm::vm::{Machine, MachineStatus, Context, BlockHeader, Patch, Fork, PC,
+                    AccountCommitment, SeqMemory, HashMapStorage};
+use sputnikvm::vm::errors::RequireError;
+
+type SeqMachine = Machine<SeqMemory, HashMapStorage>;
+
+const RUNS: usize = 20;
+const DECODES: usize = 200;
+
+fn context() -> Context {
+    Context {
+        address: Address::default(),
+        caller: Address::default(),
+        // PUSH2 10000, then loop on JUMPDEST, PUSH1 1, SWAP1, SUB,
+        // DUP1, PUSH1 3, JUMPI
+        code: read_hex("0x6127105b6001900380600357").unwrap(),
+        data: Vec::new(),
+        gas_limit: Gas::from(10000000u64),
+        gas_price: Gas::zero(),
+        origin: Address::default(),
+        value: U256::zero(),
+        is_static: false,
+        is_create: false,
+        blob_versioned_hashes: Vec::new(),
+    }
+}
+
+fn block() -> BlockHeader {
+    BlockHeader {
+        coinbase: Address::default(),
+        timestamp: M256::zero(),
+        number: M256::zero(),
+        difficulty: M256::zero(),
+        gas_limit: Gas::from(10000000u64),
+        base_fee: Gas::zero(),
+        blob_base_fee: Gas::zero(),
+    }
+}
+
+fn run(machine: &mut SeqMachine) {
+    loop {
+        match machine.status() {
+            MachineStatus::Running => (),
+            MachineStatus::ExitedOk => return,
+            status => panic!("unexpected status: {:?}", status),
+        }
+        match machine.step() {
+            Ok(()) => (),
+            Err(RequireError::Account(address)) => {
+                machine.commit_account(AccountCommitment::Full {
+                    nonce: M256::zero(),
+                    address: address,
+                    balance: U256::zero(),
+                    storage: HashMapStorage::default(),
+                    code: Vec::new(),
+                }).unwrap();
+            },
+            Err(error) => panic!("unexpected require: {:?}", error),
+        }
+    }
+}
+
+/// Time a loop made mostly of small pushes, and the decoding of
+/// pushes of every width.
+fn main() {
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        let mut machine = SeqMachine::new(context(), block(), Patch::new(Fork::Cancun), 1);
+        run(&mut machine);
+    }
+    println!("loop:   {:?}", start.elapsed());
+
+    for &(name, min, max) in [("small", 1, 8), ("large", 9, 32)].iter() {
+        let mut code = Vec::new();
+        for _ in 0..64 {
+            for width in min..(max + 1) {
+                code.push(0x5f + width as u8);
+                code.extend((0..width).map(|i| i as u8 + 1));
+            }
+        }
+        let start = Instant::now();
+        for _ in 0..DECODES {
+            let mut pc = PC::new(&code);
+            while !pc.is_end() {
+                pc.read().unwrap();
+            }
+        }
+        println!("{}:  {:?}", name, start.elapsed());
+    }
+}
@@ -0,0 +1,83 @@
+extern crate sputnikvm;
+
+use std::time::Instant;
+use sputnikvm::{Gas, M256, U256, Address, read_hex};
+use sputnikvm::vm::{Machine, MachineStatus, Context, BlockHeader, Patch, Fork,
+                    AccountCommitment, SeqMemory, HashMapStorage};
+use sputnikvm::vm::errors::RequireError;
+
+type SeqMachine = Machine<SeqMemory, HashMapStorage>;
+
+const RUNS: usize = 20000;
+
+fn context() -> Context {
+    Context {
+        address: Address::default(),
+        caller: Address::default(),
+        // PUSH1 1, PUSH2 0x1000, MSTORE, then 32 times PUSH1 1, and
+        // 32 times POP
+        code: read_hex(&format!("0x600161100052{}{}", "6001".repeat(32), "50".repeat(32))).unwrap(),
+        data: Vec::new(),
+        gas_limit: Gas::from(1000000u64),
+        gas_price: Gas::zero(),
+        origin: Address::default(),
+        value: U256::zero(),
+        is_static: false,
+        is_create: false,
+        blob_versioned_hashes: Vec::new(),
+    }
+}
+
+fn block() -> BlockHeader {
+    BlockHeader {
+        coinbase: Address::default(),
+        timestamp: M256::zero(),
+        number: M256::zero(),
+        difficulty: M256::zero(),
+        gas_limit: Gas::from(1000000u64),
+        base_fee: Gas::zero(),
+        blob_base_fee: Gas::zero(),
+    }
+}
+
+fn run(machine: &mut SeqMachine) {
+    loop {
+        match machine.status() {
+            MachineStatus::Running => (),
+            MachineStatus::ExitedOk => return,
+            status => panic!("unexpected status: {:?}", status),
+        }
+        match machine.step() {
+            Ok(()) => (),
+            Err(RequireError::Account(address)) => {
+                machine.commit_account(AccountCommitment::Full {
+                    nonce: M256::zero(),
+                    address: address,
+                    balance: U256::zero(),
+                    storage: HashMapStorage::default(),
+                    code: Vec::new(),
+                }).unwrap();
+            },
+            Err(error) => panic!("unexpected require: {:?}", error),
+        }
+    }
+}
+
+/// Compare running many short transactions on new runtimes and on a
+/// single runtime that is reset between them.
+fn main() {
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        let mut machine = SeqMachine::new(context(), block(), Patch::new(Fork::Cancun), 1);
+        run(&mut machine);
+    }
+    println!("new:   {:?}", start.elapsed());
+
+    let start = Instant::now();
+    let mut machine = SeqMachine::new(context(), block(), Patch::new(Fork::Cancun), 1);
+    for _ in 0..RUNS {
+        machine.reset(context(), block(), Patch::new(Fork::Cancun));
+        run(&mut machine);
+    }
+    println!("reset: {:?}", start.elapsed());
+}
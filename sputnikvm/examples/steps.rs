@@ -0,0 +1,54 @@
+extern crate sputnikvm;
+
+use sputnikvm::{Gas, M256, U256, Address, read_hex};
+use sputnikvm::vm::{Machine, Context, BlockHeader, Patch, Fork, AccountCommitment,
+                    SeqMemory, HashMapStorage};
+use sputnikvm::vm::errors::RequireError;
+
+fn main() {
+    let address = Address::default();
+    let context = Context {
+        address: address,
+        caller: address,
+        // PUSH1 1, PUSH1 2, ADD, PUSH1 0, SSTORE
+        code: read_hex("0x6001600201600055").unwrap(),
+        data: Vec::new(),
+        gas_limit: Gas::from(100000u64),
+        gas_price: Gas::zero(),
+        origin: address,
+        value: U256::zero(),
+        is_static: false,
+        is_create: false,
+        blob_versioned_hashes: Vec::new(),
+    };
+    let block = BlockHeader {
+        coinbase: Address::default(),
+        timestamp: M256::zero(),
+        number: M256::zero(),
+        difficulty: M256::zero(),
+        gas_limit: Gas::from(1000000u64),
+        base_fee: Gas::zero(),
+        blob_base_fee: Gas::zero(),
+    };
+
+    let mut machine: Machine<SeqMemory, HashMapStorage> =
+        Machine::new(context, block, Patch::new(Fork::Frontier), 1);
+    let mut steps = machine.steps();
+    while let Some(event) = steps.next() {
+        match event {
+            Ok(status) => println!("{:?}", status),
+            Err(RequireError::Account(address)) => {
+                println!("committing account {:?}", address);
+                steps.commit_account(AccountCommitment::Full {
+                    nonce: M256::zero(),
+                    address: address,
+                    balance: U256::zero(),
+                    storage: HashMapStorage::default(),
+                    code: Vec::new(),
+                }).unwrap();
+            },
+            Err(error) => panic!("unexpected require: {:?}", error),
+        }
+    }
+    println!("used gas: {:?}", machine.state().used_gas);
+}
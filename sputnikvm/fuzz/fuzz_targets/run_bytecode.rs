@@ -0,0 +1,16 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate sputnikvm;
+
+use sputnikvm::vm::run_bytecode;
+
+// The first byte is the length of the call data, taken from the end
+// of the input. Everything in between is the code.
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() {
+        return;
+    }
+    let split = data.len() - ::std::cmp::min(data[0] as usize, data.len() - 1);
+    let (code, calldata) = data[1..].split_at(split - 1);
+    run_bytecode(code, calldata, 1_000_000);
+});
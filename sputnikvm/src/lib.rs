@@ -2,11 +2,30 @@
         unused_comparisons, unused_must_use,
         unused_variables, non_shorthand_field_patterns,
         unreachable_code)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
 extern crate log;
-extern crate crypto;
+#[cfg(feature = "std")]
 extern crate rlp;
 extern crate bigint;
+extern crate tiny_keccak;
+extern crate sha2;
+extern crate ripemd160;
+extern crate libsecp256k1;
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+#[cfg(test)]
+extern crate crypto;
+
+/// Stands in for `std` without the `std` feature, so that `std::`
+/// paths resolve to `core` and `alloc`. Maps and sets of the VM are
+/// in `utils::collections`.
+#[cfg(not(feature = "std"))]
+mod std {
+    pub use core::*;
+    pub use alloc::{borrow, collections, fmt, slice, str, sync};
+}
 
 mod utils;
 pub mod vm;
@@ -16,3 +35,6 @@ pub use utils::gas::Gas;
 pub use utils::address::Address;
 pub use utils::opcode::Opcode;
 pub use utils::read_hex;
+pub use utils::asm::{assemble, AssembleError};
+#[cfg(feature = "std")]
+pub use utils::trie::{trie_root, sec_trie_root, storage_root, account_rlp};
@@ -4,9 +4,10 @@ use std::fmt;
 
 use utils::bigint::M256;
 use utils::{read_hex, ParseHexError};
+#[cfg(feature = "std")]
 use rlp::{Encodable, RlpStream};
 
-#[derive(Eq, PartialEq, Debug, Copy, Clone, Hash)]
+#[derive(Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Hash)]
 /// Represents an Ethereum address. This address is 20 bytes long.
 pub struct Address([u8; 20]);
 
@@ -24,6 +25,7 @@ impl Default for Address {
     }
 }
 
+#[cfg(feature = "std")]
 impl Encodable for Address {
     fn rlp_append(&self, s: &mut RlpStream) {
         let leading_empty_bytes = 20 - (self.bits() + 7) / 8;
@@ -0,0 +1,175 @@
+//! Minimal EVM assembler, mostly useful for tests and fixtures
+
+use utils::collections::Map;
+use super::opcode::Opcode;
+use super::bigint::read_hex;
+#[cfg(not(feature = "std"))]
+use alloc::string::{String, ToString};
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+/// Errors returned by `assemble`.
+pub enum AssembleError {
+    /// The mnemonic is not a known opcode.
+    UnknownMnemonic(String),
+    /// A `PUSH` is not followed by an immediate.
+    MissingImmediate,
+    /// The immediate of a `PUSH` cannot be parsed.
+    InvalidImmediate(String),
+    /// The immediate does not fit into the width of the `PUSH`.
+    ImmediateTooLong(String),
+    /// A label is referenced but never defined.
+    UnknownLabel(String),
+    /// A label is defined more than once.
+    DuplicateLabel(String),
+}
+
+fn mnemonic(opcode: Opcode) -> String {
+    match opcode {
+        Opcode::PUSH(v) => format!("PUSH{}", v),
+        Opcode::DUP(v) => format!("DUP{}", v),
+        Opcode::SWAP(v) => format!("SWAP{}", v),
+        Opcode::LOG(v) => format!("LOG{}", v),
+        _ => format!("{:?}", opcode),
+    }
+}
+
+fn opcode(name: &str) -> Option<u8> {
+    (0..256).map(|v| v as u8).find(|&v| {
+        let opcode = Opcode::from(v);
+        let byte: u8 = opcode.into();
+        byte == v && mnemonic(opcode) == name
+    })
+}
+
+enum Immediate {
+    Bytes(Vec<u8>),
+    Label(String),
+}
+
+fn immediate(token: &str) -> Result<Immediate, AssembleError> {
+    if token.starts_with('@') {
+        return Ok(Immediate::Label(token[1..].to_string()));
+    }
+
+    let bytes = if token.starts_with("0x") {
+        let digits = &token[2..];
+        let padded = if digits.len() % 2 == 0 { digits.to_string() } else { format!("0{}", digits) };
+        if padded.is_empty() {
+            return Err(AssembleError::InvalidImmediate(token.to_string()));
+        }
+        read_hex(&padded).map_err(|_| AssembleError::InvalidImmediate(token.to_string()))?
+    } else {
+        let value: u64 = token.parse().map_err(|_| AssembleError::InvalidImmediate(token.to_string()))?;
+        let bytes: [u8; 8] = [(value >> 56) as u8, (value >> 48) as u8, (value >> 40) as u8,
+                              (value >> 32) as u8, (value >> 24) as u8, (value >> 16) as u8,
+                              (value >> 8) as u8, value as u8];
+        bytes.to_vec()
+    };
+
+    let start = bytes.iter().position(|&b| b != 0).unwrap_or(bytes.len() - 1);
+    Ok(Immediate::Bytes(bytes[start..].to_vec()))
+}
+
+/// Assemble whitespace separated mnemonics into bytecode. Text after
+/// `;` on a line is a comment.
+///
+/// `PUSH1` to `PUSH32` take an immediate of at most their width,
+/// while a bare `PUSH` uses the smallest width that fits. An
+/// immediate is either hex (`0x60`), decimal (`96`) or a label
+/// reference (`@loop`), which a bare `PUSH` encodes in two
+/// bytes. `loop:` defines a label at the current position; it does
+/// not emit a `JUMPDEST` by itself.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    let mut code = Vec::new();
+    let mut labels = Map::new();
+    let mut references = Vec::new();
+
+    let mut tokens = source.lines()
+        .flat_map(|line| line.split(';').next().unwrap().split_whitespace());
+    while let Some(token) = tokens.next() {
+        if token.ends_with(':') {
+            let label = token[..(token.len() - 1)].to_string();
+            if labels.insert(label.clone(), code.len()).is_some() {
+                return Err(AssembleError::DuplicateLabel(label));
+            }
+            continue;
+        }
+
+        let width = if token == "PUSH" {
+            None
+        } else {
+            match opcode(token) {
+                Some(v) => match Opcode::from(v) {
+                    Opcode::PUSH(width) => Some(width),
+                    _ => {
+                        code.push(v);
+                        continue;
+                    },
+                },
+                None => return Err(AssembleError::UnknownMnemonic(token.to_string())),
+            }
+        };
+
+        let token = tokens.next().ok_or(AssembleError::MissingImmediate)?;
+        let bytes = match immediate(token)? {
+            Immediate::Bytes(bytes) => bytes,
+            Immediate::Label(label) => {
+                let width = width.unwrap_or(2);
+                references.push((code.len() + 1, width, label));
+                vec![0u8; width]
+            },
+        };
+        let width = width.unwrap_or(bytes.len());
+        if bytes.len() > width || width > 32 {
+            return Err(AssembleError::ImmediateTooLong(token.to_string()));
+        }
+        code.push(Opcode::PUSH(width).into());
+        code.extend(::std::iter::repeat(0u8).take(width - bytes.len()));
+        code.extend(bytes);
+    }
+
+    for (position, width, label) in references {
+        let mut value = *labels.get(&label).ok_or(AssembleError::UnknownLabel(label.clone()))?;
+        for i in (0..width).rev() {
+            code[position + i] = value as u8;
+            value = value >> 8;
+        }
+        if value != 0 {
+            return Err(AssembleError::ImmediateTooLong(format!("@{}", label)));
+        }
+    }
+
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, AssembleError};
+
+    #[test]
+    fn assembled_program() {
+        let code = assemble("
+            PUSH1 0x05
+            PUSH 3          ; PUSH1
+            ADD
+            PUSH @end
+            JUMP
+            INVALID
+        end:
+            JUMPDEST
+            PUSH2 0x10
+            STOP
+        ").unwrap();
+        assert_eq!(code, vec![0x60, 0x05, 0x60, 0x03, 0x01, 0x61, 0x00, 0x0a, 0x56, 0xfe,
+                              0x5b, 0x61, 0x00, 0x10, 0x00]);
+        // The label resolves to the offset of the JUMPDEST.
+        assert_eq!(code[0x0a], 0x5b);
+
+        assert_eq!(assemble("PUSH1 0x100"), Err(AssembleError::ImmediateTooLong("0x100".to_string())));
+        assert_eq!(assemble("PUSH @nowhere"), Err(AssembleError::UnknownLabel("nowhere".to_string())));
+        assert_eq!(assemble("PUSH2"), Err(AssembleError::MissingImmediate));
+        assert_eq!(assemble("ADDD"), Err(AssembleError::UnknownMnemonic("ADDD".to_string())));
+    }
+}
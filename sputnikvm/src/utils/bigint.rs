@@ -1 +1 @@
-pub use bigint::{M256, U256, MI256, U512, Sign, ParseHexError, read_hex};
+pub use bigint::{M256, U256, MI256, U512, ParseHexError, read_hex};
@@ -0,0 +1,13 @@
+//! Map and set types used by the VM. They are hash maps and sets with
+//! the `std` feature, and B-tree maps and sets from `alloc` without
+//! it, so keys must implement both `Hash` and `Ord`.
+
+#[cfg(feature = "std")]
+pub use std::collections::{HashMap as Map, HashSet as Set};
+#[cfg(feature = "std")]
+pub use std::collections::hash_map::Values as MapValues;
+
+#[cfg(not(feature = "std"))]
+pub use alloc::collections::{BTreeMap as Map, BTreeSet as Set};
+#[cfg(not(feature = "std"))]
+pub use alloc::collections::btree_map::Values as MapValues;
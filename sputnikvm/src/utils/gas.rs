@@ -16,6 +16,27 @@ impl Gas {
     pub fn zero() -> Gas { Gas(U512::zero()) }
     /// Bits needed to represent this value.
     pub fn bits(self) -> usize { self.0.bits() }
+
+    /// Convert to `u64`, saturating at `u64::max_value()` instead of
+    /// panicking when the value does not fit.
+    pub fn as_u64_saturating(self) -> u64 {
+        if self > Gas::from(u64::max_value()) {
+            u64::max_value()
+        } else {
+            self.into()
+        }
+    }
+
+    /// Convert to `usize`, returning `None` instead of panicking or
+    /// truncating when the value does not fit.
+    pub fn try_into_usize(self) -> Option<usize> {
+        if self > Gas::from(usize::max_value()) {
+            None
+        } else {
+            let val: U256 = self.into();
+            Some(val.into())
+        }
+    }
 }
 
 impl Default for Gas { fn default() -> Gas { Gas::zero() } }
@@ -109,3 +130,26 @@ impl fmt::UpperHex for Gas {
         write!(f, "{:X}", self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use utils::bigint::U256;
+    use super::Gas;
+
+    #[test]
+    fn u64_boundary() {
+        let max = Gas::from(u64::max_value());
+        assert_eq!(max.as_u64_saturating(), u64::max_value());
+        assert_eq!((max + Gas::from(1u64)).as_u64_saturating(), u64::max_value());
+        assert_eq!(Gas::from(U256::max_value()).as_u64_saturating(), u64::max_value());
+        assert_eq!(Gas::from(42u64).as_u64_saturating(), 42);
+    }
+
+    #[test]
+    fn usize_boundary() {
+        assert_eq!(Gas::from(usize::max_value()).try_into_usize(), Some(usize::max_value()));
+        assert_eq!((Gas::from(usize::max_value()) + Gas::from(1u64)).try_into_usize(), None);
+        assert_eq!(Gas::from(U256::max_value()).try_into_usize(), None);
+        assert_eq!(Gas::from(0usize).try_into_usize(), Some(0));
+    }
+}
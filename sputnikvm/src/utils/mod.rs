@@ -3,5 +3,9 @@ pub mod bigint;
 pub mod address;
 pub mod gas;
 pub mod opcode;
+pub mod asm;
+pub mod collections;
+#[cfg(feature = "std")]
+pub mod trie;
 
 pub use self::bigint::{read_hex, ParseHexError};
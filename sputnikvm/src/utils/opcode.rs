@@ -1,6 +1,6 @@
 //! Ethereum opcodes
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Hash)]
 /// Opcode enum. One-to-one corresponding to an `u8` value.
 pub enum Opcode {
     STOP, ADD, MUL, SUB, DIV, SDIV, MOD, SMOD, ADDMOD, MULMOD, EXP,
@@ -12,19 +12,21 @@ pub enum Opcode {
 
     ADDRESS, BALANCE, ORIGIN, CALLER, CALLVALUE, CALLDATALOAD,
     CALLDATASIZE, CALLDATACOPY, CODESIZE, CODECOPY, GASPRICE,
-    EXTCODESIZE, EXTCODECOPY,
+    EXTCODESIZE, EXTCODECOPY, EXTCODEHASH,
 
-    BLOCKHASH, COINBASE, TIMESTAMP, NUMBER, DIFFICULTY, GASLIMIT,
+    BLOCKHASH, COINBASE, TIMESTAMP, NUMBER, DIFFICULTY, GASLIMIT, CHAINID,
+    SELFBALANCE, BASEFEE, BLOBHASH, BLOBBASEFEE,
 
     POP, MLOAD, MSTORE, MSTORE8, SLOAD, SSTORE, JUMP, JUMPI, PC,
-    MSIZE, GAS, JUMPDEST,
+    MSIZE, GAS, JUMPDEST, TLOAD, TSTORE,
 
+    PUSH0,
     PUSH(usize),
     DUP(usize),
     SWAP(usize),
     LOG(usize),
 
-    CREATE, CALL, CALLCODE, RETURN, DELEGATECALL,
+    CREATE, CALL, CALLCODE, RETURN, DELEGATECALL, STATICCALL,
 
     INVALID, SUICIDE
 }
@@ -72,6 +74,7 @@ impl From<u8> for Opcode {
             0x3a => Opcode::GASPRICE,
             0x3b => Opcode::EXTCODESIZE,
             0x3c => Opcode::EXTCODECOPY,
+            0x3f => Opcode::EXTCODEHASH,
 
             0x40 => Opcode::BLOCKHASH,
             0x41 => Opcode::COINBASE,
@@ -79,6 +82,11 @@ impl From<u8> for Opcode {
             0x43 => Opcode::NUMBER,
             0x44 => Opcode::DIFFICULTY,
             0x45 => Opcode::GASLIMIT,
+            0x46 => Opcode::CHAINID,
+            0x47 => Opcode::SELFBALANCE,
+            0x48 => Opcode::BASEFEE,
+            0x49 => Opcode::BLOBHASH,
+            0x4a => Opcode::BLOBBASEFEE,
 
             0x50 => Opcode::POP,
             0x51 => Opcode::MLOAD,
@@ -92,6 +100,10 @@ impl From<u8> for Opcode {
             0x59 => Opcode::MSIZE,
             0x5a => Opcode::GAS,
             0x5b => Opcode::JUMPDEST,
+            0x5c => Opcode::TLOAD,
+            0x5d => Opcode::TSTORE,
+
+            0x5f => Opcode::PUSH0,
 
             0x60 => Opcode::PUSH(1),
             0x61 => Opcode::PUSH(2),
@@ -171,6 +183,7 @@ impl From<u8> for Opcode {
             0xf2 => Opcode::CALLCODE,
             0xf3 => Opcode::RETURN,
             0xf4 => Opcode::DELEGATECALL,
+            0xfa => Opcode::STATICCALL,
 
             0xff => Opcode::SUICIDE,
             _ => Opcode::INVALID,
@@ -221,6 +234,7 @@ impl Into<u8> for Opcode {
             Opcode::GASPRICE => 0x3a,
             Opcode::EXTCODESIZE => 0x3b,
             Opcode::EXTCODECOPY => 0x3c,
+            Opcode::EXTCODEHASH => 0x3f,
 
             Opcode::BLOCKHASH => 0x40,
             Opcode::COINBASE => 0x41,
@@ -228,6 +242,11 @@ impl Into<u8> for Opcode {
             Opcode::NUMBER => 0x43,
             Opcode::DIFFICULTY => 0x44,
             Opcode::GASLIMIT => 0x45,
+            Opcode::CHAINID => 0x46,
+            Opcode::SELFBALANCE => 0x47,
+            Opcode::BASEFEE => 0x48,
+            Opcode::BLOBHASH => 0x49,
+            Opcode::BLOBBASEFEE => 0x4a,
 
             Opcode::POP => 0x50,
             Opcode::MLOAD => 0x51,
@@ -241,6 +260,10 @@ impl Into<u8> for Opcode {
             Opcode::MSIZE => 0x59,
             Opcode::GAS => 0x5a,
             Opcode::JUMPDEST => 0x5b,
+            Opcode::TLOAD => 0x5c,
+            Opcode::TSTORE => 0x5d,
+
+            Opcode::PUSH0 => 0x5f,
 
             Opcode::PUSH(v) => {
                 assert!(v >= 1 && v <= 32);
@@ -267,6 +290,7 @@ impl Into<u8> for Opcode {
             Opcode::CALLCODE => 0xf2,
             Opcode::RETURN => 0xf3,
             Opcode::DELEGATECALL => 0xf4,
+            Opcode::STATICCALL => 0xfa,
 
             Opcode::INVALID => 0xfe,
             Opcode::SUICIDE => 0xff,
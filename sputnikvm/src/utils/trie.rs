@@ -0,0 +1,158 @@
+//! Root hash of a Merkle Patricia trie
+
+use rlp::{self, RlpStream};
+use super::bigint::{M256, U256};
+use tiny_keccak::keccak256;
+
+fn nibbles(key: &[u8]) -> Vec<u8> {
+    key.iter().flat_map(|&b| vec![b >> 4, b & 0x0f]).collect()
+}
+
+/// Hex-prefix encoding of a partial path.
+fn hex_prefix(nibbles: &[u8], leaf: bool) -> Vec<u8> {
+    let flag = if leaf { 2 } else { 0 };
+    let mut ret = Vec::new();
+    let rest = if nibbles.len() % 2 == 1 {
+        ret.push(((flag + 1) << 4) | nibbles[0]);
+        &nibbles[1..]
+    } else {
+        ret.push(flag << 4);
+        nibbles
+    };
+    for pair in rest.chunks(2) {
+        ret.push((pair[0] << 4) | pair[1]);
+    }
+    ret
+}
+
+/// Append a reference to a child node, which is the node itself if
+/// its encoding is shorter than 32 bytes, and its hash otherwise.
+fn append_child(stream: &mut RlpStream, node: &[u8]) {
+    if node.len() < 32 {
+        stream.append_raw(node, 1);
+    } else {
+        let hash: &[u8] = &keccak256(node);
+        stream.append(&hash);
+    }
+}
+
+/// RLP encoding of the node holding `items`, whose keys all share
+/// the first `depth` nibbles. Items are sorted by key.
+fn encode_node(items: &[(Vec<u8>, &[u8])], depth: usize) -> Vec<u8> {
+    let mut stream = RlpStream::new();
+    if items.len() == 0 {
+        stream.append_empty_data();
+        return stream.out();
+    }
+
+    if items.len() == 1 {
+        let (ref key, value) = items[0];
+        stream.begin_list(2);
+        stream.append(&hex_prefix(&key[depth..], true));
+        stream.append(&value);
+        return stream.out();
+    }
+
+    let first = &items[0].0;
+    let mut shared = 0;
+    while items.iter().all(|&(ref key, _)| key.len() > depth + shared &&
+                           key[depth + shared] == first[depth + shared]) {
+        shared += 1;
+    }
+
+    if shared > 0 {
+        stream.begin_list(2);
+        stream.append(&hex_prefix(&first[depth..(depth + shared)], false));
+        append_child(&mut stream, &encode_node(items, depth + shared));
+        return stream.out();
+    }
+
+    stream.begin_list(17);
+    let mut value: &[u8] = &[];
+    let mut start = 0;
+    if items[0].0.len() == depth {
+        value = items[0].1;
+        start = 1;
+    }
+    for nibble in 0..16 {
+        let end = start + items[start..].iter().take_while(|&&(ref key, _)| key[depth] == nibble).count();
+        if end == start {
+            stream.append_empty_data();
+        } else {
+            append_child(&mut stream, &encode_node(&items[start..end], depth + 1));
+        }
+        start = end;
+    }
+    stream.append(&value);
+    stream.out()
+}
+
+/// Root hash of the trie holding the given key-value pairs. Keys must
+/// be unique.
+pub fn trie_root(items: Vec<(Vec<u8>, Vec<u8>)>) -> M256 {
+    let mut items: Vec<(Vec<u8>, &[u8])> = items.iter()
+        .map(|&(ref key, ref value)| (nibbles(key), value.as_slice())).collect();
+    items.sort();
+    M256::from(keccak256(&encode_node(&items, 0)))
+}
+
+/// Root hash of the secure trie holding the given key-value pairs,
+/// where each key is replaced by its Keccak-256 hash. This is the
+/// trie of the world state and of account storage.
+pub fn sec_trie_root(items: Vec<(Vec<u8>, Vec<u8>)>) -> M256 {
+    trie_root(items.into_iter().map(|(key, value)| (keccak256(&key).to_vec(), value)).collect())
+}
+
+/// Root hash of the storage trie of an account. Slots holding zero
+/// are not part of the trie.
+pub fn storage_root<'a, I: IntoIterator<Item=(&'a M256, &'a M256)>>(storage: I) -> M256 {
+    sec_trie_root(storage.into_iter().filter(|&(_, value)| *value != M256::zero()).map(|(index, value)| {
+        let index: [u8; 32] = (*index).into();
+        (index.to_vec(), rlp::encode(value).to_vec())
+    }).collect())
+}
+
+/// RLP encoding of an account in the world state trie, given its
+/// storage root and code.
+pub fn account_rlp(nonce: M256, balance: U256, storage_root: M256, code: &[u8]) -> Vec<u8> {
+    let storage_root: [u8; 32] = storage_root.into();
+    let code_hash: &[u8] = &keccak256(code);
+    let mut stream = RlpStream::new_list(4);
+    stream.append(&nonce);
+    stream.append(&M256::from(balance));
+    stream.append(&&storage_root[..]);
+    stream.append(&code_hash);
+    stream.out()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use utils::bigint::{M256, U256};
+    use utils::address::Address;
+    use super::{sec_trie_root, storage_root, account_rlp};
+
+    #[test]
+    fn genesis_state_root() {
+        let sender = Address::from_str("0xa94f5374fce5edbc8e2a8697c15331677e6ebf0b").unwrap();
+        let contract = Address::from_str("0x095e7baea6a6c7c4c2dfeb977efac326af552d87").unwrap();
+        let key = |address: Address| { let key: [u8; 20] = address.into(); key.to_vec() };
+        assert_eq!(sec_trie_root(Vec::new()),
+                   M256::from_str("0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421").unwrap());
+
+        let empty = storage_root(Vec::new());
+        let mut items = vec![(key(sender), account_rlp(M256::zero(), U256::from_str("0x0de0b6b3a7640000").unwrap(),
+                                                       empty, &[]))];
+        assert_eq!(sec_trie_root(items.clone()),
+                   M256::from_str("0x517f2cdf6adb1a644878c390ffab4e130f1bed4b498ef7ce58c5addd98d61018").unwrap());
+
+        // The zero slot is left out of the storage trie.
+        let storage = vec![(M256::zero(), M256::from(3u64)), (M256::from(1u64), M256::zero())];
+        let code = [0x60, 0x02, 0x60, 0x01, 0x01, 0x60, 0x00, 0x55];
+        items.push((key(contract), account_rlp(M256::from(1u64), U256::zero(),
+                                               storage_root(storage.iter().map(|&(ref k, ref v)| (k, v))),
+                                               &code)));
+        assert_eq!(sec_trie_root(items),
+                   M256::from_str("0x61bd4731aff5fc701496deb4a31c3648aa50823a03dc6e9572fda162c555b11e").unwrap());
+    }
+}
@@ -1,11 +1,15 @@
 //! Account commitment managment
 
-use std::collections::hash_map::{self, HashMap};
+use utils::collections::{Map, Set, MapValues};
 use utils::address::Address;
 use utils::bigint::{M256, U256};
 
+#[cfg(feature = "std")]
+use utils::trie::{sec_trie_root, storage_root, account_rlp};
 use vm::Storage;
-use vm::errors::{RequireError, CommitError};
+use vm::errors::{RequireError, CommitError, MachineError};
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
 
 #[derive(Debug, Clone)]
 /// A single account commitment.
@@ -75,28 +79,123 @@ impl<S: Storage> Account<S> {
     }
 }
 
+#[derive(Debug, Clone)]
+/// Change of a single account after execution, compared to its
+/// committed state.
+pub enum AccountChange {
+    /// A full account that was changed or created.
+    Full {
+        address: Address,
+        /// The new balance.
+        balance: U256,
+        /// The new nonce.
+        nonce: M256,
+        /// Code of the account, if it was created.
+        code: Option<Vec<u8>>,
+        /// Changed storage slots, as index, old value and new value.
+        storage: Vec<(M256, M256, M256)>,
+    },
+    /// Balance of a not committed account is increased.
+    IncreaseBalance(Address, U256),
+    /// Balance of a not committed account is decreased.
+    DecreaseBalance(Address, U256),
+}
+
+impl AccountChange {
+    /// Address of this account change.
+    pub fn address(&self) -> Address {
+        match self {
+            &AccountChange::Full {
+                address,
+                ..
+            } => address,
+            &AccountChange::IncreaseBalance(address, _) => address,
+            &AccountChange::DecreaseBalance(address, _) => address,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// World state changes of an execution, to be applied to the
+/// client's database.
+pub struct StateDiff {
+    /// Changed accounts.
+    pub accounts: Vec<AccountChange>,
+    /// Accounts to be deleted, either suicided or empty after EIP161.
+    pub deleted: Vec<Address>,
+}
+
 #[derive(Debug, Clone)]
 /// A struct that manages the current account state for one EVM.
 pub struct AccountState<S> {
-    accounts: HashMap<Address, Account<S>>,
-    codes: HashMap<Address, Vec<u8>>,
+    accounts: Map<Address, Account<S>>,
+    codes: Map<Address, Vec<u8>>,
+    touched: Map<Address, Set<M256>>,
+    originals: Map<Address, Account<S>>,
+    created: Set<Address>,
+    removed: Set<Address>,
 }
 
 impl<S: Storage> Default for AccountState<S> {
     fn default() -> Self {
         Self {
-            accounts: HashMap::new(),
-            codes: HashMap::new(),
+            accounts: Map::new(),
+            codes: Map::new(),
+            touched: Map::new(),
+            originals: Map::new(),
+            created: Set::new(),
+            removed: Set::new(),
         }
     }
 }
 
 impl<S: Storage + Default + Clone> AccountState<S> {
     /// Returns all accounts right now in this account state.
-    pub fn accounts(&self) -> hash_map::Values<Address, Account<S>> {
+    pub fn accounts(&self) -> MapValues<Address, Account<S>> {
         self.accounts.values()
     }
 
+    /// Returns all storage slots read or written so far, as pairs of
+    /// account address and storage index. Slots that were only read
+    /// are included.
+    pub fn touched_storage<'a>(&'a self) -> impl Iterator<Item=(Address, M256)> + 'a {
+        self.touched.iter().flat_map(|(&address, indexes)| {
+            indexes.iter().map(move |&index| (address, index))
+        })
+    }
+
+    /// Record that a storage slot of an account has been accessed.
+    pub fn touch_storage(&mut self, address: Address, index: M256) {
+        self.touched.entry(address).or_insert_with(Set::new).insert(index);
+    }
+
+    /// Merge touched storage slots of another account state into
+    /// this one. This is used to keep accesses made by sub calls
+    /// that failed.
+    pub fn merge_touched_storage(&mut self, other: &AccountState<S>) {
+        for (address, index) in other.touched_storage() {
+            self.touch_storage(address, index);
+        }
+    }
+
+    /// Merge accounts committed to another account state that are
+    /// not yet known to this one. Commitments are plain facts about
+    /// the world state, so they are kept even if the changes of the
+    /// runtime that received them are dropped.
+    pub fn merge_commitments(&mut self, other: &AccountState<S>) {
+        for (&address, account) in &other.originals {
+            if !self.accounts.contains_key(&address) {
+                self.originals.insert(address, account.clone());
+                self.accounts.insert(address, account.clone());
+            }
+        }
+        for (&address, code) in &other.codes {
+            if !self.accounts.contains_key(&address) && !self.codes.contains_key(&address) {
+                self.codes.insert(address, code.clone());
+            }
+        }
+    }
+
     /// Returns Ok(()) if a full account is in this account
     /// state. Otherwise raise a `RequireError`.
     pub fn require(&self, address: Address) -> Result<(), RequireError> {
@@ -133,13 +232,15 @@ impl<S: Storage + Default + Clone> AccountState<S> {
                     return Err(CommitError::AlreadyCommitted);
                 }
 
-                self.accounts.insert(address, Account::Full {
+                let account = Account::Full {
                     nonce,
                     address,
                     balance,
                     storage,
                     code,
-                });
+                };
+                self.originals.insert(address, account.clone());
+                self.accounts.insert(address, account);
             },
             AccountCommitment::Code {
                 address,
@@ -175,6 +276,13 @@ impl<S: Storage + Default + Clone> AccountState<S> {
         return Err(RequireError::AccountCode(address));
     }
 
+    /// Whether the account has code, as opposed to an externally
+    /// owned or empty account. If the code of the account is not
+    /// committed, returns a `RequireError`.
+    pub fn is_contract(&self, address: Address) -> Result<bool, RequireError> {
+        Ok(self.code(address)?.len() != 0)
+    }
+
     /// Find nonce by its address in this account state. If the search
     /// failed, returns a `RequireError`.
     pub fn nonce(&self, address: Address) -> Result<M256, RequireError> {
@@ -240,16 +348,59 @@ impl<S: Storage + Default + Clone> AccountState<S> {
     }
 
     /// Create a new account (that should not yet have existed
-    /// before).
-    pub fn create(&mut self, address: Address, balance: U256, code: &[u8]) {
+    /// before) with the given nonce. Storage already written by the
+    /// init code is kept.
+    pub fn create(&mut self, address: Address, balance: U256, nonce: M256, code: &[u8]) {
+        self.created.insert(address);
+        self.removed.remove(&address);
+        let storage = match self.accounts.remove(&address) {
+            Some(Account::Full { storage, .. }) => storage,
+            _ => S::default(),
+        };
         self.accounts.insert(address, Account::Full {
-            address, balance, storage: S::default(), code: code.into(), nonce: M256::zero(),
+            address, balance, storage, code: code.into(), nonce,
         });
     }
 
-    /// Increase the balance of an account.
-    pub fn increase_balance(&mut self, address: Address, topup: U256) {
-        if topup == U256::zero() { return; }
+    /// Check that the balance of an account can be increased by
+    /// `topup` without overflowing.
+    pub fn check_increase_balance(&self, address: Address, topup: U256) -> Result<(), MachineError> {
+        match self.accounts.get(&address) {
+            Some(&Account::Full { balance, .. }) |
+            Some(&Account::IncreaseBalance(_, balance)) => {
+                if balance.overflowing_add(topup).1 {
+                    return Err(MachineError::BalanceOverflow);
+                }
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Check that the balance of an account can be decreased by
+    /// `withdraw` without going below zero.
+    pub fn check_decrease_balance(&self, address: Address, withdraw: U256) -> Result<(), MachineError> {
+        match self.accounts.get(&address) {
+            Some(&Account::Full { balance, .. }) => {
+                if balance < withdraw {
+                    return Err(MachineError::BalanceOverflow);
+                }
+            },
+            Some(&Account::DecreaseBalance(_, balance)) => {
+                if balance.overflowing_add(withdraw).1 {
+                    return Err(MachineError::BalanceOverflow);
+                }
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Increase the balance of an account. The account is left
+    /// unchanged if the balance would overflow.
+    pub fn increase_balance(&mut self, address: Address, topup: U256) -> Result<(), MachineError> {
+        if topup == U256::zero() { return Ok(()); }
+        self.check_increase_balance(address, topup)?;
         let account = match self.accounts.remove(&address) {
             Some(Account::Full {
                 address,
@@ -285,11 +436,14 @@ impl<S: Storage + Default + Clone> AccountState<S> {
         if account.is_some() {
             self.accounts.insert(address, account.unwrap());
         }
+        Ok(())
     }
 
-    /// Decrease the balance of an account.
-    pub fn decrease_balance(&mut self, address: Address, withdraw: U256) {
-        if withdraw == U256::zero() { return; }
+    /// Decrease the balance of an account. The account is left
+    /// unchanged if the balance would go below zero.
+    pub fn decrease_balance(&mut self, address: Address, withdraw: U256) -> Result<(), MachineError> {
+        if withdraw == U256::zero() { return Ok(()); }
+        self.check_decrease_balance(address, withdraw)?;
         let account = match self.accounts.remove(&address) {
             Some(Account::Full {
                 address,
@@ -325,6 +479,7 @@ impl<S: Storage + Default + Clone> AccountState<S> {
         if account.is_some() {
             self.accounts.insert(address, account.unwrap());
         }
+        Ok(())
     }
 
     /// Set nonce of an account. If the account is not already
@@ -344,6 +499,11 @@ impl<S: Storage + Default + Clone> AccountState<S> {
         }
     }
 
+    /// Whether the account was deleted in this account state.
+    pub fn is_removed(&self, address: Address) -> bool {
+        self.removed.contains(&address)
+    }
+
     /// Delete an account from this account state. The account is set
     /// to null. If the account is not already commited, returns a
     /// `RequireError`.
@@ -372,6 +532,113 @@ impl<S: Storage + Default + Clone> AccountState<S> {
             },
         };
         self.accounts.insert(address, account);
+        self.removed.insert(address);
         Ok(())
     }
+
+    /// Compute the changes of this account state compared to the
+    /// committed accounts. Only touched storage slots are
+    /// compared. If `delete_empty` is set, full accounts that end up
+    /// with zero balance, zero nonce and no code are deleted.
+    pub fn diff(&self, delete_empty: bool) -> StateDiff {
+        let mut accounts = Vec::new();
+        let mut deleted = Vec::new();
+
+        for account in self.accounts.values() {
+            match account {
+                &Account::Full { address, balance, nonce, ref storage, ref code } => {
+                    if self.removed.contains(&address) ||
+                        (delete_empty && balance == U256::zero() &&
+                         nonce == M256::zero() && code.len() == 0)
+                    {
+                        deleted.push(address);
+                        continue;
+                    }
+
+                    let original = match self.originals.get(&address) {
+                        Some(&Account::Full { balance, nonce, ref storage, .. }) =>
+                            Some((balance, nonce, storage)),
+                        _ => None,
+                    };
+                    let created = self.created.contains(&address);
+
+                    let mut changes = Vec::new();
+                    if let Some(indexes) = self.touched.get(&address) {
+                        for &index in indexes {
+                            let old = match original {
+                                Some((_, _, original)) if !created => original.read(index),
+                                _ => M256::zero(),
+                            };
+                            let new = storage.read(index);
+                            if old != new {
+                                changes.push((index, old, new));
+                            }
+                        }
+                    }
+                    changes.sort_by_key(|&(index, _, _)| index);
+
+                    let changed = match original {
+                        Some((original_balance, original_nonce, _)) =>
+                            original_balance != balance || original_nonce != nonce,
+                        None => true,
+                    };
+                    if created || changed || changes.len() > 0 {
+                        accounts.push(AccountChange::Full {
+                            address,
+                            balance,
+                            nonce,
+                            code: if created { Some(code.clone()) } else { None },
+                            storage: changes,
+                        });
+                    }
+                },
+                &Account::IncreaseBalance(address, topup) => {
+                    accounts.push(AccountChange::IncreaseBalance(address, topup));
+                },
+                &Account::DecreaseBalance(address, withdraw) => {
+                    accounts.push(AccountChange::DecreaseBalance(address, withdraw));
+                },
+            }
+        }
+
+        StateDiff {
+            accounts,
+            deleted,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: Storage + Default + Clone + Into<Map<M256, M256>>> AccountState<S> {
+    /// Compute the state root of the world made of the full accounts
+    /// in this account state, which is the post state root only if
+    /// every account of the world has been committed. Deleted
+    /// accounts are left out, and so are empty accounts if
+    /// `delete_empty` is set. Returns a `RequireError` if an account
+    /// only has its balance changed.
+    pub fn state_root(&self, delete_empty: bool) -> Result<M256, RequireError> {
+        let mut items = Vec::new();
+        for account in self.accounts.values() {
+            match account {
+                &Account::Full { address, balance, nonce, ref storage, ref code } => {
+                    if self.removed.contains(&address) ||
+                        (delete_empty && balance == U256::zero() &&
+                         nonce == M256::zero() && code.len() == 0)
+                    {
+                        continue;
+                    }
+
+                    let storage: Map<M256, M256> = storage.clone().into();
+                    let address: [u8; 20] = address.into();
+                    items.push((address.to_vec(),
+                                account_rlp(nonce, balance, storage_root(&storage), code)));
+                },
+                &Account::IncreaseBalance(address, _) |
+                &Account::DecreaseBalance(address, _) => {
+                    return Err(RequireError::Account(address));
+                },
+            }
+        }
+        Ok(sec_trie_root(items))
+    }
 }
@@ -1,16 +1,16 @@
 //! Blockhash commitment management
-use std::collections::hash_map::HashMap;
+use utils::collections::Map;
 use utils::bigint::M256;
 
 use vm::errors::{RequireError, CommitError};
 
 #[derive(Debug, Clone)]
 /// A struct that manages the current blockhash state for one EVM.
-pub struct BlockhashState(HashMap<M256, M256>);
+pub struct BlockhashState(Map<M256, M256>);
 
 impl Default for BlockhashState {
     fn default() -> BlockhashState {
-        BlockhashState(HashMap::new())
+        BlockhashState(Map::new())
     }
 }
 
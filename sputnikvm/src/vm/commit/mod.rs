@@ -3,5 +3,5 @@
 mod account;
 mod blockhash;
 
-pub use self::account::{AccountCommitment, Account, AccountState};
+pub use self::account::{AccountCommitment, Account, AccountState, AccountChange, StateDiff};
 pub use self::blockhash::BlockhashState;
@@ -102,8 +102,26 @@ pub enum MachineError {
     CallstackOverflow,
     /// For instruction that requires reading a range, it is invalid.
     InvalidRange,
-    /// Not enough gas to continue.
+    /// Not enough gas to execute the next instruction.
     EmptyGas,
+    /// Not enough gas to pay for the memory expansion of the next
+    /// instruction.
+    EmptyMemoryGas,
+    /// Total log data of the call tree exceeds the patch limit.
+    LogDataLimitExceeded,
+    /// Number of logs of the call tree exceeds the patch limit.
+    LogLimitExceeded,
+    /// Total memory of the call tree exceeds the patch limit.
+    MemoryLimitExceeded,
+    /// A state modifying instruction is run inside a static call.
+    StaticViolation,
+    /// A balance transfer would overflow the balance of the receiving
+    /// account or take the sender below zero. Only possible with
+    /// inconsistent committed account data.
+    BalanceOverflow,
+    /// The precompiled contract exists in the patch but is not
+    /// implemented by this VM.
+    UnimplementedPrecompiled,
 }
 
 impl From<MachineError> for EvalError {
@@ -117,6 +135,9 @@ impl From<MachineError> for EvalError {
 pub enum VMError {
     /// VM runtime error.
     Machine(MachineError),
+    /// The call stack of the VM is inconsistent, so the finished sub
+    /// runtime cannot be applied to its parent. Non-recoverable.
+    Apply(ApplyError),
 }
 
 impl From<MachineError> for VMError {
@@ -125,6 +146,12 @@ impl From<MachineError> for VMError {
     }
 }
 
+impl From<ApplyError> for VMError {
+    fn from(val: ApplyError) -> VMError {
+        VMError::Apply(val)
+    }
+}
+
 #[derive(Debug, Clone)]
 /// Errors stating that the VM requires additional information to
 /// continue running.
@@ -140,8 +167,25 @@ impl From<RequireError> for EvalError {
     }
 }
 
+#[derive(Debug, Clone)]
+/// Errors returned when applying a sub runtime that does not belong
+/// to the current runtime.
+pub enum ApplyError {
+    /// The current runtime is not invoking a sub runtime.
+    NotInvoking,
+    /// The sub runtime is still running or invoking its own sub
+    /// runtime.
+    SubNotExited,
+    /// The sub runtime used more gas than the current runtime has, so
+    /// it was not derived from it.
+    SubGasExceeded,
+}
+
 #[derive(Debug, Clone)]
 /// Errors returned when committing a new information.
 pub enum CommitError {
     AlreadyCommitted,
+    /// The block is not one of the 256 blocks before the current one,
+    /// so `BLOCKHASH` never reads its hash.
+    InvalidBlockhash,
 }
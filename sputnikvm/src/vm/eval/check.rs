@@ -3,24 +3,99 @@
 use utils::bigint::M256;
 use utils::gas::Gas;
 
-use vm::{Memory, Storage, Instruction};
-use vm::errors::{MachineError, EvalError};
+use vm::{Memory, Storage, Instruction, Patch, Fork};
+use vm::errors::{MachineError, EvalError, PCError};
 
 use vm::eval::{State, ControlCheck};
-use super::utils::{check_range, check_memory_write_range};
+use vm::eval::run::create_address;
+use super::utils::{check_memory_read_range, check_memory_write_range};
+
+/// Hard fork that introduced each opcode added after Frontier, keyed
+/// by opcode byte. This is the only place where the availability of
+/// an opcode depends on the patch.
+const OPCODE_FORKS: &[(u8, Fork)] = &[
+    (0x3f, Fork::Constantinople), // EXTCODEHASH
+    (0x46, Fork::Istanbul),       // CHAINID
+    (0x47, Fork::Istanbul),       // SELFBALANCE
+    (0x48, Fork::London),         // BASEFEE
+    (0x49, Fork::Cancun),         // BLOBHASH
+    (0x4a, Fork::Cancun),         // BLOBBASEFEE
+    (0x5c, Fork::Cancun),         // TLOAD
+    (0x5d, Fork::Cancun),         // TSTORE
+    (0x5f, Fork::Shanghai),       // PUSH0
+    (0xf4, Fork::Homestead),      // DELEGATECALL
+    (0xfa, Fork::Byzantium),      // STATICCALL
+];
+
+/// Check that the opcode has been introduced under the patch.
+pub fn check_opcode_fork(opcode: u8, patch: &Patch) -> Result<(), EvalError> {
+    match OPCODE_FORKS.iter().find(|&&(v, _)| v == opcode) {
+        Some(&(_, fork)) if patch.fork < fork => Err(PCError::InvalidOpcode.into()),
+        _ => Ok(()),
+    }
+}
 
 fn check_callstack_overflow<M: Memory, S: Storage>(state: &State<M, S>) -> Result<(), MachineError> {
-    if state.depth >= 2 {
+    if state.depth >= state.patch.callstack_limit {
         return Err(MachineError::CallstackOverflow);
     } else {
         return Ok(());
     }
 }
 
-pub fn extra_check_opcode<M: Memory + Default, S: Storage + Default + Clone>(instruction: Instruction, state: &State<M, S>, stipend_gas: Gas, after_gas: Gas) -> Result<(), EvalError> {
+fn check_log_data_limit<M: Memory, S: Storage>(state: &State<M, S>, len: M256) -> Result<(), MachineError> {
+    match state.patch.log_data_limit {
+        Some(limit) => {
+            let total = state.logs.iter().fold(0, |acc, log| acc + log.data.len());
+            if Gas::from(total) + Gas::from(len) > Gas::from(limit) {
+                Err(MachineError::LogDataLimitExceeded)
+            } else {
+                Ok(())
+            }
+        },
+        None => Ok(()),
+    }
+}
+
+fn check_log_limit<M: Memory, S: Storage>(state: &State<M, S>) -> Result<(), MachineError> {
+    match state.patch.log_limit {
+        Some(limit) if state.logs.len() >= limit => Err(MachineError::LogLimitExceeded),
+        _ => Ok(()),
+    }
+}
+
+fn check_static<M: Memory, S: Storage>(instruction: Instruction, state: &State<M, S>) -> Result<(), MachineError> {
+    if !state.context.is_static {
+        return Ok(());
+    }
+
     match instruction {
+        Instruction::SSTORE | Instruction::TSTORE | Instruction::LOG(_) |
+        Instruction::CREATE | Instruction::SUICIDE => Err(MachineError::StaticViolation),
         Instruction::CALL => {
-            if after_gas - stipend_gas < state.stack.peek(0).unwrap().into() {
+            match state.stack.peek(2) {
+                Ok(value) if value != M256::zero() => Err(MachineError::StaticViolation),
+                _ => Ok(()),
+            }
+        },
+        _ => Ok(()),
+    }
+}
+
+pub fn extra_check_opcode<M: Memory + Default, S: Storage + Default + Clone>(instruction: Instruction, state: &State<M, S>, stipend_gas: Gas, after_gas: Gas) -> Result<(), EvalError> {
+    match instruction {
+        Instruction::CALL | Instruction::DELEGATECALL => {
+            // After EIP150 the forwarded gas is capped instead.
+            if !state.patch.eip150() && after_gas - stipend_gas < state.stack.peek(0)?.into() {
+                Err(EvalError::Machine(MachineError::EmptyGas))
+            } else {
+                Ok(())
+            }
+        },
+        Instruction::SSTORE => {
+            // The gas left before the instruction is charged must be
+            // more than the stipend a value transfer forwards.
+            if state.patch.sstore_gas_sentinel && state.available_gas() <= Gas::from(state.patch.gas.call_stipend) {
                 Err(EvalError::Machine(MachineError::EmptyGas))
             } else {
                 Ok(())
@@ -34,6 +109,8 @@ pub fn extra_check_opcode<M: Memory + Default, S: Storage + Default + Clone>(ins
 /// Check whether `run_opcode` would fail without mutating any of the
 /// machine state.
 pub fn check_opcode<M: Memory + Default, S: Storage + Default + Clone>(instruction: Instruction, state: &State<M, S>) -> Result<Option<ControlCheck>, EvalError> {
+    check_static(instruction, state)?;
+
     match instruction {
         Instruction::STOP => Ok(None),
         Instruction::ADD => { state.stack.check_pop_push(2, 1)?; Ok(None) },
@@ -62,14 +139,14 @@ pub fn check_opcode<M: Memory + Default, S: Storage + Default + Clone>(instructi
 
         Instruction::SHA3 => {
             state.stack.check_pop_push(2, 1)?;
-            check_range(state.stack.peek(0).unwrap(), state.stack.peek(1).unwrap())?;
+            check_memory_read_range(state.stack.peek(0)?, state.stack.peek(1)?)?;
             Ok(None)
         },
 
         Instruction::ADDRESS => { state.stack.check_pop_push(0, 1)?; Ok(None) },
         Instruction::BALANCE => {
             state.stack.check_pop_push(1, 1)?;
-            state.account_state.require(state.stack.peek(0).unwrap().into())?;
+            state.account_state.require(state.stack.peek(0)?.into())?;
             Ok(None)
         },
         Instruction::ORIGIN => { state.stack.check_pop_push(0, 1)?; Ok(None) },
@@ -80,34 +157,41 @@ pub fn check_opcode<M: Memory + Default, S: Storage + Default + Clone>(instructi
         Instruction::CALLDATACOPY => {
             state.stack.check_pop_push(3, 0)?;
             check_memory_write_range(&state.memory,
-                                     state.stack.peek(0).unwrap(), state.stack.peek(2).unwrap())?;
+                                     state.stack.peek(0)?, state.stack.peek(2)?)?;
             Ok(None)
         },
         Instruction::CODESIZE => { state.stack.check_pop_push(0, 1)?; Ok(None) },
         Instruction::CODECOPY => {
             state.stack.check_pop_push(3, 0)?;
             check_memory_write_range(&state.memory,
-                               state.stack.peek(0).unwrap(), state.stack.peek(2).unwrap())?;
+                               state.stack.peek(0)?, state.stack.peek(2)?)?;
             Ok(None)
         },
         Instruction::GASPRICE => { state.stack.check_pop_push(0, 1)?; Ok(None) },
         Instruction::EXTCODESIZE => {
             state.stack.check_pop_push(1, 1)?;
-            state.account_state.require_code(state.stack.peek(0).unwrap().into())?;
+            let address = state.stack.peek(0)?.into();
+            state.external_code(address)?;
             Ok(None)
         },
         Instruction::EXTCODECOPY => {
             state.stack.check_pop_push(4, 0)?;
-            state.account_state.require_code(state.stack.peek(0).unwrap().into())?;
+            let address = state.stack.peek(0)?.into();
+            state.external_code(address)?;
             check_memory_write_range(&state.memory,
-                                     state.stack.peek(1).unwrap(), state.stack.peek(3).unwrap())?;
+                                     state.stack.peek(1)?, state.stack.peek(3)?)?;
+            Ok(None)
+        },
+        Instruction::EXTCODEHASH => {
+            state.stack.check_pop_push(1, 1)?;
+            state.account_state.require(state.stack.peek(0)?.into())?;
             Ok(None)
         },
 
         Instruction::BLOCKHASH => {
             state.stack.check_pop_push(1, 1)?;
             let current_number = state.block.number;
-            let number = state.stack.peek(0).unwrap();
+            let number = state.stack.peek(0)?;
             if !(number >= current_number || current_number - number > M256::from(256u64)) {
                 state.blockhash_state.get(number)?;
             }
@@ -118,17 +202,26 @@ pub fn check_opcode<M: Memory + Default, S: Storage + Default + Clone>(instructi
         Instruction::NUMBER => { state.stack.check_pop_push(0, 1)?; Ok(None) },
         Instruction::DIFFICULTY => { state.stack.check_pop_push(0, 1)?; Ok(None) },
         Instruction::GASLIMIT => { state.stack.check_pop_push(0, 1)?; Ok(None) },
+        Instruction::CHAINID => { state.stack.check_pop_push(0, 1)?; Ok(None) },
+        Instruction::SELFBALANCE => {
+            state.stack.check_pop_push(0, 1)?;
+            state.account_state.require(state.context.address)?;
+            Ok(None)
+        },
+        Instruction::BASEFEE => { state.stack.check_pop_push(0, 1)?; Ok(None) },
+        Instruction::BLOBHASH => { state.stack.check_pop_push(1, 1)?; Ok(None) },
+        Instruction::BLOBBASEFEE => { state.stack.check_pop_push(0, 1)?; Ok(None) },
 
         Instruction::POP => { state.stack.check_pop_push(1, 0)?; Ok(None) },
         Instruction::MLOAD => { state.stack.check_pop_push(1, 1)?; Ok(None) },
         Instruction::MSTORE => {
             state.stack.check_pop_push(2, 0)?;
-            state.memory.check_write(state.stack.peek(0).unwrap())?;
+            check_memory_write_range(&state.memory, state.stack.peek(0)?, M256::from(32u64))?;
             Ok(None)
         },
         Instruction::MSTORE8 => {
             state.stack.check_pop_push(2, 0)?;
-            state.memory.check_write(state.stack.peek(0).unwrap())?;
+            check_memory_write_range(&state.memory, state.stack.peek(0)?, M256::from(1u64))?;
             Ok(None)
         },
         Instruction::SLOAD => {
@@ -139,18 +232,17 @@ pub fn check_opcode<M: Memory + Default, S: Storage + Default + Clone>(instructi
         Instruction::SSTORE => {
             state.stack.check_pop_push(2, 0)?;
             state.account_state.require(state.context.address)?;
-            state.account_state.storage(state.context.address)
-                .unwrap().check_write(state.stack.peek(0).unwrap())?;
+            state.account_state.storage(state.context.address)?.check_write(state.stack.peek(0)?)?;
             Ok(None)
         },
         Instruction::JUMP => {
             state.stack.check_pop_push(1, 0)?;
-            Ok(Some(ControlCheck::Jump(state.stack.peek(0).unwrap())))
+            Ok(Some(ControlCheck::Jump(state.stack.peek(0)?)))
         },
         Instruction::JUMPI => {
             state.stack.check_pop_push(2, 0)?;
-            if state.stack.peek(1).unwrap() != M256::zero() {
-                Ok(Some(ControlCheck::Jump(state.stack.peek(0).unwrap())))
+            if state.stack.peek(1)? != M256::zero() {
+                Ok(Some(ControlCheck::Jump(state.stack.peek(0)?)))
             } else {
                 Ok(None)
             }
@@ -159,50 +251,84 @@ pub fn check_opcode<M: Memory + Default, S: Storage + Default + Clone>(instructi
         Instruction::MSIZE => { state.stack.check_pop_push(0, 1)?; Ok(None) },
         Instruction::GAS => { state.stack.check_pop_push(0, 1)?; Ok(None) },
         Instruction::JUMPDEST => Ok(None),
+        Instruction::TLOAD => { state.stack.check_pop_push(1, 1)?; Ok(None) },
+        Instruction::TSTORE => { state.stack.check_pop_push(2, 0)?; Ok(None) },
 
-        Instruction::PUSH(v) => { state.stack.check_pop_push(0, 1)?; Ok(None) },
+        Instruction::PUSH0 => { state.stack.check_pop_push(0, 1)?; Ok(None) },
+        Instruction::PUSH(_, _) => { state.stack.check_pop_push(0, 1)?; Ok(None) },
 
+        // The decoder only produces DUP1..DUP16 and SWAP1..SWAP16.
+        Instruction::DUP(v) if v == 0 || v > 16 => Err(PCError::InvalidOpcode.into()),
+        Instruction::SWAP(v) if v == 0 || v > 16 => Err(PCError::InvalidOpcode.into()),
         Instruction::DUP(v) => { state.stack.check_pop_push(v, v+1)?; Ok(None) },
         Instruction::SWAP(v) => { state.stack.check_pop_push(v+1, v+1)?; Ok(None) },
 
         Instruction::LOG(v) => {
             state.stack.check_pop_push(v+2, 0)?;
-            check_range(state.stack.peek(0).unwrap(), state.stack.peek(1).unwrap())?;
+            check_memory_read_range(state.stack.peek(0)?, state.stack.peek(1)?)?;
+            check_log_data_limit(state, state.stack.peek(1)?)?;
+            check_log_limit(state)?;
             Ok(None)
         },
         Instruction::CREATE => {
             state.stack.check_pop_push(3, 1)?;
-            check_range(state.stack.peek(1).unwrap(), state.stack.peek(2).unwrap())?;
+            check_memory_read_range(state.stack.peek(1)?, state.stack.peek(2)?)?;
             state.account_state.require(state.context.address)?;
+            // The target is needed to detect an address collision.
+            let nonce = state.account_state.nonce(state.context.address)?;
+            state.account_state.require(create_address(state.context.address, nonce))?;
             Ok(None)
         },
         Instruction::CALL => {
             state.stack.check_pop_push(7, 1)?;
-            check_range(state.stack.peek(3).unwrap(), state.stack.peek(4).unwrap())?;
+            check_memory_read_range(state.stack.peek(3)?, state.stack.peek(4)?)?;
             check_memory_write_range(&state.memory,
-                                     state.stack.peek(5).unwrap(), state.stack.peek(6).unwrap())?;
+                                     state.stack.peek(5)?, state.stack.peek(6)?)?;
             state.account_state.require(state.context.address)?;
-            state.account_state.require(state.stack.peek(1).unwrap().into())?;
+            // A full account carries its code, so the target code is
+            // always available when the call is run.
+            state.account_state.require(state.stack.peek(1)?.into())?;
             Ok(None)
         },
         Instruction::CALLCODE => {
             state.stack.check_pop_push(7, 1)?;
-            check_range(state.stack.peek(3).unwrap(), state.stack.peek(4).unwrap())?;
+            check_memory_read_range(state.stack.peek(3)?, state.stack.peek(4)?)?;
             check_memory_write_range(&state.memory,
-                                     state.stack.peek(5).unwrap(), state.stack.peek(6).unwrap())?;
+                                     state.stack.peek(5)?, state.stack.peek(6)?)?;
             state.account_state.require(state.context.address)?;
-            state.account_state.require(state.stack.peek(1).unwrap().into())?;
+            // A full account carries its code, so the target code is
+            // always available when the call is run.
+            state.account_state.require(state.stack.peek(1)?.into())?;
             Ok(None)
         },
         Instruction::RETURN => {
             state.stack.check_pop_push(2, 0)?;
-            check_range(state.stack.peek(0).unwrap(), state.stack.peek(1).unwrap())?;
+            check_memory_read_range(state.stack.peek(0)?, state.stack.peek(1)?)?;
+            Ok(None)
+        },
+        Instruction::DELEGATECALL => {
+            state.stack.check_pop_push(6, 1)?;
+            check_memory_read_range(state.stack.peek(2)?, state.stack.peek(3)?)?;
+            check_memory_write_range(&state.memory,
+                                     state.stack.peek(4)?, state.stack.peek(5)?)?;
+            state.account_state.require(state.context.address)?;
+            state.account_state.require(state.stack.peek(1)?.into())?;
+            Ok(None)
+        },
+        Instruction::STATICCALL => {
+            state.stack.check_pop_push(6, 1)?;
+            check_memory_read_range(state.stack.peek(2)?, state.stack.peek(3)?)?;
+            check_memory_write_range(&state.memory,
+                                     state.stack.peek(4)?, state.stack.peek(5)?)?;
+            state.account_state.require(state.context.address)?;
+            state.account_state.require(state.stack.peek(1)?.into())?;
             Ok(None)
         },
-        Instruction::DELEGATECALL => unimplemented!(),
         Instruction::SUICIDE => {
             state.stack.check_pop_push(1, 0)?;
             state.account_state.require(state.context.address)?;
+            let balance = state.account_state.balance(state.context.address)?;
+            state.account_state.check_increase_balance(state.stack.peek(0)?.into(), balance)?;
             Ok(None)
         },
     }
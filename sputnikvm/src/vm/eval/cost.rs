@@ -5,50 +5,31 @@ use utils::address::Address;
 use utils::bigint::{M256, U256};
 
 use std::cmp::max;
-use vm::{Memory, Storage, Instruction};
+use vm::{Memory, Storage, Instruction, GasSchedule};
 use super::State;
 
-const G_ZERO: usize = 0;
-const G_BASE: usize = 2;
-const G_VERYLOW: usize = 3;
-const G_LOW: usize = 5;
-const G_MID: usize = 8;
-const G_HIGH: usize = 10;
-const G_EXTCODE_DEFAULT: usize = 20;
-const G_EXTCODE_EIP150: usize = 700;
-const G_BALANCE_DEFAULT: usize = 20;
-const G_BALANCE_EIP150: usize = 400;
-const G_SLOAD_DEFAULT: usize = 50;
-const G_SLOAD_EIP150: usize = 200;
-const G_JUMPDEST: usize = 1;
-const G_SSET: usize = 20000;
-const G_SRESET: usize = 5000;
-const R_SCLEAR: usize = 15000;
-const R_SUICIDE: usize = 24000;
-const G_SUICIDE_DEFAULT: usize = 0;
-const G_SUICIDE_EIP150: usize = 5000;
-const G_CREATE: usize = 32000;
-const G_CODEDEPOSITE: usize = 200;
-const G_CALL_DEFAULT: usize = 40;
-const G_CALL_EIP150: usize = 700;
-const G_CALLVALUE: usize = 9000;
-const G_CALLSTIPEND: usize = 2300;
-const G_NEWACCOUNT: usize = 25000;
-const G_EXP: usize = 10;
-const G_EXPBYTE_DEFAULT: usize = 10;
-const G_EXPBYTE_EIP160: usize = 50;
-const G_MEMORY: usize = 3;
-const G_TXCREATE: usize = 32000;
-const G_TXDATAZERO: usize = 4;
-const G_TXDATANONZERO: usize = 68;
-const G_TRANSACTION: usize = 21000;
-const G_LOG: usize = 375;
-const G_LOGDATA: usize = 8;
-const G_LOGTOPIC: usize = 375;
-const G_SHA3: usize = 30;
-const G_SHA3WORD: usize = 6;
-const G_COPY: usize = 3;
-const G_BLOCKHASH: usize = 20;
+fn account_access_cost<M: Memory + Default,
+                       S: Storage + Default>(machine: &State<M, S>, address: Address, default: usize) -> Gas {
+    if !machine.patch.berlin() {
+        default.into()
+    } else if machine.accessed_addresses.contains(&address) {
+        machine.patch.gas.warm_storage_read.into()
+    } else {
+        machine.patch.gas.cold_account_access.into()
+    }
+}
+
+fn sload_cost<M: Memory + Default,
+              S: Storage + Default>(machine: &State<M, S>) -> Gas {
+    let index = machine.stack.peek(0).unwrap();
+    if !machine.patch.berlin() {
+        machine.patch.gas.sload.into()
+    } else if machine.accessed_storage.contains(&(machine.context.address, index)) {
+        machine.patch.gas.warm_storage_read.into()
+    } else {
+        machine.patch.gas.cold_sload.into()
+    }
+}
 
 fn sstore_cost<M: Memory + Default,
                S: Storage + Default + Clone>(machine: &State<M, S>) -> Gas {
@@ -56,10 +37,16 @@ fn sstore_cost<M: Memory + Default,
     let value = machine.stack.peek(1).unwrap();
     let address = machine.context.address;
 
-    if value != M256::zero() && machine.account_state.storage(address).unwrap().read(index) == M256::zero() {
-        G_SSET.into()
+    let cold = if machine.patch.berlin() && !machine.accessed_storage.contains(&(address, index)) {
+        Gas::from(machine.patch.gas.cold_sload)
+    } else {
+        Gas::zero()
+    };
+
+    cold + if value != M256::zero() && machine.account_state.storage(address).unwrap().read(index) == M256::zero() {
+        machine.patch.gas.sset.into()
     } else {
-        G_SRESET.into()
+        machine.patch.gas.sreset.into()
     }
 }
 
@@ -70,14 +57,15 @@ fn call_cost<M: Memory + Default,
 
 fn extra_cost<M: Memory + Default,
               S: Storage + Default + Clone>(machine: &State<M, S>) -> Gas {
-    Gas::from(if machine.patch.eip150() { G_CALL_EIP150 } else { G_CALL_DEFAULT }) + xfer_cost(machine) + new_cost(machine)
+    let address: Address = machine.stack.peek(1).unwrap().into();
+    account_access_cost(machine, address, machine.patch.gas.call) + xfer_cost(machine) + new_cost(machine)
 }
 
 fn xfer_cost<M: Memory + Default,
              S: Storage + Default>(machine: &State<M, S>) -> Gas {
     let val = machine.stack.peek(2).unwrap();
     if val != M256::zero() {
-        G_CALLVALUE.into()
+        machine.patch.gas.call_value.into()
     } else {
         Gas::zero()
     }
@@ -87,7 +75,7 @@ fn new_cost<M: Memory + Default,
             S: Storage + Default + Clone>(machine: &State<M, S>) -> Gas {
     let address: Address = machine.stack.peek(1).unwrap().into();
     if machine.account_state.balance(address).unwrap() == U256::zero() && machine.account_state.nonce(address).unwrap() == M256::zero() && machine.account_state.code(address).unwrap().len() == 0 {
-        G_NEWACCOUNT.into()
+        machine.patch.gas.new_account.into()
     } else {
         Gas::zero()
     }
@@ -96,13 +84,25 @@ fn new_cost<M: Memory + Default,
 fn suicide_cost<M: Memory + Default,
                 S: Storage + Default>(machine: &State<M, S>) -> Gas {
     let address: Address = machine.stack.peek(0).unwrap().into();
-    Gas::from(if machine.patch.eip150() { G_SUICIDE_EIP150 } else { G_SUICIDE_DEFAULT }) + if address == Address::default() {
-        Gas::from(G_NEWACCOUNT)
+    let cold = if machine.patch.berlin() && !machine.accessed_addresses.contains(&address) {
+        Gas::from(machine.patch.gas.cold_account_access)
+    } else {
+        Gas::zero()
+    };
+    cold + Gas::from(machine.patch.gas.suicide) + if address == Address::default() {
+        Gas::from(machine.patch.gas.new_account)
     } else {
         Gas::zero()
     }
 }
 
+/// Cost of copying `len` bytes, charged per word rounded up.
+fn copy_cost(schedule: &GasSchedule, len: M256) -> Gas {
+    let wordd = Gas::from(len) / Gas::from(32u64);
+    let wordr = Gas::from(len) % Gas::from(32u64);
+    Gas::from(schedule.copy) * if wordr == Gas::zero() { wordd } else { wordd + Gas::from(1u64) }
+}
+
 fn memory_expand(current: Gas, from: Gas, len: Gas) -> Gas {
     if len == Gas::zero() {
         return current;
@@ -117,14 +117,24 @@ fn memory_expand(current: Gas, from: Gas, len: Gas) -> Gas {
     max(current, new)
 }
 
+/// Calculate the intrinsic gas of a MessageCall transaction, which is
+/// charged before the VM starts.
+pub fn intrinsic_gas(schedule: &GasSchedule, data: &[u8]) -> Gas {
+    let mut gas = Gas::from(schedule.transaction);
+    for &byte in data {
+        gas = gas + Gas::from(if byte == 0 { schedule.tx_data_zero } else { schedule.tx_data_non_zero });
+    }
+    gas
+}
+
 /// Calculate code deposit cost for a ContractCreation transaction.
-pub fn code_deposit_gas(len: usize) -> Gas {
-    Gas::from(G_CODEDEPOSITE) * Gas::from(len)
+pub fn code_deposit_gas(schedule: &GasSchedule, len: usize) -> Gas {
+    Gas::from(schedule.code_deposit) * Gas::from(len)
 }
 
 /// Calculate the memory gas from the memory cost.
-pub fn memory_gas(a: Gas) -> Gas {
-    (Gas::from(G_MEMORY) * a + a * a / Gas::from(512u64)).into()
+pub fn memory_gas(schedule: &GasSchedule, a: Gas) -> Gas {
+    (Gas::from(schedule.memory) * a + a * a / Gas::from(512u64)).into()
 }
 
 /// Calculate the memory cost. This is the same as the active memory
@@ -162,7 +172,15 @@ pub fn memory_cost<M: Memory + Default, S: Storage + Default + Clone>(instructio
             let len: U256 = stack.peek(2).unwrap().into();
             memory_expand(current, Gas::from(from), Gas::from(len))
         },
-        Instruction::CALL => {
+        Instruction::DELEGATECALL | Instruction::STATICCALL => {
+            let in_from: U256 = stack.peek(2).unwrap().into();
+            let in_len: U256 = stack.peek(3).unwrap().into();
+            let out_from: U256 = stack.peek(4).unwrap().into();
+            let out_len: U256 = stack.peek(5).unwrap().into();
+            memory_expand(memory_expand(current, Gas::from(in_from), Gas::from(in_len)),
+                          Gas::from(out_from), Gas::from(out_len))
+        },
+        Instruction::CALL | Instruction::CALLCODE => {
             let in_from: U256 = stack.peek(3).unwrap().into();
             let in_len: U256 = stack.peek(4).unwrap().into();
             let out_from: U256 = stack.peek(5).unwrap().into();
@@ -180,8 +198,11 @@ pub fn memory_cost<M: Memory + Default, S: Storage + Default + Clone>(instructio
 /// Calculate the gas cost.
 pub fn gas_cost<M: Memory + Default, S: Storage + Default + Clone>(instruction: Instruction, state: &State<M, S>) -> Gas {
     match instruction {
-        Instruction::CALL | Instruction::CALLCODE |
-        Instruction::DELEGATECALL => call_cost(state),
+        Instruction::CALL | Instruction::CALLCODE => call_cost(state),
+        Instruction::DELEGATECALL | Instruction::STATICCALL => {
+            let address: Address = state.stack.peek(1).unwrap().into();
+            account_access_cost(state, address, state.patch.gas.call)
+        },
         Instruction::SUICIDE => suicide_cost(state),
         Instruction::SSTORE => sstore_cost(state),
 
@@ -189,79 +210,87 @@ pub fn gas_cost<M: Memory + Default, S: Storage + Default + Clone>(instruction:
             let len = state.stack.peek(1).unwrap();
             let wordd = Gas::from(len) / Gas::from(32u64);
             let wordr = Gas::from(len) % Gas::from(32u64);
-            (Gas::from(G_SHA3) + Gas::from(G_SHA3WORD) * if wordr == Gas::zero() { wordd } else { wordd + Gas::from(1u64) }).into()
+            (Gas::from(state.patch.gas.sha3) + Gas::from(state.patch.gas.sha3_word) * if wordr == Gas::zero() { wordd } else { wordd + Gas::from(1u64) }).into()
         },
 
         Instruction::LOG(v) => {
             let len = state.stack.peek(1).unwrap();
-            (Gas::from(G_LOG) + Gas::from(G_LOGDATA) * Gas::from(len) + Gas::from(G_LOGTOPIC) * Gas::from(v)).into()
+            (Gas::from(state.patch.gas.log) + Gas::from(state.patch.gas.log_data) * Gas::from(len) + Gas::from(state.patch.gas.log_topic) * Gas::from(v)).into()
         },
 
         Instruction::EXTCODECOPY => {
-            let len = state.stack.peek(3).unwrap();
-            let wordd = Gas::from(len) / Gas::from(32u64);
-            let wordr = Gas::from(len) % Gas::from(32u64);
-            (Gas::from(if state.patch.eip150() { G_EXTCODE_EIP150 } else { G_EXTCODE_DEFAULT }) + Gas::from(G_COPY) * if wordr == Gas::zero() { wordd } else { wordd + Gas::from(1u64) }).into()
+            let address: Address = state.stack.peek(0).unwrap().into();
+            account_access_cost(state, address, state.patch.gas.extcode) + copy_cost(&state.patch.gas, state.stack.peek(3).unwrap())
         },
 
         Instruction::CALLDATACOPY | Instruction::CODECOPY => {
-            let len = state.stack.peek(2).unwrap();
-            let wordd = Gas::from(len) / Gas::from(32u64);
-            let wordr = Gas::from(len) % Gas::from(32u64);
-            (Gas::from(G_VERYLOW) + Gas::from(G_COPY) * if wordr == Gas::zero() { wordd } else { wordd + Gas::from(1u64) }).into()
+            Gas::from(state.patch.gas.verylow) + copy_cost(&state.patch.gas, state.stack.peek(2).unwrap())
         },
 
         Instruction::EXP => {
             if state.stack.peek(1).unwrap() == M256::zero() {
-                Gas::from(G_EXP)
+                Gas::from(state.patch.gas.exp)
             } else {
-                Gas::from(G_EXP) + Gas::from(if state.patch.eip160() { G_EXPBYTE_EIP160 } else { G_EXPBYTE_DEFAULT }) * (Gas::from(1u64) + Gas::from(state.stack.peek(1).unwrap().log2floor()) / Gas::from(8u64))
+                Gas::from(state.patch.gas.exp) + Gas::from(state.patch.gas.exp_byte) * (Gas::from(1u64) + Gas::from(state.stack.peek(1).unwrap().log2floor()) / Gas::from(8u64))
             }
         }
 
-        Instruction::CREATE => G_CREATE.into(),
-        Instruction::JUMPDEST => G_JUMPDEST.into(),
-        Instruction::SLOAD => (if state.patch.eip150() { G_SLOAD_EIP150 } else { G_SLOAD_DEFAULT }).into(),
+        Instruction::CREATE => state.patch.gas.create.into(),
+        Instruction::JUMPDEST => state.patch.gas.jumpdest.into(),
+        Instruction::SLOAD => sload_cost(state),
 
         // W_zero
         Instruction::STOP | Instruction::RETURN
-            => G_ZERO.into(),
+            => state.patch.gas.zero.into(),
 
         // W_base
         Instruction::ADDRESS | Instruction::ORIGIN | Instruction::CALLER |
         Instruction::CALLVALUE | Instruction::CALLDATASIZE |
         Instruction::CODESIZE | Instruction::GASPRICE | Instruction::COINBASE |
         Instruction::TIMESTAMP | Instruction::NUMBER | Instruction::DIFFICULTY |
-        Instruction::GASLIMIT | Instruction::POP | Instruction::PC |
-        Instruction::MSIZE | Instruction::GAS
-            => G_BASE.into(),
+        Instruction::GASLIMIT | Instruction::CHAINID | Instruction::BASEFEE |
+        Instruction::BLOBBASEFEE | Instruction::POP | Instruction::PC |
+        Instruction::MSIZE | Instruction::GAS | Instruction::PUSH0
+            => state.patch.gas.base.into(),
 
         // W_verylow
         Instruction::ADD | Instruction::SUB | Instruction::NOT | Instruction::LT |
         Instruction::GT | Instruction::SLT | Instruction::SGT | Instruction::EQ |
         Instruction::ISZERO | Instruction::AND | Instruction::OR | Instruction::XOR |
         Instruction::BYTE | Instruction::CALLDATALOAD | Instruction::MLOAD |
-        Instruction::MSTORE | Instruction::MSTORE8 | Instruction::PUSH(_) |
-        Instruction::DUP(_) | Instruction::SWAP(_)
-            => G_VERYLOW.into(),
+        Instruction::MSTORE | Instruction::MSTORE8 | Instruction::PUSH(_, _) |
+        Instruction::DUP(_) | Instruction::SWAP(_) | Instruction::BLOBHASH
+            => state.patch.gas.verylow.into(),
 
         // W_low
         Instruction::MUL | Instruction::DIV | Instruction::SDIV | Instruction::MOD |
-        Instruction::SMOD | Instruction::SIGNEXTEND
-            => G_LOW.into(),
+        Instruction::SMOD | Instruction::SIGNEXTEND | Instruction::SELFBALANCE
+            => state.patch.gas.low.into(),
 
         // W_mid
         Instruction::ADDMOD | Instruction::MULMOD | Instruction::JUMP
-            => G_MID.into(),
+            => state.patch.gas.mid.into(),
 
         // W_high
-        Instruction::JUMPI => G_HIGH.into(),
+        Instruction::JUMPI => state.patch.gas.high.into(),
 
         // W_extcode
-        Instruction::EXTCODESIZE => (if state.patch.eip150() { G_EXTCODE_EIP150 } else { G_EXTCODE_DEFAULT }).into(),
+        Instruction::EXTCODESIZE => {
+            let address: Address = state.stack.peek(0).unwrap().into();
+            account_access_cost(state, address, state.patch.gas.extcode)
+        },
+        Instruction::EXTCODEHASH => {
+            let address: Address = state.stack.peek(0).unwrap().into();
+            account_access_cost(state, address, state.patch.gas.extcodehash)
+        },
 
-        Instruction::BALANCE => (if state.patch.eip150() { G_BALANCE_EIP150 } else { G_BALANCE_DEFAULT }).into(),
-        Instruction::BLOCKHASH => G_BLOCKHASH.into(),
+        Instruction::BALANCE => {
+            let address: Address = state.stack.peek(0).unwrap().into();
+            account_access_cost(state, address, state.patch.gas.balance)
+        },
+        Instruction::BLOCKHASH => state.patch.gas.blockhash.into(),
+        Instruction::TLOAD => state.patch.gas.tload.into(),
+        Instruction::TSTORE => state.patch.gas.tstore.into(),
     }
 }
 
@@ -272,7 +301,7 @@ pub fn gas_stipend<M: Memory + Default, S: Storage + Default + Clone>(instructio
             let value = state.stack.peek(2).unwrap();
 
             if value != M256::zero() {
-                G_CALLSTIPEND.into()
+                state.patch.gas.call_stipend.into()
             } else {
                 Gas::zero()
             }
@@ -290,14 +319,17 @@ pub fn gas_refund<M: Memory + Default, S: Storage + Default + Clone>(instruction
             let address = state.context.address;
 
             if value == M256::zero() && state.account_state.storage(address).unwrap().read(index) != M256::zero() {
-                Gas::from(R_SCLEAR)
+                Gas::from(state.patch.gas.sclear_refund)
             } else {
                 Gas::zero()
             }
         },
         Instruction::SUICIDE => {
-            // TODO: check whether I_a belongs to A_s
-            Gas::zero()
+            if state.account_state.is_removed(state.context.address) {
+                Gas::zero()
+            } else {
+                Gas::from(state.patch.gas.suicide_refund)
+            }
         },
         _ => Gas::zero()
     }
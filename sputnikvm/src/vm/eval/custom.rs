@@ -0,0 +1,23 @@
+//! Opcodes added by embedders
+
+use utils::gas::Gas;
+use super::super::errors::EvalError;
+use super::{State, Control};
+
+/// Handler of opcode bytes outside of the instruction set, for chains
+/// that add their own opcodes in unused slots. It is only consulted
+/// for bytes that would otherwise be invalid. Memory expansion by a
+/// custom opcode is not charged; include it in `gas_cost` instead.
+pub trait CustomOpcode<M, S>: Send + Sync {
+    /// Whether this handler runs `opcode`.
+    fn handles(&self, opcode: u8) -> bool;
+    /// Check whether `opcode` can run, for example whether the stack
+    /// has enough items. Errors exit the runtime as for any other
+    /// instruction.
+    fn check(&self, opcode: u8, state: &State<M, S>) -> Result<(), EvalError>;
+    /// Gas charged for running `opcode`.
+    fn gas_cost(&self, opcode: u8, state: &State<M, S>) -> Gas;
+    /// Run `opcode`. `after_gas` is the gas available once the cost
+    /// is paid.
+    fn run(&self, opcode: u8, state: &mut State<M, S>, stipend_gas: Gas, after_gas: Gas) -> Option<Control>;
+}
@@ -1,19 +1,34 @@
 //! VM Runtime
-use utils::bigint::M256;
+use utils::collections::{Map, Set};
+use std::cmp::min;
+use std::sync::Arc;
+use utils::bigint::{M256, U256};
+use utils::address::Address;
 use utils::gas::Gas;
+use utils::opcode::Opcode;
 use super::commit::{AccountState, BlockhashState};
-use super::errors::{RequireError, MachineError, CommitError, EvalError, PCError};
-use super::{Stack, Context, BlockHeader, Patch, PC, Storage, Memory, AccountCommitment, Log};
+use super::errors::{RequireError, MachineError, CommitError, EvalError, PCError, ApplyError};
+use super::{Stack, Context, BlockHeader, Patch, PC, Storage, Memory, AccountCommitment, Log,
+            Instruction};
+use super::TraceStep;
+use super::precompiled::{is_precompiled, precompiled_gas, run_precompiled};
 
-use self::check::{check_opcode, extra_check_opcode};
+use self::check::{check_opcode, check_opcode_fork, extra_check_opcode};
 use self::run::run_opcode;
 use self::cost::{gas_refund, gas_stipend, gas_cost, memory_cost, memory_gas, code_deposit_gas};
-use self::utils::copy_into_memory;
+use self::utils::{copy_into_memory, checked_usize};
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+pub use self::cost::intrinsic_gas;
+pub use self::run::EMPTY_CODE_HASH;
+pub use self::custom::CustomOpcode;
 
 mod cost;
 mod run;
 mod check;
 mod utils;
+mod custom;
 
 /// A VM state without PC.
 pub struct State<M, S> {
@@ -25,26 +40,180 @@ pub struct State<M, S> {
     pub patch: Patch,
 
     pub out: Vec<u8>,
+    /// Data returned by the most recent sub runtime of this runtime.
+    /// Empty at the start of the frame.
+    pub return_data: Vec<u8>,
 
+    /// Active memory size in 32-byte words (`μ_i` in the Yellow
+    /// Paper). This is not a gas figure; see `memory_gas`.
     pub memory_cost: Gas,
+    /// Sum of the active memory sizes, in words, of all outer frames
+    /// of this runtime.
+    pub outer_memory_cost: Gas,
     pub used_gas: Gas,
     pub refunded_gas: Gas,
 
     pub account_state: AccountState<S>,
     pub blockhash_state: BlockhashState,
     pub logs: Vec<Log>,
+    /// Transient storage of the current transaction, keyed by account
+    /// address and index. Shared by the call tree and discarded
+    /// afterwards.
+    pub transient_storage: Map<(Address, M256), M256>,
+    /// Accounts already accessed in the current transaction
+    /// (EIP-2929). Rolled back together with failed sub runtimes.
+    pub accessed_addresses: Set<Address>,
+    /// Storage slots already accessed in the current transaction,
+    /// keyed by account address and index (EIP-2929).
+    pub accessed_storage: Set<(Address, M256)>,
+    /// Gas used by the sub runtimes applied to this runtime, in the
+    /// order they were invoked.
+    pub sub_calls: Vec<SubCall>,
+    /// Data returned by every sub runtime in the call tree of this
+    /// runtime, including sub runtimes of sub runtimes, in the order
+    /// they exited.
+    pub frame_returns: Vec<FrameReturn>,
 
     pub depth: usize,
 }
 
 impl<M, S> State<M, S> {
     pub fn memory_gas(&self) -> Gas {
-        memory_gas(self.memory_cost)
+        memory_gas(&self.patch.gas, self.memory_cost)
+    }
+
+    /// Gas used by this runtime, including its memory gas.
+    pub fn total_used_gas(&self) -> Gas {
+        self.used_gas + self.memory_gas()
     }
 
     pub fn available_gas(&self) -> Gas {
+        if self.patch.ignore_gas {
+            return Gas::from(U256::max_value());
+        }
         self.context.gas_limit - self.memory_gas() - self.used_gas
     }
+
+    /// Whether the account is the contract being created by this
+    /// runtime, whose code is empty until the init code returns.
+    fn is_constructing(&self, address: Address) -> bool {
+        self.context.is_create && self.context.address == address
+    }
+
+    /// Mark the account or storage slot the instruction accesses as
+    /// warm, so that later accesses are charged the warm cost.
+    fn mark_accessed(&mut self, instruction: Instruction) {
+        match instruction {
+            Instruction::SLOAD | Instruction::SSTORE => {
+                let index = self.stack.peek(0).unwrap();
+                self.accessed_storage.insert((self.context.address, index));
+            },
+            Instruction::BALANCE | Instruction::EXTCODESIZE |
+            Instruction::EXTCODECOPY | Instruction::EXTCODEHASH |
+            Instruction::SUICIDE => {
+                self.accessed_addresses.insert(self.stack.peek(0).unwrap().into());
+            },
+            Instruction::CALL | Instruction::CALLCODE | Instruction::DELEGATECALL |
+            Instruction::STATICCALL => {
+                self.accessed_addresses.insert(self.stack.peek(1).unwrap().into());
+            },
+            _ => (),
+        }
+    }
+}
+
+impl<M, S: Storage + Default + Clone> State<M, S> {
+    /// Code of the account as seen by `EXTCODESIZE` and
+    /// `EXTCODECOPY`. The contract being created, and precompiled
+    /// contracts of the patch that are not committed, have empty
+    /// code.
+    fn external_code(&self, address: Address) -> Result<&[u8], RequireError> {
+        if self.is_constructing(address) {
+            return Ok(&[]);
+        }
+        match self.account_state.code(address) {
+            Err(_) if is_precompiled(&self.patch, address) => Ok(&[]),
+            result => result,
+        }
+    }
+}
+
+impl<M, S: Clone> State<M, S> {
+    /// Take a snapshot of the account state, logs and gas of this
+    /// state, to be restored by `restore` if a speculative execution
+    /// path is rejected. Unlike `derive`, this is for the same
+    /// runtime.
+    ///
+    /// The snapshot is a full copy of those fields, and costs the same
+    /// as the copy `derive` makes for every sub runtime. The fields are
+    /// public and modified in place, so there is no journal to replay.
+    pub fn snapshot(&self) -> StateSnapshot<S> {
+        StateSnapshot {
+            account_state: self.account_state.clone(),
+            logs: self.logs.clone(),
+            transient_storage: self.transient_storage.clone(),
+            accessed_addresses: self.accessed_addresses.clone(),
+            accessed_storage: self.accessed_storage.clone(),
+            used_gas: self.used_gas,
+            refunded_gas: self.refunded_gas,
+        }
+    }
+
+    /// Restore a snapshot previously taken by `snapshot`.
+    pub fn restore(&mut self, snapshot: StateSnapshot<S>) {
+        self.account_state = snapshot.account_state;
+        self.logs = snapshot.logs;
+        self.transient_storage = snapshot.transient_storage;
+        self.accessed_addresses = snapshot.accessed_addresses;
+        self.accessed_storage = snapshot.accessed_storage;
+        self.used_gas = snapshot.used_gas;
+        self.refunded_gas = snapshot.refunded_gas;
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A snapshot of a `State`, taken by `State::snapshot`.
+pub struct StateSnapshot<S> {
+    account_state: AccountState<S>,
+    logs: Vec<Log>,
+    transient_storage: Map<(Address, M256), M256>,
+    accessed_addresses: Set<Address>,
+    accessed_storage: Set<(Address, M256)>,
+    used_gas: Gas,
+    refunded_gas: Gas,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+/// Gas used by a sub runtime, recorded by its parent.
+pub struct SubCall {
+    /// Address the sub runtime ran at.
+    pub address: Address,
+    /// Whether the sub runtime was a contract creation.
+    pub is_create: bool,
+    /// Gas used by the sub runtime itself, including its memory gas.
+    pub used_gas: Gas,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+/// Data returned by a sub runtime, recorded by its parent.
+pub struct FrameReturn {
+    /// Address the sub runtime ran at.
+    pub address: Address,
+    /// Data returned by the sub runtime.
+    pub data: Vec<u8>,
+    /// Whether the sub runtime exited with an error, in which case
+    /// its changes were reverted.
+    pub reverted: bool,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+/// Growth of the active memory of a runtime by one instruction.
+/// Sizes are in bytes, and always a multiple of 32.
+pub struct MemoryGrowth {
+    /// Position of the instruction in the code.
+    pub position: usize,
+    pub old_size: Gas,
+    pub new_size: Gas,
 }
 
 /// A VM state with PC.
@@ -52,6 +221,10 @@ pub struct Machine<M, S> {
     state: State<M, S>,
     pc: PC,
     status: MachineStatus,
+    trace: Option<Vec<TraceStep>>,
+    opcode_counts: Option<Map<Opcode, usize>>,
+    memory_growths: Option<Vec<MemoryGrowth>>,
+    custom_opcodes: Option<Arc<dyn CustomOpcode<M, S>>>,
 }
 
 #[derive(Debug, Clone)]
@@ -73,6 +246,41 @@ pub enum MachineStatus {
     InvokeCall(Context, (M256, M256)),
 }
 
+/// An iterator over the steps of a runtime, created by
+/// `Machine::steps`.
+pub struct Steps<'a, M: 'a, S: 'a> {
+    machine: &'a mut Machine<M, S>,
+}
+
+impl<'a, M: Memory + Default, S: Storage + Default + Clone> Steps<'a, M, S> {
+    /// Commit a new account into the underlying runtime.
+    pub fn commit_account(&mut self, commitment: AccountCommitment<S>) -> Result<(), CommitError> {
+        self.machine.commit_account(commitment)
+    }
+
+    /// Commit a new blockhash into the underlying runtime.
+    pub fn commit_blockhash(&mut self, number: M256, hash: M256) -> Result<(), CommitError> {
+        self.machine.commit_blockhash(number, hash)
+    }
+
+    /// Get the underlying runtime.
+    pub fn machine(&self) -> &Machine<M, S> {
+        self.machine
+    }
+}
+
+impl<'a, M: Memory + Default, S: Storage + Default + Clone> Iterator for Steps<'a, M, S> {
+    type Item = Result<MachineStatus, RequireError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.machine.status {
+            MachineStatus::Running => (),
+            _ => return None,
+        }
+        Some(self.machine.step().map(|()| self.machine.status()))
+    }
+}
+
 #[derive(Debug, Clone)]
 /// Used for `check` for additional checks related to the runtime.
 pub enum ControlCheck {
@@ -88,12 +296,33 @@ pub enum Control {
     InvokeCall(Context, (M256, M256)),
 }
 
+/// Addresses that are warm at the start of a transaction (EIP-2929):
+/// the sender, the target and the precompiled contracts.
+fn initial_accessed_addresses(context: &Context) -> Set<Address> {
+    let mut accessed_addresses = Set::new();
+    accessed_addresses.insert(context.address);
+    accessed_addresses.insert(context.caller);
+    for i in 0x01..0x0au64 {
+        accessed_addresses.insert(Address::from(M256::from(i)));
+    }
+    accessed_addresses
+}
+
 impl<M: Memory + Default, S: Storage + Default + Clone> Machine<M, S> {
-    /// Create a new runtime.
+    /// Create a new runtime. The block header is captured here and
+    /// shared with every sub runtime, so overrides (for example, a
+    /// different `number` or `timestamp` for simulation) must be
+    /// applied to the header before calling this.
     pub fn new(context: Context, block: BlockHeader, patch: Patch, depth: usize) -> Self {
+        let accessed_addresses = initial_accessed_addresses(&context);
+
         Machine {
             pc: PC::new(context.code.as_slice()),
             status: MachineStatus::Running,
+            trace: None,
+            opcode_counts: None,
+            memory_growths: None,
+            custom_opcodes: None,
             state: State {
                 memory: M::default(),
                 stack: Stack::default(),
@@ -103,14 +332,21 @@ impl<M: Memory + Default, S: Storage + Default + Clone> Machine<M, S> {
                 patch: patch,
 
                 out: Vec::new(),
+                return_data: Vec::new(),
 
                 memory_cost: Gas::zero(),
+                outer_memory_cost: Gas::zero(),
                 used_gas: Gas::zero(),
                 refunded_gas: Gas::zero(),
 
                 account_state: AccountState::default(),
                 blockhash_state: BlockhashState::default(),
                 logs: Vec::new(),
+                transient_storage: Map::new(),
+                accessed_addresses: accessed_addresses,
+                accessed_storage: Set::new(),
+                sub_calls: Vec::new(),
+                frame_returns: Vec::new(),
 
                 depth: depth,
             },
@@ -122,9 +358,16 @@ impl<M: Memory + Default, S: Storage + Default + Clone> Machine<M, S> {
     /// review whether it wants to accept the result of this sub
     /// runtime afterwards.
     pub fn derive(&self, context: Context) -> Self {
+        let mut accessed_addresses = self.state.accessed_addresses.clone();
+        accessed_addresses.insert(context.address);
+
         Machine {
             pc: PC::new(context.code.as_slice()),
             status: MachineStatus::Running,
+            trace: self.trace.as_ref().map(|_| Vec::new()),
+            opcode_counts: self.opcode_counts.as_ref().map(|_| Map::new()),
+            memory_growths: self.memory_growths.as_ref().map(|_| Vec::new()),
+            custom_opcodes: self.custom_opcodes.clone(),
             state: State {
                 memory: M::default(),
                 stack: Stack::default(),
@@ -134,27 +377,101 @@ impl<M: Memory + Default, S: Storage + Default + Clone> Machine<M, S> {
                 patch: self.state.patch.clone(),
 
                 out: Vec::new(),
+                return_data: Vec::new(),
 
                 memory_cost: Gas::zero(),
+                outer_memory_cost: self.state.outer_memory_cost + self.state.memory_cost,
                 used_gas: Gas::zero(),
                 refunded_gas: Gas::zero(),
 
                 account_state: self.state.account_state.clone(),
                 blockhash_state: self.state.blockhash_state.clone(),
                 logs: self.state.logs.clone(),
+                transient_storage: self.state.transient_storage.clone(),
+                accessed_addresses: accessed_addresses,
+                accessed_storage: self.state.accessed_storage.clone(),
+                sub_calls: Vec::new(),
+                frame_returns: Vec::new(),
 
-                depth: self.state.depth + 1,
+                // The call stack limit keeps this far from
+                // overflowing, but never wrap around.
+                depth: self.state.depth.saturating_add(1),
             },
         }
     }
 
+    /// Reset this runtime to run a new context, as if it was created
+    /// by `new` with the same depth. The memory and stack are cleared
+    /// but keep their allocations, so that running many short
+    /// transactions on one runtime does not reallocate them. Tracing,
+    /// opcode counting and memory growth recording stay enabled if
+    /// they were.
+    pub fn reset(&mut self, context: Context, block: BlockHeader, patch: Patch) {
+        self.pc = PC::new(context.code.as_slice());
+        self.status = MachineStatus::Running;
+        match self.trace {
+            Some(ref mut trace) => trace.clear(),
+            None => (),
+        }
+        match self.opcode_counts {
+            Some(ref mut counts) => counts.clear(),
+            None => (),
+        }
+        match self.memory_growths {
+            Some(ref mut growths) => growths.clear(),
+            None => (),
+        }
+
+        let state = &mut self.state;
+        state.memory.clear();
+        state.stack.clear();
+        state.accessed_addresses = initial_accessed_addresses(&context);
+
+        state.context = context;
+        state.block = block;
+        state.patch = patch;
+
+        state.out.clear();
+        state.return_data.clear();
+
+        state.memory_cost = Gas::zero();
+        state.outer_memory_cost = Gas::zero();
+        state.used_gas = Gas::zero();
+        state.refunded_gas = Gas::zero();
+
+        state.account_state = AccountState::default();
+        state.blockhash_state = BlockhashState::default();
+        state.logs.clear();
+        state.transient_storage.clear();
+        state.accessed_storage.clear();
+        state.sub_calls.clear();
+        state.frame_returns.clear();
+    }
+
+    /// Pre-warm the accounts and storage slots of an access list
+    /// (EIP-2930), so that their first access in this runtime is
+    /// charged the warm cost.
+    pub fn warm_access_list(&mut self, access_list: &[(Address, Vec<M256>)]) {
+        for &(address, ref indexes) in access_list {
+            self.state.accessed_addresses.insert(address);
+            for index in indexes {
+                self.state.accessed_storage.insert((address, *index));
+            }
+        }
+    }
+
     /// Commit a new account into this runtime.
     pub fn commit_account(&mut self, commitment: AccountCommitment<S>) -> Result<(), CommitError> {
         self.state.account_state.commit(commitment)
     }
 
-    /// Commit a new blockhash into this runtime.
+    /// Commit a new blockhash into this runtime. Only the 256 blocks
+    /// before the current one can be committed.
     pub fn commit_blockhash(&mut self, number: M256, hash: M256) -> Result<(), CommitError> {
+        let current_number = self.state.block.number;
+        if number >= current_number || current_number - number > M256::from(256u64) {
+            return Err(CommitError::InvalidBlockhash);
+        }
         self.state.blockhash_state.commit(number, hash)
     }
 
@@ -163,77 +480,172 @@ impl<M: Memory + Default, S: Storage + Default + Clone> Machine<M, S> {
     /// should have been created by the current runtime's `derive`
     /// function. Depending whether the current runtime is invoking a
     /// ContractCreation or MessageCall instruction, it will apply
-    /// various states back.
-    pub fn apply_sub(&mut self, sub: Machine<M, S>) {
-        use std::mem::swap;
-        let mut status = MachineStatus::Running;
-        swap(&mut status, &mut self.status);
-        match status {
+    /// various states back. Nothing is changed if the sub runtime
+    /// cannot be applied.
+    pub fn apply_sub(&mut self, sub: Machine<M, S>) -> Result<(), ApplyError> {
+        use std::mem::replace;
+        match self.status {
+            MachineStatus::InvokeCreate(_) | MachineStatus::InvokeCall(_, _) => (),
+            _ => return Err(ApplyError::NotInvoking),
+        }
+        match sub.status {
+            MachineStatus::ExitedOk | MachineStatus::ExitedErr(_) => (),
+            _ => return Err(ApplyError::SubNotExited),
+        }
+        if self.state.available_gas() < sub.state.total_used_gas() {
+            return Err(ApplyError::SubGasExceeded);
+        }
+
+        match replace(&mut self.status, MachineStatus::Running) {
             MachineStatus::InvokeCreate(_) => {
                 self.apply_create(sub);
             },
             MachineStatus::InvokeCall(_, (out_start, out_len)) => {
                 self.apply_call(sub, out_start, out_len);
             },
-            _ => panic!(),
+            _ => unreachable!(),
         }
+        Ok(())
+    }
+
+    fn record_sub_call(&mut self, sub: &Machine<M, S>) {
+        self.state.frame_returns.extend(sub.state.frame_returns.iter().cloned());
+        self.state.frame_returns.push(FrameReturn {
+            address: sub.state.context.address,
+            data: sub.state.out.clone(),
+            reverted: match sub.status {
+                MachineStatus::ExitedErr(_) => true,
+                _ => false,
+            },
+        });
+        self.state.sub_calls.push(SubCall {
+            address: sub.state.context.address,
+            is_create: sub.state.context.is_create,
+            used_gas: sub.state.total_used_gas(),
+        });
     }
 
     fn apply_create(&mut self, sub: Machine<M, S>) {
-        if self.state.available_gas() < sub.state.used_gas {
-            panic!();
-        }
+        let used_gas = sub.state.total_used_gas();
+        self.record_sub_call(&sub);
+        // The output of init code is the contract code, not return
+        // data. A failed creation would only leave return data if it
+        // reverted, and there is no REVERT.
+        self.state.return_data = Vec::new();
 
-        match sub.status() {
+        let deposit_gas = code_deposit_gas(&self.state.patch.gas, sub.state.out.len());
+        let deposit = sub.state.available_gas() >= deposit_gas;
+        let status = match sub.status() {
+            // From Homestead a creation that cannot pay for its code
+            // fails (EIP-2).
+            MachineStatus::ExitedOk if !deposit && self.state.patch.homestead() =>
+                MachineStatus::ExitedErr(MachineError::EmptyGas),
+            status => status,
+        };
+
+        match status {
             MachineStatus::ExitedOk => {
-                self.state.account_state = sub.state.account_state;
+                let mut account_state = sub.state.account_state;
+                account_state.merge_commitments(&self.state.account_state);
+                self.state.account_state = account_state;
                 self.state.blockhash_state = sub.state.blockhash_state;
                 self.state.logs = sub.state.logs;
-                self.state.used_gas = self.state.used_gas + sub.state.used_gas;
+                self.state.transient_storage = sub.state.transient_storage;
+                self.state.accessed_addresses = sub.state.accessed_addresses;
+                self.state.accessed_storage = sub.state.accessed_storage;
+                self.state.used_gas = self.state.used_gas + used_gas;
                 self.state.refunded_gas = self.state.refunded_gas + sub.state.refunded_gas;
-                if self.state.available_gas() >= code_deposit_gas(sub.state.out.len()) {
-                    self.state.account_state.decrease_balance(self.state.context.address,
-                                                              sub.state.context.value);
-                    self.state.account_state.create(sub.state.context.address,
-                                                    sub.state.context.value,
-                                                    sub.state.out.as_slice());
+                // In Frontier a creation that cannot pay for its code
+                // deploys no code.
+                let code: &[u8] = if deposit {
+                    self.state.used_gas = self.state.used_gas + deposit_gas;
+                    sub.state.out.as_slice()
+                } else {
+                    &[]
+                };
+                if let Err(error) = self.state.account_state.decrease_balance(
+                    self.state.context.address, sub.state.context.value)
+                {
+                    self.status = MachineStatus::ExitedErr(error);
+                    return;
                 }
-
+                // After EIP161 contracts start with nonce 1.
+                let nonce = if self.state.patch.eip161() { M256::from(1u64) } else { M256::zero() };
+                self.state.account_state.create(sub.state.context.address,
+                                                sub.state.context.value,
+                                                nonce,
+                                                code);
             },
-            MachineStatus::ExitedErr(_) => {
-                // self.state.used_gas = self.state.used_gas + sub.state.used_gas;
-                // self.state.stack.pop().unwrap();
-                // self.state.stack.push(M256::zero()).unwrap();
+            MachineStatus::ExitedErr(error) => {
+                self.state.account_state.merge_touched_storage(&sub.state.account_state);
+                self.state.account_state.merge_commitments(&sub.state.account_state);
+                // A failed creation pushes no address, and consumes
+                // all the gas it was given unless it overflowed the
+                // call stack and never ran.
+                match error {
+                    MachineError::CallstackOverflow => (),
+                    _ => self.state.used_gas = self.state.used_gas + sub.state.context.gas_limit,
+                }
+                self.state.stack.pop().unwrap();
+                self.state.stack.push(M256::zero()).unwrap();
             },
-            _ => panic!(),
+            _ => unreachable!(),
         }
     }
 
     fn apply_call(&mut self, sub: Machine<M, S>, out_start: M256, out_len: M256) {
-        if self.state.available_gas() < sub.state.used_gas {
-            panic!();
-        }
+        let used_gas = sub.state.total_used_gas();
+        self.record_sub_call(&sub);
 
         match sub.status() {
             MachineStatus::ExitedOk => {
-                self.state.account_state = sub.state.account_state;
+                let mut account_state = sub.state.account_state;
+                account_state.merge_commitments(&self.state.account_state);
+                self.state.account_state = account_state;
                 self.state.blockhash_state = sub.state.blockhash_state;
                 self.state.logs = sub.state.logs;
-                self.state.used_gas = self.state.used_gas + sub.state.used_gas;
+                self.state.transient_storage = sub.state.transient_storage;
+                self.state.accessed_addresses = sub.state.accessed_addresses;
+                self.state.accessed_storage = sub.state.accessed_storage;
+                self.state.used_gas = self.state.used_gas + used_gas;
                 self.state.refunded_gas = self.state.refunded_gas + sub.state.refunded_gas;
-                self.state.account_state.decrease_balance(self.state.context.address,
-                                                          sub.state.context.value);
-                self.state.account_state.increase_balance(sub.state.context.address,
-                                                          sub.state.context.value);
+                // CALLCODE runs in the caller's own context, so its
+                // value is sent to itself.
+                if sub.state.context.address != self.state.context.address {
+                    let (from, to) = (self.state.context.address, sub.state.context.address);
+                    let value = sub.state.context.value;
+                    let account_state = &mut self.state.account_state;
+                    let transfer = account_state.check_increase_balance(to, value)
+                        .and_then(|()| account_state.decrease_balance(from, value))
+                        .and_then(|()| account_state.increase_balance(to, value));
+                    if let Err(error) = transfer {
+                        self.status = MachineStatus::ExitedErr(error);
+                        return;
+                    }
+                }
+                self.state.return_data = sub.state.out.clone();
+                // Only the returned bytes are copied, and the rest of
+                // the output region is left as it is.
+                let out_len = min(out_len, M256::from(sub.state.out.len()));
                 copy_into_memory(&mut self.state.memory, sub.state.out.as_slice(),
                                  out_start, M256::zero(), out_len);
             },
-            MachineStatus::ExitedErr(_) => {
-                // self.state.used_gas = self.state.used_gas + sub.state.used_gas;
+            MachineStatus::ExitedErr(error) => {
+                // Only a reverted call leaves return data, and there
+                // is no REVERT.
+                self.state.return_data = Vec::new();
+                self.state.account_state.merge_touched_storage(&sub.state.account_state);
+                self.state.account_state.merge_commitments(&sub.state.account_state);
+                // As with `apply_create`, the gas given to a sub
+                // runtime that overflowed the call stack is returned.
+                match error {
+                    MachineError::CallstackOverflow => (),
+                    _ => self.state.used_gas = self.state.used_gas + sub.state.context.gas_limit,
+                }
                 self.state.stack.pop().unwrap();
-                self.state.stack.push(M256::from(1u64)).unwrap();
+                self.state.stack.push(M256::zero()).unwrap();
             },
-            _ => panic!(),
+            _ => unreachable!(),
         }
     }
 
@@ -241,20 +653,39 @@ impl<M: Memory + Default, S: Storage + Default + Clone> Machine<M, S> {
     /// errors.
     pub fn check(&self) -> Result<(), EvalError> {
         let instruction = self.pc.peek()?;
+        check_opcode_fork(self.pc.peek_opcode()?, &self.state.patch)?;
         check_opcode(instruction, &self.state).and_then(|v| {
             match v {
                 None => Ok(()),
                 Some(ControlCheck::Jump(dest)) => {
-                    if dest <= M256::from(usize::max_value()) && self.pc.is_valid(dest.into()) {
-                        Ok(())
-                    } else {
-                        Err(EvalError::Machine(MachineError::PC(PCError::BadJumpDest)))
+                    match checked_usize(dest) {
+                        Some(dest) if self.pc.is_valid(dest) => Ok(()),
+                        _ => Err(EvalError::Machine(MachineError::PC(PCError::BadJumpDest))),
                     }
                 }
             }
         })
     }
 
+    /// Returns the information that needs to be committed before the
+    /// next instruction can run, without mutating the runtime. This
+    /// is best-effort: only the next instruction is inspected, and it
+    /// may require more once the returned information is committed.
+    pub fn requirements(&self) -> Vec<RequireError> {
+        match self.status {
+            MachineStatus::Running => (),
+            _ => return Vec::new(),
+        }
+        if self.pc.is_end() {
+            return Vec::new();
+        }
+
+        match self.check() {
+            Err(EvalError::Require(error)) => vec![error],
+            _ => Vec::new(),
+        }
+    }
+
     /// Step an instruction in the PC. The eval result is refected by
     /// the runtime status, and it will only return an error if
     /// there're accounts or blockhashes to be committed to this
@@ -266,16 +697,44 @@ impl<M: Memory + Default, S: Storage + Default + Clone> Machine<M, S> {
             _ => panic!(),
         }
 
-        if self.state.depth >= 2 {
+        if self.state.depth >= self.state.patch.callstack_limit {
             self.status = MachineStatus::ExitedErr(MachineError::CallstackOverflow);
             return Ok(());
         }
 
+        // Precompiled contracts have no code of their own.
+        if self.pc.is_end() && self.pc.position() == 0 && !self.state.context.is_create &&
+            is_precompiled(&self.state.patch, self.state.context.address)
+        {
+            match precompiled_gas(&self.state.patch.gas, self.state.context.address,
+                                  self.state.context.data.len()) {
+                Some(gas) => self.step_precompiled(gas),
+                // Rather than succeed for free, fail and consume all
+                // the gas.
+                None => {
+                    self.state.used_gas = self.state.context.gas_limit;
+                    self.status = MachineStatus::ExitedErr(MachineError::UnimplementedPrecompiled);
+                },
+            }
+            return Ok(());
+        }
+
         if self.pc.is_end() {
             self.status = MachineStatus::ExitedOk;
             return Ok(());
         }
 
+        let opcode = self.pc.peek_opcode().unwrap();
+        if Opcode::from(opcode) == Opcode::INVALID {
+            let handler = self.custom_opcodes.clone();
+            match handler {
+                Some(ref handler) if handler.handles(opcode) => {
+                    return self.step_custom(&**handler, opcode);
+                },
+                _ => (),
+            }
+        }
+
         match self.check() {
             Ok(()) => (),
             Err(EvalError::Machine(error)) => {
@@ -290,11 +749,15 @@ impl<M: Memory + Default, S: Storage + Default + Clone> Machine<M, S> {
         let instruction = self.pc.peek().unwrap();
         let position = self.pc.position();
         let memory_cost = memory_cost(instruction, &self.state);
-        let memory_gas = memory_gas(memory_cost);
+        let memory_gas = memory_gas(&self.state.patch.gas, memory_cost);
         let gas_cost = gas_cost(instruction, &self.state);
         let gas_stipend = gas_stipend(instruction, &self.state);
         let gas_refund = gas_refund(instruction, &self.state);
-        let after_gas = self.state.context.gas_limit - memory_gas - self.state.used_gas - gas_cost + gas_stipend;
+        let after_gas = if self.state.patch.ignore_gas {
+            self.state.available_gas()
+        } else {
+            self.state.context.gas_limit - memory_gas - self.state.used_gas - gas_cost + gas_stipend
+        };
 
         match extra_check_opcode(instruction, &self.state, gas_stipend, after_gas) {
             Ok(()) => (),
@@ -307,9 +770,31 @@ impl<M: Memory + Default, S: Storage + Default + Clone> Machine<M, S> {
             },
         }
 
-        if self.state.context.gas_limit < memory_gas + self.state.used_gas + gas_cost - gas_stipend {
-            self.status = MachineStatus::ExitedErr(MachineError::EmptyGas);
-            return Ok(());
+        match self.state.patch.memory_limit {
+            Some(limit) => {
+                if (self.state.outer_memory_cost + memory_cost) * Gas::from(32u64) > Gas::from(limit) {
+                    self.status = MachineStatus::ExitedErr(MachineError::MemoryLimitExceeded);
+                    return Ok(());
+                }
+            },
+            None => (),
+        }
+
+        if !self.state.patch.ignore_gas {
+            if self.state.context.gas_limit < memory_gas + self.state.used_gas {
+                self.status = MachineStatus::ExitedErr(MachineError::EmptyMemoryGas);
+                return Ok(());
+            }
+            if self.state.context.gas_limit < memory_gas + self.state.used_gas + gas_cost - gas_stipend {
+                self.status = MachineStatus::ExitedErr(MachineError::EmptyGas);
+                return Ok(());
+            }
+        }
+
+        self.record_step();
+
+        if self.state.patch.berlin() {
+            self.state.mark_accessed(instruction);
         }
 
         let instruction = self.pc.read().unwrap();
@@ -317,15 +802,126 @@ impl<M: Memory + Default, S: Storage + Default + Clone> Machine<M, S> {
                                 &mut self.state, gas_stipend, after_gas);
 
         self.state.used_gas = self.state.used_gas + gas_cost - gas_stipend;
+        if memory_cost > self.state.memory_cost {
+            match self.memory_growths {
+                Some(ref mut growths) => growths.push(MemoryGrowth {
+                    position: position,
+                    old_size: self.state.memory_cost * Gas::from(32u64),
+                    new_size: memory_cost * Gas::from(32u64),
+                }),
+                None => (),
+            }
+        }
         self.state.memory_cost = memory_cost;
         self.state.refunded_gas = self.state.refunded_gas + gas_refund;
 
+        self.apply_control(result)
+    }
+
+    /// Step until this runtime exits or invokes a sub runtime. A
+    /// `RequireError` is returned as from `step`, after which the
+    /// runtime resumes once the information is committed.
+    pub fn run_to_invoke(&mut self) -> Result<(), RequireError> {
+        loop {
+            match self.status {
+                MachineStatus::Running => (),
+                _ => return Ok(()),
+            }
+            self.step()?;
+        }
+    }
+
+    /// Run the precompiled contract at the context address as a
+    /// single step. It fails, consuming all its gas, if the gas
+    /// limit does not cover its cost.
+    fn step_precompiled(&mut self, gas: Gas) {
+        if !self.state.patch.ignore_gas && gas > self.state.context.gas_limit {
+            self.state.used_gas = self.state.context.gas_limit;
+            self.status = MachineStatus::ExitedErr(MachineError::EmptyGas);
+            return;
+        }
+
+        self.state.out = run_precompiled(self.state.context.address, &self.state.context.data);
+        self.state.used_gas = gas;
+        self.status = MachineStatus::ExitedOk;
+    }
+
+    /// Record the opcode about to run in the trace and the opcode
+    /// counts, if they are enabled. Custom opcodes are counted as
+    /// `Opcode::INVALID`.
+    fn record_step(&mut self) {
+        let position = self.pc.position();
+        let opcode = self.pc.peek_opcode().unwrap();
+
+        match self.trace {
+            Some(ref mut trace) => {
+                trace.push(TraceStep {
+                    position: position,
+                    opcode: opcode,
+                    gas: self.state.available_gas(),
+                    stack_len: self.state.stack.len(),
+                    memory_cost: self.state.memory_cost,
+                });
+            },
+            None => (),
+        }
+
+        match self.opcode_counts {
+            Some(ref mut counts) => {
+                *counts.entry(Opcode::from(opcode)).or_insert(0) += 1;
+            },
+            None => (),
+        }
+    }
+
+    /// Step an opcode run by the registered custom opcode handler.
+    fn step_custom(&mut self, handler: &dyn CustomOpcode<M, S>, opcode: u8) -> Result<(), RequireError> {
+        match handler.check(opcode, &self.state) {
+            Ok(()) => (),
+            Err(EvalError::Machine(error)) => {
+                self.status = MachineStatus::ExitedErr(error);
+                return Ok(());
+            },
+            Err(EvalError::Require(error)) => {
+                return Err(error);
+            },
+        }
+
+        let gas_cost = handler.gas_cost(opcode, &self.state);
+        if !self.state.patch.ignore_gas && self.state.available_gas() < gas_cost {
+            self.status = MachineStatus::ExitedErr(MachineError::EmptyGas);
+            return Ok(());
+        }
+        let after_gas = if self.state.patch.ignore_gas {
+            self.state.available_gas()
+        } else {
+            self.state.available_gas() - gas_cost
+        };
+
+        self.record_step();
+        self.pc.skip().unwrap();
+        let result = handler.run(opcode, &mut self.state, Gas::zero(), after_gas);
+        self.state.used_gas = self.state.used_gas + gas_cost;
+
+        self.apply_control(result)
+    }
+
+    fn apply_control(&mut self, result: Option<Control>) -> Result<(), RequireError> {
         match result {
             None => Ok(()),
             Some(Control::Jump(dest)) => {
                 self.pc.jump(dest.into()).unwrap();
                 Ok(())
             },
+            Some(Control::InvokeCall(_, _)) | Some(Control::InvokeCreate(_))
+                if self.state.patch.sub_call_placeholder.is_some() =>
+            {
+                let placeholder = self.state.patch.sub_call_placeholder.unwrap();
+                self.state.return_data = Vec::new();
+                self.state.stack.pop().unwrap();
+                self.state.stack.push(placeholder).unwrap();
+                Ok(())
+            },
             Some(Control::InvokeCall(context, (from, len))) => {
                 self.status = MachineStatus::InvokeCall(context, (from, len));
                 Ok(())
@@ -341,13 +937,2692 @@ impl<M: Memory + Default, S: Storage + Default + Clone> Machine<M, S> {
         }
     }
 
+    /// Register a handler for opcode bytes outside of the instruction
+    /// set. Sub runtimes derived afterwards use the same handler.
+    pub fn set_custom_opcodes(&mut self, handler: Arc<dyn CustomOpcode<M, S>>) {
+        self.custom_opcodes = Some(handler);
+    }
+
+    /// Start recording a compact trace of every executed
+    /// instruction. Sub runtimes derived afterwards record their own
+    /// traces.
+    pub fn enable_trace(&mut self) {
+        if self.trace.is_none() {
+            self.trace = Some(Vec::new());
+        }
+    }
+
+    /// Get the recorded trace. Empty if tracing is not enabled.
+    pub fn trace(&self) -> &[TraceStep] {
+        match self.trace {
+            Some(ref trace) => trace.as_slice(),
+            None => &[],
+        }
+    }
+
+    /// Start counting executed instructions per opcode. Sub runtimes
+    /// derived afterwards keep their own counts.
+    pub fn enable_opcode_counts(&mut self) {
+        if self.opcode_counts.is_none() {
+            self.opcode_counts = Some(Map::new());
+        }
+    }
+
+    /// Get how many times an opcode was executed. Always zero if
+    /// counting is not enabled.
+    pub fn opcode_count(&self, opcode: Opcode) -> usize {
+        match self.opcode_counts {
+            Some(ref counts) => counts.get(&opcode).cloned().unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    /// Get the counts of all executed opcodes, if counting is
+    /// enabled.
+    pub fn opcode_counts(&self) -> Option<&Map<Opcode, usize>> {
+        self.opcode_counts.as_ref()
+    }
+
+    /// Start recording every growth of the active memory. Sub
+    /// runtimes derived afterwards record their own.
+    pub fn enable_memory_growths(&mut self) {
+        if self.memory_growths.is_none() {
+            self.memory_growths = Some(Vec::new());
+        }
+    }
+
+    /// Get the recorded memory growths. Empty if recording is not
+    /// enabled.
+    pub fn memory_growths(&self) -> &[MemoryGrowth] {
+        match self.memory_growths {
+            Some(ref growths) => growths.as_slice(),
+            None => &[],
+        }
+    }
+
+    /// Iterate over the execution of this runtime. Each item is the
+    /// result of one `step`. On a `RequireError` the iterator pauses,
+    /// and resumes on the next call after the caller has committed
+    /// the required information through `Steps`. It ends once the
+    /// runtime is no longer running.
+    pub fn steps<'a>(&'a mut self) -> Steps<'a, M, S> {
+        Steps { machine: self }
+    }
+
+    /// Get the program counter of the runtime.
+    pub fn pc(&self) -> &PC {
+        &self.pc
+    }
+
     /// Get the runtime state.
     pub fn state(&self) -> &State<M, S> {
         &self.state
     }
 
+    /// Consume the runtime and return its state, so that logs and
+    /// account changes can be moved out without cloning. Panics if
+    /// the runtime has not exited yet.
+    pub fn into_state(self) -> State<M, S> {
+        match self.status {
+            MachineStatus::ExitedOk | MachineStatus::ExitedErr(_) => self.state,
+            _ => panic!(),
+        }
+    }
+
+    /// Bytes of the active memory of this runtime, up to `MSIZE`.
+    pub fn memory_dump(&self) -> Vec<u8> {
+        let len = (self.state.memory_cost * Gas::from(32u64)).try_into_usize()
+            .unwrap_or(usize::max_value());
+        self.state.memory.slice(0, len).into_owned()
+    }
+
     /// Get the current runtime status.
     pub fn status(&self) -> MachineStatus {
         self.status.clone()
     }
+
+    /// Get the context of the sub runtime this runtime is invoking,
+    /// to be passed to `derive`. Returns `None` unless the status is
+    /// `InvokeCall` or `InvokeCreate`.
+    pub fn pending_invoke(&self) -> Option<&Context> {
+        match self.status {
+            MachineStatus::InvokeCall(ref context, _) |
+            MachineStatus::InvokeCreate(ref context) => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Continue without running the pending sub runtime. The result
+    /// pushed by the invoking instruction is kept, and the sub
+    /// runtime uses no gas and transfers no value. Does nothing
+    /// unless the status is `InvokeCall` or `InvokeCreate`.
+    pub fn skip_invoke(&mut self) {
+        if self.pending_invoke().is_some() {
+            self.status = MachineStatus::Running;
+        }
+    }
+
+    /// Gas the next instruction would be charged if it were executed
+    /// now, including memory expansion and net of the stipend of a
+    /// value transfer. The gas used by a sub runtime is not included.
+    ///
+    /// The cost of variable-cost instructions, such as `SSTORE`,
+    /// `SHA3` or the `CALL` family, depends on the current stack and
+    /// state, so this returns the same errors as `check`, including
+    /// any `RequireError`.
+    pub fn instruction_gas_cost(&self) -> Result<Gas, EvalError> {
+        self.check()?;
+        let instruction = self.pc.peek()?;
+        let memory_gas = memory_gas(&self.state.patch.gas, memory_cost(instruction, &self.state));
+        Ok(memory_gas - self.state.memory_gas() + gas_cost(instruction, &self.state) -
+           gas_stipend(instruction, &self.state))
+    }
+
+    /// Gas refunded by this runtime and its sub runtimes so far,
+    /// before the refund cap is applied.
+    pub fn refunded_gas(&self) -> Gas {
+        self.state.refunded_gas
+    }
+
+    /// Gas used by this runtime after refunds. The refund is capped at
+    /// `1 / max_refund_quotient` of the used gas. The intrinsic gas of
+    /// the transaction is not included.
+    pub fn net_used_gas(&self) -> Gas {
+        let used_gas = self.state.total_used_gas();
+        let max_refund = used_gas / Gas::from(self.state.patch.gas.max_refund_quotient);
+        used_gas - min(self.state.refunded_gas, max_refund)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use utils::collections::Map;
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use utils::bigint::{M256, U256};
+    use utils::gas::Gas;
+    use utils::address::Address;
+    use utils::opcode::Opcode;
+    use utils::asm::assemble;
+    use utils::trie::trie_root;
+    use utils::read_hex;
+    use vm::{Context, Log, BlockHeader, Patch, Fork, GasSchedule, AccountCommitment, SeqMemory, HashMapStorage,
+             TRACE_STEP_LEN, serialize_trace, EMPTY_CODE_HASH, SeqVM, Storage, Account, AccountChange,
+             Memory, State, Control, CustomOpcode};
+    use vm::errors::{RequireError, MachineError, PCError, EvalError, VMError, StackError, ApplyError,
+                     CommitError};
+    use vm::{run_bytecode, VMStatus};
+    use vm::{Instruction, PC};
+    use super::{Machine, MachineStatus, SubCall, FrameReturn, MemoryGrowth, AccountState};
+    use super::check::{check_opcode, check_opcode_fork};
+    use super::cost::{gas_cost, intrinsic_gas};
+    use super::run::create_address;
+
+    type SeqMachine = Machine<SeqMemory, HashMapStorage>;
+
+    fn address(v: u64) -> Address {
+        Address::from(M256::from(v))
+    }
+
+    fn context(address: Address, caller: Address, origin: Address, code: Vec<u8>) -> Context {
+        Context {
+            address: address,
+            caller: caller,
+            code: code,
+            data: Vec::new(),
+            gas_limit: Gas::from(100000u64),
+            gas_price: Gas::zero(),
+            origin: origin,
+            value: U256::zero(),
+            is_static: false,
+            is_create: false,
+            blob_versioned_hashes: Vec::new(),
+        }
+    }
+
+    fn block() -> BlockHeader {
+        BlockHeader {
+            coinbase: Address::default(),
+            timestamp: M256::zero(),
+            number: M256::zero(),
+            difficulty: M256::zero(),
+            gas_limit: Gas::from(1000000u64),
+            base_fee: Gas::zero(),
+            blob_base_fee: Gas::zero(),
+        }
+    }
+
+    fn commit(machine: &mut SeqMachine, address: Address, code: Vec<u8>) {
+        machine.commit_account(AccountCommitment::Full {
+            nonce: M256::zero(),
+            address: address,
+            balance: U256::from(1000000u64),
+            storage: HashMapStorage::default(),
+            code: code,
+        }).unwrap();
+    }
+
+    /// Step the machine until it is no longer running, committing
+    /// empty accounts whenever one is required.
+    fn run(machine: &mut SeqMachine) {
+        loop {
+            match machine.status() {
+                MachineStatus::Running => (),
+                _ => return,
+            }
+            match machine.step() {
+                Ok(()) => (),
+                Err(RequireError::Account(address)) |
+                Err(RequireError::AccountCode(address)) => commit(machine, address, Vec::new()),
+                Err(RequireError::Blockhash(_)) => panic!(),
+            }
+        }
+    }
+
+    /// Fire the VM, committing accounts with the given codes (or empty
+    /// code) whenever one is required.
+    fn fire(vm: &mut SeqVM, codes: &[(Address, Vec<u8>)]) {
+        loop {
+            match vm.fire() {
+                Ok(()) => return,
+                Err(RequireError::Account(address)) |
+                Err(RequireError::AccountCode(address)) => {
+                    let code = codes.iter().find(|&&(a, _)| a == address)
+                        .map(|&(_, ref code)| code.clone()).unwrap_or(Vec::new());
+                    vm.commit_account(AccountCommitment::Full {
+                        nonce: M256::zero(),
+                        address: address,
+                        balance: U256::from(1000000u64),
+                        storage: HashMapStorage::default(),
+                        code: code,
+                    }).unwrap();
+                },
+                Err(RequireError::Blockhash(_)) => panic!(),
+            }
+        }
+    }
+
+    /// Read a storage value of a full account returned by the VM.
+    fn storage(vm: &SeqVM, address: Address, index: u64) -> M256 {
+        for account in vm.accounts() {
+            match account {
+                &Account::Full { address: a, ref storage, .. } if a == address => {
+                    return storage.read(M256::from(index));
+                },
+                _ => (),
+            }
+        }
+        panic!()
+    }
+
+    fn call_code(to: u8) -> Vec<u8> {
+        assemble(&format!("PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 {} PUSH2 0x1000 CALL", to)).unwrap()
+    }
+
+    #[test]
+    fn origin_constant_across_call_frames() {
+        let origin = address(1);
+        let first = address(0x30);
+        let second = address(0x31);
+        let third = address(0x32);
+        // CALL the third address with 0x100 gas, as the second frame
+        // only has the 0x1000 forwarded to it.
+        let second_code = assemble("PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 50 PUSH2 0x100 CALL").unwrap();
+
+        let mut machine = SeqMachine::new(context(first, origin, origin, call_code(0x31)),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        commit(&mut machine, first, call_code(0x31));
+        commit(&mut machine, second, second_code);
+        commit(&mut machine, third, Vec::new());
+        run(&mut machine);
+        let sub_context = machine.pending_invoke().unwrap().clone();
+        assert_eq!(sub_context.origin, origin);
+        assert_eq!(sub_context.caller, first);
+        assert_eq!(sub_context.address, second);
+
+        let mut sub = machine.derive(sub_context);
+        run(&mut sub);
+        let sub_sub_context = sub.pending_invoke().unwrap().clone();
+        assert_eq!(sub_sub_context.origin, origin);
+        assert_eq!(sub_sub_context.caller, second);
+        assert_eq!(sub_sub_context.address, third);
+
+        let mut sub_sub = sub.derive(sub_sub_context);
+        run(&mut sub_sub);
+        sub.apply_sub(sub_sub).unwrap();
+        run(&mut sub);
+        machine.apply_sub(sub).unwrap();
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::from(1u64));
+    }
+
+    #[test]
+    fn jumpi_validates_dest_only_when_taken() {
+        let origin = address(1);
+        let owner = address(2);
+
+        let mut machine = SeqMachine::new(context(owner, origin, origin,
+                                                  assemble("PUSH1 0 PUSH1 16 JUMPI STOP").unwrap()),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+
+        let mut machine = SeqMachine::new(context(owner, origin, origin,
+                                                  assemble("PUSH1 1 PUSH1 16 JUMPI STOP").unwrap()),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedErr(MachineError::PC(PCError::BadJumpDest)) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn msize_counts_active_words_in_bytes() {
+        let origin = address(1);
+        let owner = address(2);
+
+        let mut machine = SeqMachine::new(context(owner, origin, origin,
+                                                  assemble("PUSH1 1 PUSH1 0 MSTORE MSIZE").unwrap()),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        run(&mut machine);
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::from(32u64));
+
+        let mut machine = SeqMachine::new(context(owner, origin, origin,
+                                                  assemble("PUSH1 1 PUSH1 32 MSTORE MSIZE").unwrap()),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        run(&mut machine);
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::from(64u64));
+    }
+
+    #[test]
+    fn difficulty_reads_prevrandao_after_merge() {
+        let origin = address(1);
+        let owner = address(2);
+        let header = block().with_prevrandao(M256::from(0x1234u64));
+
+        for patch in vec![Patch::new(Fork::Frontier), Patch::new(Fork::Merge)] {
+            let mut machine = SeqMachine::new(context(owner, origin, origin, assemble("DIFFICULTY").unwrap()),
+                                              header.clone(), patch, 1);
+            run(&mut machine);
+            assert_eq!(machine.state().stack.peek(0).unwrap(), M256::from(0x1234u64));
+        }
+    }
+
+    #[test]
+    fn log_data_limit() {
+        let origin = address(1);
+        let owner = address(2);
+        let mut patch = Patch::new(Fork::Frontier);
+        patch.log_data_limit = Some(48);
+
+        let code = assemble("PUSH1 32 PUSH1 0 LOG0 PUSH1 32 PUSH1 0 LOG0").unwrap();
+        let mut machine = SeqMachine::new(context(owner, origin, origin, code),
+                                          block(), patch, 1);
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedErr(MachineError::LogDataLimitExceeded) => (),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().logs.len(), 1);
+    }
+
+    #[test]
+    fn memory_limit() {
+        let origin = address(1);
+        let owner = address(2);
+        let mut patch = Patch::new(Fork::Frontier);
+        patch.memory_limit = Some(64);
+
+        let code = assemble("PUSH1 1 PUSH1 32 MSTORE PUSH1 1 PUSH1 64 MSTORE").unwrap();
+        let mut machine = SeqMachine::new(context(owner, origin, origin, code),
+                                          block(), patch, 1);
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedErr(MachineError::MemoryLimitExceeded) => (),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().memory_cost, Gas::from(2u64));
+    }
+
+    #[test]
+    fn trace_is_deterministic() {
+        let origin = address(1);
+        let owner = address(2);
+        let code = assemble("PUSH1 1 PUSH1 32 MSTORE PUSH1 2 PUSH1 3 ADD STOP").unwrap();
+
+        let mut first = SeqMachine::new(context(owner, origin, origin, code.clone()),
+                                        block(), Patch::new(Fork::Frontier), 1);
+        first.enable_trace();
+        run(&mut first);
+        let mut second = SeqMachine::new(context(owner, origin, origin, code),
+                                         block(), Patch::new(Fork::Frontier), 1);
+        second.enable_trace();
+        run(&mut second);
+
+        assert_eq!(first.trace().len(), 7);
+        assert_eq!(first.trace(), second.trace());
+        assert_eq!(first.trace()[3].opcode, 0x60);
+        assert_eq!(first.trace()[3].memory_cost, Gas::from(2u64));
+        assert_eq!(first.trace()[5].stack_len, 2);
+
+        let bytes = serialize_trace(first.trace());
+        assert_eq!(bytes.len(), 7 * TRACE_STEP_LEN);
+        assert_eq!(bytes, serialize_trace(second.trace()));
+    }
+
+    #[test]
+    fn transient_storage_shared_by_call_tree() {
+        let origin = address(1);
+        let owner = address(2);
+        let code = assemble("PUSH1 42 PUSH1 1 TSTORE
+                             PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 3 PUSH2 0xc000 CALLCODE
+                             PUSH1 2 TLOAD PUSH1 1 SSTORE").unwrap();
+        let child = assemble("PUSH1 1 TLOAD PUSH1 0 SSTORE PUSH1 7 PUSH1 2 TSTORE").unwrap();
+
+        let mut vm = SeqVM::new(context(owner, origin, origin, code),
+                                block(), Patch::new(Fork::Cancun));
+        fire(&mut vm, &[(address(3), child)]);
+        assert_eq!(storage(&vm, owner, 0), M256::from(42u64));
+        assert_eq!(storage(&vm, owner, 1), M256::from(7u64));
+    }
+
+    #[test]
+    fn transient_storage_rolled_back_on_failure() {
+        let origin = address(1);
+        let owner = address(2);
+        let code = assemble("PUSH1 42 PUSH1 1 TSTORE
+                             PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 3 PUSH2 0xc000 CALLCODE").unwrap();
+        let child = assemble("PUSH1 7 PUSH1 1 TSTORE INVALID").unwrap();
+
+        let mut vm = SeqVM::new(context(owner, origin, origin, code),
+                                block(), Patch::new(Fork::Cancun));
+        fire(&mut vm, &[(address(3), child)]);
+        assert_eq!(vm.0[0].state().transient_storage.get(&(owner, M256::from(1u64))),
+                   Some(&M256::from(42u64)));
+    }
+
+    #[test]
+    fn touched_storage_includes_reads() {
+        let owner = address(2);
+        let code = assemble("PUSH1 5 SLOAD POP PUSH1 1 PUSH1 7 SSTORE").unwrap();
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+
+        let mut touched: Vec<(Address, M256)> = machine.state().account_state
+            .touched_storage().collect();
+        touched.sort_by_key(|&(_, index)| index);
+        assert_eq!(touched, vec![(owner, M256::from(5u64)), (owner, M256::from(7u64))]);
+    }
+
+    #[test]
+    fn sload_cold_then_warm() {
+        let owner = address(2);
+        let code = assemble("PUSH1 0 SLOAD PUSH1 0 SLOAD").unwrap();
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code),
+                                          block(), Patch::new(Fork::Berlin), 1);
+        run(&mut machine);
+        assert_eq!(machine.state().used_gas, Gas::from(3u64 + 2100 + 3 + 100));
+
+        let code = assemble("PUSH1 0 SLOAD PUSH1 0 SLOAD").unwrap();
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code),
+                                          block(), Patch::new(Fork::EIP160), 1);
+        run(&mut machine);
+        assert_eq!(machine.state().used_gas, Gas::from(3u64 + 200 + 3 + 200));
+    }
+
+    #[test]
+    fn call_target_cold_then_warm() {
+        let mut code = call_code(0x0a);
+        code.extend(assemble("POP").unwrap());
+        code.extend(call_code(0x0a));
+        let mut vm = SeqVM::new(context(address(2), address(1), address(1), code),
+                                block(), Patch::new(Fork::Berlin));
+        fire(&mut vm, &[]);
+        // Fourteen pushes, one POP, then a cold and a warm CALL.
+        assert_eq!(Gas::from(100000u64) - vm.available_gas(),
+                   Gas::from(14u64 * 3 + 2 + 2600 + 100));
+
+        // Precompiled contracts start warm. SHA256 of no input costs
+        // 60.
+        let mut vm = SeqVM::new(context(address(0x1000), address(1), address(1), call_code(0x02)),
+                                block(), Patch::new(Fork::Berlin));
+        fire(&mut vm, &[]);
+        assert_eq!(Gas::from(100000u64) - vm.available_gas(),
+                   Gas::from(7u64 * 3 + 100 + 60));
+    }
+
+    #[test]
+    fn sstore_reset_cold_then_warm() {
+        let owner = address(2);
+        let code = assemble("PUSH1 0 PUSH1 0 SSTORE PUSH1 0 PUSH1 0 SSTORE").unwrap();
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code.clone()),
+                                          block(), Patch::new(Fork::Berlin), 1);
+        run(&mut machine);
+        assert_eq!(machine.state().used_gas, Gas::from(6u64 + 2100 + 2900 + 6 + 2900));
+
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code),
+                                          block(), Patch::new(Fork::Istanbul), 1);
+        run(&mut machine);
+        assert_eq!(machine.state().used_gas, Gas::from(6u64 + 5000 + 6 + 5000));
+    }
+
+    #[test]
+    fn access_list_prewarms_slot() {
+        let owner = address(2);
+        let code = assemble("PUSH1 0 SLOAD").unwrap();
+
+        let mut cold = SeqVM::new(context(owner, address(1), address(1), code.clone()),
+                                  block(), Patch::new(Fork::Berlin));
+        fire(&mut cold, &[]);
+        let mut warm = SeqVM::new(context(owner, address(1), address(1), code),
+                                  block(), Patch::new(Fork::Berlin))
+            .with_access_list(&[(owner, vec![M256::zero()])]);
+        fire(&mut warm, &[]);
+
+        assert_eq!(warm.available_gas() - cold.available_gas(), Gas::from(2100u64 - 100));
+    }
+
+    #[test]
+    fn state_diff() {
+        let owner = address(2);
+        let doomed = address(3);
+        let code = assemble("PUSH1 1 PUSH1 1 SSTORE PUSH1 2 PUSH1 2 SSTORE
+                             PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 10 PUSH1 3 PUSH2 0x1000 CALL").unwrap();
+        let doomed_code = assemble("PUSH1 4 SUICIDE").unwrap();
+        let mut vm = SeqVM::new(context(owner, address(1), address(1), code),
+                                block(), Patch::new(Fork::Frontier));
+        fire(&mut vm, &[(doomed, doomed_code)]);
+
+        let diff = vm.state_diff();
+        assert_eq!(diff.deleted, vec![doomed]);
+        let mut found = false;
+        for account in &diff.accounts {
+            match account {
+                &AccountChange::Full { address, balance, ref code, ref storage, .. } if address == owner => {
+                    assert_eq!(balance, U256::from(1000000u64 - 10));
+                    assert!(code.is_none());
+                    assert_eq!(storage, &vec![(M256::from(1u64), M256::zero(), M256::from(1u64)),
+                                              (M256::from(2u64), M256::zero(), M256::from(2u64))]);
+                    found = true;
+                },
+                account => assert!(account.address() != doomed),
+            }
+        }
+        assert!(found);
+    }
+
+    #[test]
+    fn return_charges_memory_expansion() {
+        let owner = address(2);
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1),
+                                                  assemble("PUSH1 32 PUSH1 64 RETURN").unwrap()),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        run(&mut machine);
+        assert_eq!(machine.state().memory_cost, Gas::from(3u64));
+        assert_eq!(machine.state().available_gas(), Gas::from(100000u64 - 6 - 9));
+        assert_eq!(machine.state().out, vec![0u8; 32]);
+
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1),
+                                                  assemble("PUSH1 0 PUSH2 0x1000 RETURN").unwrap()),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        run(&mut machine);
+        assert_eq!(machine.state().memory_cost, Gas::zero());
+        assert_eq!(machine.state().available_gas(), Gas::from(100000u64 - 6));
+        assert!(machine.state().out.is_empty());
+    }
+
+    #[test]
+    fn extcodesize_of_precompiled_is_zero() {
+        let extcodesize = |fork, target: u64| {
+            let code = assemble(&format!("PUSH {} EXTCODESIZE", target)).unwrap();
+            let mut machine = SeqMachine::new(context(address(2), address(1), address(1), code),
+                                              block(), Patch::new(fork), 1);
+            machine.step().unwrap();
+            machine.step().map(|()| machine.state().stack.peek(0).unwrap())
+        };
+
+        assert_eq!(extcodesize(Fork::Frontier, 1).unwrap(), M256::zero());
+        assert_eq!(extcodesize(Fork::Istanbul, 9).unwrap(), M256::zero());
+        // 0x09 is a plain account before Istanbul.
+        match extcodesize(Fork::Byzantium, 9) {
+            Err(RequireError::AccountCode(missing)) => assert_eq!(missing, address(9)),
+            _ => panic!(),
+        }
+    }
+
+    // Pushes 2^200.
+    fn push_huge() -> Vec<u8> {
+        assemble("PUSH26 0x0100000000000000000000000000000000000000000000000000").unwrap()
+    }
+
+    fn exit_error(code: Vec<u8>) -> MachineError {
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1), code),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedErr(error) => error,
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn huge_memory_offset() {
+        let mut code = assemble("PUSH1 1").unwrap();
+        code.extend(push_huge());
+        code.extend(assemble("MSTORE").unwrap());
+        match exit_error(code) { MachineError::EmptyMemoryGas => (), _ => panic!() }
+
+        let mut code = push_huge();
+        code.extend(assemble("MLOAD").unwrap());
+        match exit_error(code) { MachineError::EmptyMemoryGas => (), _ => panic!() }
+
+        let mut code = assemble("PUSH1 1 PUSH1 0").unwrap();
+        code.extend(push_huge());
+        code.extend(assemble("CALLDATACOPY").unwrap());
+        match exit_error(code) { MachineError::EmptyMemoryGas => (), _ => panic!() }
+
+        let mut code = push_huge();
+        code.extend(assemble("JUMP").unwrap());
+        match exit_error(code) { MachineError::PC(PCError::BadJumpDest) => (), _ => panic!() }
+    }
+
+    #[test]
+    fn ignore_gas() {
+        let code = assemble("PUSH2 0x3e8 JUMPDEST PUSH1 1 SWAP1 SUB DUP1 PUSH1 3 JUMPI STOP").unwrap();
+        let mut context = context(address(2), address(1), address(1), code);
+        context.gas_limit = Gas::from(10u64);
+        let mut patch = Patch::new(Fork::Frontier);
+        patch.ignore_gas = true;
+        let mut machine = SeqMachine::new(context, block(), patch, 1);
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+        assert!(machine.state().used_gas > Gas::from(1000u64 * 20));
+    }
+
+    #[test]
+    fn swap_depths() {
+        let machine = SeqMachine::new(context(address(2), address(1), address(1), Vec::new()),
+                                      block(), Patch::new(Fork::Frontier), 1);
+        for byte in 0..256usize {
+            match Opcode::from(byte as u8) {
+                Opcode::SWAP(v) => assert!(v >= 1 && v <= 16),
+                _ => (),
+            }
+        }
+        match check_opcode(Instruction::SWAP(0), machine.state()) {
+            Err(EvalError::Machine(MachineError::PC(PCError::InvalidOpcode))) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn custom_gas_schedule() {
+        let mut patch = Patch::new(Fork::Frontier);
+        patch.gas.verylow = 7;
+        patch.gas.jumpdest = 0;
+        let machine = SeqMachine::new(context(address(2), address(1), address(1), Vec::new()),
+                                      block(), patch, 1);
+        let state = machine.state();
+        assert_eq!(gas_cost(Instruction::ADD, state), Gas::from(7u64));
+        assert_eq!(gas_cost(Instruction::JUMPDEST, state), Gas::from(0u64));
+        assert_eq!(gas_cost(Instruction::MUL, state), Gas::from(5u64));
+
+        assert_eq!(GasSchedule::new(Fork::Frontier).call, 40);
+        assert_eq!(GasSchedule::new(Fork::EIP150).call, 700);
+    }
+
+    #[test]
+    fn account_access_repricing() {
+        let charged = |fork, opcode: u8| {
+            // PUSH1 0x30, opcode
+            let mut machine = SeqMachine::new(context(address(2), address(1), address(1),
+                                                      vec![0x60, 0x30, opcode]),
+                                              block(), Patch::new(fork), 1);
+            run(&mut machine);
+            match machine.status() {
+                MachineStatus::ExitedOk => (),
+                _ => panic!(),
+            }
+            machine.state().used_gas - Gas::from(3u64)
+        };
+
+        // BALANCE, EXTCODESIZE and EXTCODEHASH.
+        let cases = vec![
+            (Fork::Frontier, 20, 20, None),
+            (Fork::EIP150, 400, 700, None),
+            (Fork::Constantinople, 400, 700, Some(400)),
+            (Fork::Istanbul, 700, 700, Some(700)),
+            (Fork::Berlin, 2600, 2600, Some(2600)),
+        ];
+        for (fork, balance, extcodesize, extcodehash) in cases {
+            assert_eq!(charged(fork, 0x31), Gas::from(balance as u64));
+            assert_eq!(charged(fork, 0x3b), Gas::from(extcodesize as u64));
+            if let Some(extcodehash) = extcodehash {
+                assert_eq!(charged(fork, 0x3f), Gas::from(extcodehash as u64));
+            }
+        }
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let owner = address(2);
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1),
+                                                  assemble("PUSH1 1 PUSH1 1 SSTORE").unwrap()),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        run(&mut machine);
+        let used_gas = machine.state().used_gas;
+        let snapshot = machine.state().snapshot();
+
+        machine.state.account_state.storage_mut(owner).unwrap()
+            .write(M256::from(1u64), M256::from(2u64)).unwrap();
+        machine.state.logs.push(Log { address: owner, data: Vec::new(), topics: Vec::new() });
+        machine.state.used_gas = used_gas + Gas::from(100u64);
+
+        machine.state.restore(snapshot);
+        assert_eq!(machine.state().account_state.storage(owner).unwrap().read(M256::from(1u64)),
+                   M256::from(1u64));
+        assert!(machine.state().logs.is_empty());
+        assert_eq!(machine.state().used_gas, used_gas);
+    }
+
+    #[test]
+    fn callcode_value_stays_with_caller() {
+        let owner = address(2);
+        let library = address(3);
+        let code = assemble("PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 10 PUSH1 3 PUSH2 0xc000 CALLCODE").unwrap();
+        let library_code = assemble("PUSH1 1 PUSH1 1 SSTORE").unwrap();
+        let mut vm = SeqVM::new(context(owner, address(1), address(1), code),
+                                block(), Patch::new(Fork::Frontier));
+        fire(&mut vm, &[(library, library_code)]);
+
+        assert_eq!(storage(&vm, owner, 1), M256::from(1u64));
+        for account in vm.accounts() {
+            match account {
+                &Account::Full { address, balance, .. } if address == owner => {
+                    assert_eq!(balance, U256::from(1000000u64));
+                },
+                &Account::Full { address, ref storage, .. } if address == library => {
+                    assert_eq!(storage.read(M256::from(1u64)), M256::zero());
+                },
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    fn opcode_counts() {
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1),
+                                                  assemble("PUSH1 2 PUSH1 3 ADD
+                                                            PUSH1 4 MUL PUSH1 5 ADD").unwrap()),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        machine.enable_opcode_counts();
+        run(&mut machine);
+        assert_eq!(machine.opcode_count(Opcode::ADD), 2);
+        assert_eq!(machine.opcode_count(Opcode::MUL), 1);
+        assert_eq!(machine.opcode_count(Opcode::PUSH(1)), 4);
+        assert_eq!(machine.opcode_count(Opcode::SUB), 0);
+    }
+
+    #[test]
+    fn gas_remaining_caps_refund() {
+        let owner = address(2);
+        let mut storage = Map::new();
+        storage.insert(M256::zero(), M256::from(1u64));
+        let mut vm = SeqVM::new(context(owner, address(1), address(1),
+                                        assemble("PUSH1 0 PUSH1 0 SSTORE").unwrap()),
+                                block(), Patch::new(Fork::Frontier));
+        vm.commit_account(AccountCommitment::Full {
+            nonce: M256::zero(),
+            address: owner,
+            balance: U256::zero(),
+            storage: HashMapStorage::from(storage),
+            code: assemble("PUSH1 0 PUSH1 0 SSTORE").unwrap(),
+        }).unwrap();
+        fire(&mut vm, &[]);
+
+        assert_eq!(vm.refunded_gas(), Gas::from(15000u64));
+        // The refund is capped at (21000 + 5006) / 2.
+        assert_eq!(vm.gas_remaining(), Gas::from(100000u64 - 5006 + 13003));
+    }
+
+    #[test]
+    fn require_loop_for_call_target_code() {
+        let owner = address(2);
+        let target = address(3);
+        let target_code = assemble("PUSH1 1 STOP").unwrap();
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), call_code(3)),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        let mut required = Vec::new();
+        loop {
+            match machine.status() {
+                MachineStatus::Running => (),
+                _ => break,
+            }
+            match machine.step() {
+                Ok(()) => (),
+                Err(RequireError::Account(address)) => {
+                    required.push(address);
+                    let code = if address == target { target_code.clone() } else { Vec::new() };
+                    commit(&mut machine, address, code);
+                },
+                Err(_) => panic!(),
+            }
+        }
+
+        assert_eq!(required, vec![owner, target]);
+        match machine.status() {
+            MachineStatus::InvokeCall(context, _) => assert_eq!(context.code, target_code),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn extcodecopy_gas() {
+        let code = assemble("PUSH1 100 PUSH1 0 PUSH1 0 PUSH1 3 EXTCODECOPY").unwrap();
+        for &(fork, base) in &[(Fork::Frontier, 20u64), (Fork::EIP150, 700u64)] {
+            let mut machine = SeqMachine::new(context(address(2), address(1), address(1), code.clone()),
+                                              block(), Patch::new(fork), 1);
+            run(&mut machine);
+            // Four pushes, the base cost, four copied words and four
+            // words of memory.
+            assert_eq!(machine.state().used_gas, Gas::from(4 * 3 + base + 4 * 3));
+            assert_eq!(machine.state().memory_gas(), Gas::from(4u64 * 3));
+        }
+    }
+
+    #[test]
+    fn suicide_in_static_call_fails() {
+        let owner = address(2);
+        let doomed = address(3);
+        let code = assemble("PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 3 PUSH2 0xc000 STATICCALL").unwrap();
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code),
+                                          block(), Patch::new(Fork::Byzantium), 1);
+        commit(&mut machine, doomed, assemble("PUSH1 4 SUICIDE").unwrap());
+        run(&mut machine);
+        let sub_context = match machine.status() {
+            MachineStatus::InvokeCall(context, _) => context,
+            _ => panic!(),
+        };
+        assert!(sub_context.is_static);
+
+        let mut sub = machine.derive(sub_context);
+        run(&mut sub);
+        match sub.status() {
+            MachineStatus::ExitedErr(MachineError::StaticViolation) => (),
+            _ => panic!(),
+        }
+        assert_eq!(sub.state().account_state.balance(doomed).unwrap(), U256::from(1000000u64));
+
+        machine.apply_sub(sub).unwrap();
+        assert_eq!(machine.state().account_state.balance(doomed).unwrap(), U256::from(1000000u64));
+        assert_eq!(machine.state().account_state.code(doomed).unwrap(), &[0x60, 0x04, 0xff]);
+    }
+
+    #[test]
+    fn extcodehash_of_empty_code() {
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1),
+                                                  assemble("PUSH1 3 EXTCODEHASH PUSH1 4 EXTCODEHASH").unwrap()),
+                                          block(), Patch::new(Fork::Constantinople), 1);
+        // Account 3 exists without code. Account 4 does not exist,
+        // which is committed as an empty account.
+        commit(&mut machine, address(3), Vec::new());
+        machine.commit_account(AccountCommitment::Full {
+            nonce: M256::zero(),
+            address: address(4),
+            balance: U256::zero(),
+            storage: HashMapStorage::default(),
+            code: Vec::new(),
+        }).unwrap();
+        run(&mut machine);
+
+        assert_eq!(machine.state().stack.peek(1).unwrap(), M256::from(EMPTY_CODE_HASH.as_ref()));
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::zero());
+    }
+
+    #[test]
+    fn requirements_of_pending_balance() {
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1),
+                                                  assemble("PUSH1 3 BALANCE").unwrap()),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        assert!(machine.requirements().is_empty());
+        machine.step().unwrap();
+        match machine.requirements().as_slice() {
+            &[RequireError::Account(required)] => assert_eq!(required, address(3)),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().stack.len(), 1);
+    }
+
+    #[test]
+    fn self_call_shares_storage() {
+        let owner = address(2);
+        let code = assemble("ADDRESS CALLER EQ PUSH1 27 JUMPI
+                             PUSH1 1 PUSH1 1 SSTORE
+                             PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 ADDRESS PUSH2 0xc000 CALL STOP
+                             JUMPDEST PUSH1 1 SLOAD PUSH1 2 SSTORE STOP").unwrap();
+        let mut vm = SeqVM::new(context(owner, address(1), address(1), code.clone()),
+                                block(), Patch::new(Fork::Frontier));
+        fire(&mut vm, &[(owner, code)]);
+
+        assert_eq!(storage(&vm, owner, 1), M256::from(1u64));
+        assert_eq!(storage(&vm, owner, 2), M256::from(1u64));
+    }
+
+    #[test]
+    fn basefee() {
+        let header = block().with_base_fee(Gas::from(7u64));
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1),
+                                                  assemble("BASEFEE").unwrap()),
+                                          header.clone(), Patch::new(Fork::London), 1);
+        run(&mut machine);
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::from(7u64));
+
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1),
+                                                  assemble("BASEFEE").unwrap()),
+                                          header, Patch::new(Fork::Berlin), 1);
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedErr(MachineError::PC(PCError::InvalidOpcode)) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn transient_storage_in_static_context() {
+        let mut context = context(address(2), address(1), address(1), Vec::new());
+        context.is_static = true;
+
+        context.code = assemble("PUSH1 1 TLOAD").unwrap();
+        let mut machine = SeqMachine::new(context.clone(), block(), Patch::new(Fork::Cancun), 1);
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().used_gas, Gas::from(3u64 + 100));
+
+        context.code = assemble("PUSH1 1 PUSH1 1 TSTORE").unwrap();
+        let mut machine = SeqMachine::new(context, block(), Patch::new(Fork::Cancun), 1);
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedErr(MachineError::StaticViolation) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn memory_slice_pads_with_zeros() {
+        let mut memory = SeqMemory::default();
+        memory.write_raw(M256::from(1u64), 0xaa).unwrap();
+        memory.write_raw(M256::from(2u64), 0xbb).unwrap();
+        match memory.slice(1, 2) {
+            Cow::Borrowed(slice) => assert_eq!(slice, &[0xaa, 0xbb]),
+            Cow::Owned(_) => panic!(),
+        }
+        assert_eq!(memory.slice(2, 3).as_ref(), &[0xbb, 0x00, 0x00]);
+        assert_eq!(memory.slice(8, 2).as_ref(), &[0x00, 0x00]);
+    }
+
+    #[test]
+    fn empty_gas_for_memory_and_execution() {
+        let code = assemble("PUSH1 1 PUSH3 0xffffff MSTORE").unwrap();
+        match exit_error(code) { MachineError::EmptyMemoryGas => (), _ => panic!() }
+
+        let code = assemble("JUMPDEST PUSH1 0 JUMP").unwrap();
+        match exit_error(code) { MachineError::EmptyGas => (), _ => panic!() }
+    }
+
+    #[test]
+    fn extcodesize_of_self_during_creation() {
+        // The init code is ADDRESS, EXTCODESIZE, CODESIZE.
+        let code = assemble("PUSH3 0x303b38 PUSH1 0 MSTORE PUSH1 3 PUSH1 29 PUSH1 0 CREATE").unwrap();
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1), code),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        run(&mut machine);
+        let sub_context = match machine.status() {
+            MachineStatus::InvokeCreate(context) => context,
+            _ => panic!(),
+        };
+        assert!(sub_context.is_create);
+        assert_eq!(sub_context.code, assemble("ADDRESS EXTCODESIZE CODESIZE").unwrap());
+
+        // The new account is never required, since it has no code yet.
+        let mut sub = machine.derive(sub_context);
+        while let MachineStatus::Running = sub.status() {
+            sub.step().unwrap();
+        }
+        match sub.status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+        assert_eq!(sub.state().stack.peek(0).unwrap(), M256::from(3u64));
+        assert_eq!(sub.state().stack.peek(1).unwrap(), M256::zero());
+    }
+
+    #[test]
+    fn create_collision() {
+        let creator = address(2);
+        let occupied = create_address(creator, M256::zero());
+        let code = assemble("PUSH1 0 PUSH1 0 PUSH1 0 CREATE").unwrap();
+        let create = |fork| {
+            let mut machine = SeqMachine::new(context(creator, address(1), address(1), code.clone()),
+                                              block(), Patch::new(fork), 0);
+            commit(&mut machine, creator, code.clone());
+            commit(&mut machine, occupied, assemble("STOP").unwrap());
+            run(&mut machine);
+            match machine.status() {
+                MachineStatus::ExitedOk => (),
+                _ => panic!(),
+            }
+            machine
+        };
+
+        // The init code never runs, and the creation consumes all the
+        // gas it would have been given.
+        let machine = create(Fork::Frontier);
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::zero());
+        assert_eq!(machine.state().total_used_gas(), Gas::from(100000u64));
+        assert_eq!(machine.state().account_state.nonce(creator).unwrap(), M256::from(1u64));
+        assert_eq!(machine.state().account_state.code(occupied).unwrap(), &[0x00]);
+
+        // From EIP150 that is all but one 64th of the gas left after
+        // the three pushes and CREATE.
+        let machine = create(Fork::EIP150);
+        let left = 100000u64 - (3 * 3 + 32000);
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::zero());
+        assert_eq!(machine.state().total_used_gas(), Gas::from(100000u64 - left / 64));
+    }
+
+    #[test]
+    fn create_forwards_all_but_one_64th() {
+        let code = assemble("PUSH1 0 PUSH1 0 PUSH1 0 CREATE").unwrap();
+        let left = 100000u64 - (3 * 3 + 32000);
+        for &(fork, forwarded) in [(Fork::Homestead, left), (Fork::EIP150, left - left / 64)].iter() {
+            let mut machine = SeqMachine::new(context(address(2), address(1), address(1), code.clone()),
+                                              block(), Patch::new(fork), 0);
+            commit(&mut machine, address(2), code.clone());
+            run(&mut machine);
+            assert_eq!(machine.pending_invoke().unwrap().gas_limit, Gas::from(forwarded));
+        }
+    }
+
+    #[test]
+    fn create_with_empty_deploy() {
+        let creator = address(2);
+        let created = create_address(creator, M256::zero());
+        // The init code is the zero byte in memory, a single STOP.
+        let code = assemble("PUSH1 1 PUSH1 0 PUSH1 0 CREATE PUSH1 0 SSTORE").unwrap();
+        let mut vm = SeqVM::new(context(creator, address(1), address(1), code),
+                                block(), Patch::new(Fork::Frontier));
+        fire(&mut vm, &[]);
+
+        match vm.status() {
+            VMStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+        assert_eq!(storage(&vm, creator, 0), created.into());
+        // Three pushes, one word of memory, CREATE, PUSH1 and SSTORE,
+        // without any code deposit.
+        assert_eq!(vm.available_gas(), Gas::from(100000u64 - (9 + 3 + 32000 + 3 + 20000)));
+        let deployed = vm.accounts().any(|account| match account {
+            &Account::Full { address, ref code, .. } => address == created && code.is_empty(),
+            _ => false,
+        });
+        assert!(deployed);
+    }
+
+    #[test]
+    fn create_charges_code_deposit() {
+        let creator = address(2);
+        let created = create_address(creator, M256::zero());
+        // CREATE from the init code PUSH1 2, PUSH1 0, RETURN, which
+        // deploys two zero bytes.
+        let code = assemble("
+            PUSH5 0x60026000f3
+            PUSH1 0
+            MSTORE
+            PUSH1 5
+            PUSH1 27
+            PUSH1 0
+            CREATE
+        ").unwrap();
+        let create = |fork, gas_limit: u64| {
+            let mut context = context(creator, address(1), address(1), code.clone());
+            context.gas_limit = Gas::from(gas_limit);
+            let mut machine = SeqMachine::new(context, block(), Patch::new(fork), 1);
+            commit(&mut machine, creator, code.clone());
+            run(&mut machine);
+            let mut sub = machine.derive(machine.pending_invoke().unwrap().clone());
+            run(&mut sub);
+            machine.apply_sub(sub).unwrap();
+            run(&mut machine);
+            machine
+        };
+        // Five pushes, MSTORE with a word of memory, CREATE, and the
+        // init code with a word of memory.
+        let used = 5 * 3 + 3 + 3 + 32000 + 3 * 3;
+
+        // Each deployed byte costs 200 gas.
+        let machine = create(Fork::Homestead, 100000);
+        assert_eq!(machine.state().stack.peek(0).unwrap(), created.into());
+        assert_eq!(machine.state().total_used_gas(), Gas::from(used + 2 * 200));
+        assert_eq!(machine.state().account_state.code(created).unwrap(), &[0x00, 0x00]);
+
+        // From Homestead a creation that cannot pay for its code fails
+        // and consumes its gas.
+        let machine = create(Fork::Homestead, used + 2 * 200 - 1);
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::zero());
+        assert_eq!(machine.state().total_used_gas(), Gas::from(used + 2 * 200 - 1));
+
+        // In Frontier it deploys no code instead.
+        let machine = create(Fork::Frontier, used + 2 * 200 - 1);
+        assert_eq!(machine.state().stack.peek(0).unwrap(), created.into());
+        assert_eq!(machine.state().total_used_gas(), Gas::from(used));
+        assert!(machine.state().account_state.code(created).unwrap().is_empty());
+    }
+
+    #[test]
+    fn create_out_of_gas() {
+        // The init code is JUMPDEST, PUSH1 0, JUMP, which never terminates.
+        let code = assemble("PUSH4 0x5b600056 PUSH1 0 MSTORE PUSH1 4 PUSH1 28 PUSH1 0 CREATE STOP").unwrap();
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1), code),
+                                          block(), Patch::new(Fork::Homestead), 1);
+        run(&mut machine);
+        let sub_context = match machine.status() {
+            MachineStatus::InvokeCreate(context) => context,
+            _ => panic!(),
+        };
+        let forwarded = sub_context.gas_limit;
+        assert!(forwarded > Gas::zero());
+        let mut sub = machine.derive(sub_context);
+        run(&mut sub);
+        match sub.status() {
+            MachineStatus::ExitedErr(MachineError::EmptyGas) => (),
+            _ => panic!(),
+        }
+
+        let available_gas = machine.state().available_gas();
+        machine.apply_sub(sub).unwrap();
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::zero());
+        assert_eq!(machine.state().available_gas(), available_gas - forwarded);
+        assert_eq!(machine.state().available_gas(), Gas::zero());
+        let created = create_address(address(2), M256::zero());
+        assert!(machine.state().account_state.code(created).unwrap().is_empty());
+
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn sha3_of_huge_region_runs_out_of_gas_first() {
+        let code = assemble("PUSH3 0x100000 PUSH1 0 SHA3").unwrap();
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1), code),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        run(&mut machine);
+        // The memory expansion is charged before the hash is computed,
+        // so both operands are still on the stack.
+        match machine.status() {
+            MachineStatus::ExitedErr(MachineError::EmptyMemoryGas) => (),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().stack.len(), 2);
+        assert_eq!(machine.state().stack.peek(1).unwrap(), M256::from(0x100000u64));
+        assert_eq!(machine.state().memory_cost, Gas::zero());
+        assert_eq!(machine.state().used_gas, Gas::from(6u64));
+    }
+
+    #[test]
+    fn constructor_arguments_after_init_code() {
+        let creator = address(2);
+        // The init code stores its argument and code size, then deploys
+        // JUMPDEST, STOP.
+        let mut init = assemble("PUSH1 32 PUSH1 31 PUSH1 0 CODECOPY PUSH1 0 MLOAD PUSH1 0 SSTORE
+                                 CODESIZE PUSH1 1 SSTORE
+                                 PUSH1 2 PUSH1 29 PUSH1 0 CODECOPY PUSH1 2 PUSH1 0 RETURN
+                                 JUMPDEST STOP").unwrap();
+        let argument: [u8; 32] = M256::from(42u64).into();
+        init.extend(argument.iter());
+        assert_eq!(init.len(), 63);
+        let mut code = assemble("PUSH1 63 PUSH1 15 PUSH1 0 CODECOPY
+                                 PUSH1 63 PUSH1 0 PUSH1 0 CREATE STOP").unwrap();
+        code.extend(init.iter());
+
+        let mut machine = SeqMachine::new(context(creator, address(1), address(1), code),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        run(&mut machine);
+        let sub_context = match machine.status() {
+            MachineStatus::InvokeCreate(context) => context,
+            _ => panic!(),
+        };
+        assert_eq!(sub_context.code, init);
+
+        let mut sub = machine.derive(sub_context);
+        run(&mut sub);
+        machine.apply_sub(sub).unwrap();
+
+        let created = create_address(creator, M256::zero());
+        let state = machine.state();
+        assert_eq!(state.account_state.code(created).unwrap(), &[0x5b, 0x00]);
+        assert_eq!(state.account_state.storage(created).unwrap().read(M256::zero()), M256::from(42u64));
+        assert_eq!(state.account_state.storage(created).unwrap().read(M256::from(1u64)), M256::from(63u64));
+    }
+
+    #[test]
+    fn sub_call_gas() {
+        let mut code = call_code(3);
+        code.extend(call_code(4));
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1), code),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        commit(&mut machine, address(3), assemble("PUSH1 1 PUSH1 2 ADD").unwrap());
+        commit(&mut machine, address(4), assemble("PUSH1 0 SLOAD").unwrap());
+
+        for _ in 0..2 {
+            run(&mut machine);
+            let sub_context = match machine.status() {
+                MachineStatus::InvokeCall(context, _) => context,
+                _ => panic!(),
+            };
+            let mut sub = machine.derive(sub_context);
+            run(&mut sub);
+            machine.apply_sub(sub).unwrap();
+        }
+        run(&mut machine);
+
+        assert_eq!(machine.state().sub_calls, vec![
+            SubCall { address: address(3), is_create: false, used_gas: Gas::from(9u64) },
+            SubCall { address: address(4), is_create: false, used_gas: Gas::from(53u64) },
+        ]);
+    }
+
+    #[test]
+    fn extreme_header_values() {
+        let max = M256::from(U256::max_value());
+        let header = block()
+            .with_coinbase(Address::from(max))
+            .with_timestamp(max)
+            .with_number(max)
+            .with_difficulty(max)
+            .with_gas_limit(Gas::from(U256::max_value()) + Gas::from(U256::max_value()))
+            .with_base_fee(Gas::from(U256::max_value()));
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1),
+                                                  assemble("COINBASE TIMESTAMP NUMBER
+                                                            DIFFICULTY GASLIMIT BASEFEE").unwrap()),
+                                          header, Patch::new(Fork::London), 1);
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().stack.peek(0).unwrap(), max);
+        assert_eq!(machine.state().stack.peek(1).unwrap(), max);
+        assert_eq!(machine.state().stack.peek(2).unwrap(), max);
+        assert_eq!(machine.state().stack.peek(3).unwrap(), max);
+        assert_eq!(machine.state().stack.peek(4).unwrap(), max);
+        assert_eq!(machine.state().stack.peek(5).unwrap(), Address::from(max).into());
+    }
+
+    #[test]
+    fn log_limit() {
+        let mut patch = Patch::new(Fork::Frontier);
+        patch.log_limit = Some(5);
+
+        let code = assemble("
+        loop:
+            JUMPDEST
+            PUSH1 0
+            PUSH1 0
+            LOG0
+            PUSH @loop
+            JUMP
+        ").unwrap();
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1), code),
+                                          block(), patch, 1);
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedErr(MachineError::LogLimitExceeded) => (),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().logs.len(), 5);
+    }
+
+    #[test]
+    fn reduced_refunds_after_london() {
+        let owner = address(2);
+        let refunded_gas = |fork: Fork, code: Vec<u8>| {
+            let mut storage = HashMapStorage::default();
+            storage.write(M256::from(1u64), M256::from(1u64)).unwrap();
+            let mut machine = SeqMachine::new(context(owner, address(1), address(1), code.clone()),
+                                              block(), Patch::new(fork), 1);
+            machine.commit_account(AccountCommitment::Full {
+                nonce: M256::zero(),
+                address: owner,
+                balance: U256::zero(),
+                storage: storage,
+                code: code,
+            }).unwrap();
+            run(&mut machine);
+            machine.state().refunded_gas
+        };
+
+        let clear = assemble("PUSH1 0 PUSH1 1 SSTORE").unwrap();
+        assert_eq!(refunded_gas(Fork::Berlin, clear.clone()), Gas::from(15000u64));
+        assert_eq!(refunded_gas(Fork::London, clear), Gas::from(4800u64));
+
+        let suicide = assemble("PUSH1 3 SUICIDE").unwrap();
+        assert_eq!(refunded_gas(Fork::Berlin, suicide.clone()), Gas::from(24000u64));
+        assert_eq!(refunded_gas(Fork::London, suicide), Gas::zero());
+
+        assert_eq!(GasSchedule::new(Fork::Berlin).max_refund_quotient, 2);
+        assert_eq!(GasSchedule::new(Fork::London).max_refund_quotient, 5);
+    }
+
+    #[test]
+    fn memory_dump() {
+        let code = assemble("
+            PUSH1 0xaa
+            PUSH1 0
+            MSTORE
+            PUSH1 0xbb
+            PUSH1 0x21
+            MSTORE8
+        ").unwrap();
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1), code),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        run(&mut machine);
+
+        let mut expected = vec![0u8; 64];
+        expected[31] = 0xaa;
+        expected[33] = 0xbb;
+        assert_eq!(machine.memory_dump(), expected);
+    }
+
+    #[test]
+    fn calldata_slice() {
+        let mut context = context(address(2), address(1), address(1), Vec::new());
+        context.data = vec![1, 2, 3, 4];
+        match context.calldata_slice(1, 2) {
+            Cow::Borrowed(slice) => assert_eq!(slice, &[2, 3]),
+            Cow::Owned(_) => panic!(),
+        }
+        assert_eq!(context.calldata_slice(2, 4).as_ref(), &[3, 4, 0, 0]);
+        assert_eq!(context.calldata_slice(6, 2).as_ref(), &[0, 0]);
+        assert_eq!(context.calldata_slice(usize::max_value(), 1).as_ref(), &[0]);
+    }
+
+    #[test]
+    fn parent_pays_child_memory_gas() {
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1), call_code(3)),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        commit(&mut machine, address(3), assemble("PUSH1 1 PUSH2 0x3e0 MSTORE").unwrap());
+        run(&mut machine);
+        let sub_context = match machine.status() {
+            MachineStatus::InvokeCall(context, _) => context,
+            _ => panic!(),
+        };
+        let mut sub = machine.derive(sub_context);
+        run(&mut sub);
+        // 32 words of memory cost 3 * 32 + 32 * 32 / 512.
+        assert_eq!(sub.state().memory_gas(), Gas::from(98u64));
+        assert_eq!(sub.state().total_used_gas(), Gas::from(3u64 + 3 + 3 + 98));
+
+        let used_gas = machine.state().used_gas;
+        machine.apply_sub(sub).unwrap();
+        assert_eq!(machine.state().used_gas, used_gas + Gas::from(107u64));
+    }
+
+    #[test]
+    fn sstore_gas_sentinel() {
+        let sstore = |patch: Patch, gas: u64| {
+            let mut context = context(address(2), address(1), address(1),
+                                      assemble("PUSH1 0 PUSH1 0 SSTORE").unwrap());
+            context.gas_limit = Gas::from(gas);
+            let mut patch = patch;
+            patch.gas.sreset = 200;
+            let mut machine = SeqMachine::new(context, block(), patch, 0);
+            run(&mut machine);
+            machine
+        };
+
+        match sstore(Patch::new(Fork::Istanbul), 6 + 2300).status() {
+            MachineStatus::ExitedErr(MachineError::EmptyGas) => (),
+            _ => panic!(),
+        }
+        match sstore(Patch::new(Fork::Istanbul), 6 + 2301).status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+        match sstore(Patch::new(Fork::Constantinople), 6 + 2300).status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+
+        // The sentinel follows the patch flag rather than the fork.
+        let mut patch = Patch::new(Fork::Istanbul);
+        patch.sstore_gas_sentinel = false;
+        match sstore(patch, 6 + 2300).status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+        let mut patch = Patch::new(Fork::Constantinople);
+        patch.sstore_gas_sentinel = true;
+        match sstore(patch, 6 + 2300).status() {
+            MachineStatus::ExitedErr(MachineError::EmptyGas) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn intrinsic_gas_of_data() {
+        let data = [0x00, 0x01, 0x02];
+        assert_eq!(intrinsic_gas(&GasSchedule::new(Fork::Frontier), &data),
+                   Gas::from(21000u64 + 4 + 68 * 2));
+        // Non-zero bytes are cheaper from Istanbul (EIP-2028).
+        assert_eq!(intrinsic_gas(&GasSchedule::new(Fork::Istanbul), &data),
+                   Gas::from(21000u64 + 4 + 16 * 2));
+    }
+
+    #[test]
+    fn run_bytecode_regressions() {
+        // DELEGATECALL used to hit `unimplemented!()`.
+        let result = run_bytecode(&[0xf4], &[], 100000);
+        match result.status {
+            VMStatus::ExitedErr(VMError::Machine(MachineError::Stack(StackError::Underflow))) => (),
+            _ => panic!(),
+        }
+
+        // A CODECOPY of nearly `usize::max_value()` bytes used to
+        // check every byte of the destination before charging gas.
+        let mut code = vec![0x45, 0x67, 0xff, 0xd1, 0xb1, 0x57, 0x02, 0x38, 0x90, 0x60, 0x45, 0x48, 0x39];
+        let result = run_bytecode(&code, &[], 100000);
+        match result.status {
+            VMStatus::ExitedErr(VMError::Machine(MachineError::EmptyMemoryGas)) => (),
+            _ => panic!(),
+        }
+        code.truncate(1);
+        let result = run_bytecode(&code, &[], 100000);
+        match result.status {
+            VMStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+        assert_eq!(result.available_gas, Gas::from(100000u64 - 2));
+
+        // MSTORE past the end of the address space.
+        let result = run_bytecode(&[0x60, 0x01, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+                                    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+                                    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+                                    0x52], &[], 100000);
+        match result.status {
+            VMStatus::ExitedErr(_) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn sub_call_storage_isolation() {
+        let parent = assemble("PUSH1 1 PUSH1 1 SSTORE
+                               PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 3 PUSH2 0xc000 CALLCODE
+                               STOP").unwrap();
+        let storage_after = |child: Vec<u8>| {
+            let mut context = context(address(2), address(1), address(1), parent.clone());
+            context.gas_limit = Gas::from(1000000u64);
+            let mut machine = SeqMachine::new(context, block(), Patch::new(Fork::Frontier), 1);
+            commit(&mut machine, address(3), child);
+            run(&mut machine);
+            let sub_context = match machine.status() {
+                MachineStatus::InvokeCall(context, _) => context,
+                _ => panic!(),
+            };
+            let mut sub = machine.derive(sub_context);
+            run(&mut sub);
+            machine.apply_sub(sub).unwrap();
+            run(&mut machine);
+            let storage = machine.state().account_state.storage(address(2)).unwrap();
+            (storage.read(M256::from(1u64)), storage.read(M256::from(2u64)))
+        };
+
+        assert_eq!(storage_after(assemble("PUSH1 2 PUSH1 2 SSTORE").unwrap()),
+                   (M256::from(1u64), M256::from(2u64)));
+        assert_eq!(storage_after(assemble("PUSH1 2 PUSH1 2 SSTORE INVALID").unwrap()),
+                   (M256::from(1u64), M256::zero()));
+    }
+
+    #[test]
+    fn sub_call_keeps_commitments() {
+        let applied = |child: Vec<u8>| {
+            let mut machine = SeqMachine::new(context(address(2), address(1), address(1), call_code(3)),
+                                              block(), Patch::new(Fork::Frontier), 1);
+            commit(&mut machine, address(3), child);
+            run(&mut machine);
+            let sub_context = match machine.status() {
+                MachineStatus::InvokeCall(context, _) => context,
+                _ => panic!(),
+            };
+            let mut sub = machine.derive(sub_context);
+            commit(&mut machine, address(4), Vec::new());
+            commit(&mut sub, address(5), Vec::new());
+            run(&mut sub);
+            machine.apply_sub(sub).unwrap();
+            machine
+        };
+
+        for child in vec![assemble("STOP").unwrap(), assemble("INVALID").unwrap()] {
+            let machine = applied(child);
+            assert!(machine.state().account_state.require(address(4)).is_ok());
+            assert!(machine.state().account_state.require(address(5)).is_ok());
+        }
+    }
+
+    #[test]
+    fn exp_gas_limit_boundary() {
+        let exp = |exponent: Vec<u8>, fork, gas: u64| {
+            // PUSH exponent, PUSH1 3, EXP
+            let mut code = vec![0x5f + exponent.len() as u8];
+            code.extend(exponent);
+            code.extend(assemble("PUSH1 3 EXP").unwrap());
+            let mut context = context(address(2), address(1), address(1), code);
+            context.gas_limit = Gas::from(gas);
+            let mut machine = SeqMachine::new(context, block(), Patch::new(fork), 1);
+            run(&mut machine);
+            machine
+        };
+
+        let cases = vec![
+            (vec![0x00], Fork::EIP160, 3 + 3 + 10),
+            (vec![0x01], Fork::EIP150, 3 + 3 + 10 + 10),
+            (vec![0xff], Fork::EIP160, 3 + 3 + 10 + 50),
+            (vec![0x01, 0x00], Fork::EIP160, 3 + 3 + 10 + 50 * 2),
+            (vec![0xff; 32], Fork::EIP160, 3 + 3 + 10 + 50 * 32),
+        ];
+        for (exponent, fork, cost) in cases {
+            let machine = exp(exponent.clone(), fork, cost);
+            match machine.status() {
+                MachineStatus::ExitedOk => (),
+                _ => panic!(),
+            }
+            assert_eq!(machine.state().available_gas(), Gas::zero());
+
+            match exp(exponent, fork, cost - 1).status() {
+                MachineStatus::ExitedErr(MachineError::EmptyGas) => (),
+                _ => panic!(),
+            }
+        }
+    }
+
+    #[test]
+    fn into_state() {
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1),
+                                                  assemble("PUSH1 0 PUSH1 0 LOG0").unwrap()),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        run(&mut machine);
+        let state = machine.into_state();
+        assert_eq!(state.logs.len(), 1);
+        assert_eq!(state.logs[0].address, address(2));
+        assert_eq!(state.used_gas, Gas::from(3u64 + 3 + 375));
+    }
+
+    #[test]
+    #[should_panic]
+    fn into_state_while_running() {
+        let machine = SeqMachine::new(context(address(2), address(1), address(1), assemble("STOP").unwrap()),
+                                      block(), Patch::new(Fork::Frontier), 1);
+        machine.into_state();
+    }
+
+    #[test]
+    fn trie_roots() {
+        let item = |key: &str, value: &str| (key.as_bytes().to_vec(), value.as_bytes().to_vec());
+        assert_eq!(trie_root(Vec::new()),
+                   M256::from_str("0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421").unwrap());
+        assert_eq!(trie_root(vec![item("doe", "reindeer"), item("dog", "puppy"), item("dogglesworth", "cat")]),
+                   M256::from_str("0x8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3").unwrap());
+    }
+
+    #[test]
+    fn delegatecall_fork_gating() {
+        let origin = address(1);
+        let owner = address(2);
+        let code = assemble("PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 3 PUSH2 0xc000 DELEGATECALL").unwrap();
+        let child = assemble("CALLER PUSH1 0 SSTORE CALLVALUE PUSH1 1 SSTORE").unwrap();
+
+        let mut machine = SeqMachine::new(context(owner, origin, origin, code.clone()),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedErr(MachineError::PC(PCError::InvalidOpcode)) => (),
+            _ => panic!(),
+        }
+
+        let mut ctx = context(owner, origin, origin, code);
+        ctx.value = U256::from(5u64);
+        let mut vm = SeqVM::new(ctx, block(), Patch::new(Fork::Homestead));
+        fire(&mut vm, &[(address(3), child)]);
+        match vm.status() {
+            VMStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+        // The code of 3 ran with the storage, caller and value of 2.
+        assert_eq!(storage(&vm, owner, 0), M256::from(1u64));
+        assert_eq!(storage(&vm, owner, 1), M256::from(5u64));
+        assert_eq!(storage(&vm, address(3), 0), M256::zero());
+    }
+
+    #[test]
+    fn account_state_root() {
+        let sender = Address::from_str("0xa94f5374fce5edbc8e2a8697c15331677e6ebf0b").unwrap();
+        let mut state: AccountState<HashMapStorage> = AccountState::default();
+        state.commit(AccountCommitment::Full {
+            nonce: M256::zero(),
+            address: sender,
+            balance: U256::from_str("0x0de0b6b3a7640000").unwrap(),
+            storage: HashMapStorage::default(),
+            code: Vec::new(),
+        }).unwrap();
+        assert_eq!(state.state_root(false).unwrap(),
+                   M256::from_str("0x517f2cdf6adb1a644878c390ffab4e130f1bed4b498ef7ce58c5addd98d61018").unwrap());
+
+        // Only the balance change of an uncommitted account is known.
+        state.increase_balance(address(9), U256::from(1u64)).unwrap();
+        match state.state_root(false) {
+            Err(RequireError::Account(missing)) => assert_eq!(missing, address(9)),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn truncated_push() {
+        let mut pc = PC::new(&[0x7f]);
+        assert_eq!(pc.read().unwrap(), Instruction::PUSH(32, M256::zero()));
+        assert!(pc.is_end());
+
+        // The missing immediate bytes are the low bytes of the value.
+        let mut pc = PC::new(&[0x61, 0xab]);
+        assert_eq!(pc.read().unwrap(), Instruction::PUSH(2, M256::from(0xab00u64)));
+        assert!(pc.is_end());
+
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1), vec![0x7f]),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().stack.len(), 1);
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::zero());
+    }
+
+    #[test]
+    fn block_info_opcode_forks() {
+        let valid = |fork: Fork, opcode: u8| {
+            // PUSH1 0, then the opcode
+            let code = vec![0x60, 0x00, opcode];
+            let mut machine = SeqMachine::new(context(address(2), address(1), address(1), code),
+                                              block(), Patch::new(fork), 1);
+            run(&mut machine);
+            match machine.status() {
+                MachineStatus::ExitedOk => true,
+                MachineStatus::ExitedErr(MachineError::PC(PCError::InvalidOpcode)) => false,
+                _ => panic!(),
+            }
+        };
+
+        // CHAINID and SELFBALANCE
+        for &opcode in &[0x46, 0x47] {
+            assert!(!valid(Fork::Constantinople, opcode));
+            assert!(valid(Fork::Istanbul, opcode));
+        }
+        // BASEFEE
+        assert!(!valid(Fork::Istanbul, 0x48));
+        assert!(valid(Fork::London, 0x48));
+        assert!(valid(Fork::Cancun, 0x48));
+        // BLOBHASH and BLOBBASEFEE
+        for &opcode in &[0x49, 0x4a] {
+            assert!(!valid(Fork::London, opcode));
+            assert!(valid(Fork::Cancun, opcode));
+        }
+
+        assert!(check_opcode_fork(0x5f, &Patch::new(Fork::London)).is_err());
+        assert!(check_opcode_fork(0x5f, &Patch::new(Fork::Shanghai)).is_ok());
+        assert!(check_opcode_fork(0x01, &Patch::new(Fork::Frontier)).is_ok());
+    }
+
+    #[test]
+    fn chainid_selfbalance_blobbasefee() {
+        let run_code = |patch: Patch, code: &str| {
+            let block = block().with_blob_base_fee(Gas::from(7u64));
+            let mut machine = SeqMachine::new(context(address(2), address(1), address(1), assemble(code).unwrap()),
+                                              block, patch, 1);
+            run(&mut machine);
+            match machine.status() {
+                MachineStatus::ExitedOk => (),
+                _ => panic!(),
+            }
+            (machine.state().stack.peek(0).unwrap(), machine.state().used_gas)
+        };
+
+        let mut patch = Patch::new(Fork::Istanbul);
+        assert_eq!(run_code(patch, "CHAINID"), (M256::from(1u64), Gas::from(2u64)));
+        patch.chain_id = U256::from(61u64);
+        assert_eq!(run_code(patch, "CHAINID"), (M256::from(61u64), Gas::from(2u64)));
+        assert_eq!(run_code(Patch::new(Fork::Istanbul), "SELFBALANCE"),
+                   (M256::from(1000000u64), Gas::from(5u64)));
+        assert_eq!(run_code(Patch::new(Fork::Cancun), "BLOBBASEFEE"),
+                   (M256::from(7u64), Gas::from(2u64)));
+    }
+
+    #[test]
+    fn blobhash() {
+        let hash = M256::from_str("0x01a915e4d060149eb4365960e6a7a45f334393093061116b197e3240065ff2d8").unwrap();
+        let run_blobhash = |fork: Fork, index: u8| {
+            // PUSH1 index, BLOBHASH
+            let mut ctx = context(address(2), address(1), address(1), vec![0x60, index, 0x49]);
+            ctx.blob_versioned_hashes = vec![M256::from(1u64), hash];
+            let mut machine = SeqMachine::new(ctx, block(), Patch::new(fork), 1);
+            run(&mut machine);
+            machine
+        };
+
+        let machine = run_blobhash(Fork::Cancun, 1);
+        match machine.status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().stack.peek(0).unwrap(), hash);
+        assert_eq!(machine.state().used_gas, Gas::from(6u64));
+
+        let machine = run_blobhash(Fork::Cancun, 2);
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::zero());
+
+        match run_blobhash(Fork::Shanghai, 1).status() {
+            MachineStatus::ExitedErr(MachineError::PC(PCError::InvalidOpcode)) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn reset_machine() {
+        let origin = address(1);
+        let owner = address(2);
+        let first = assemble("PUSH1 1 PUSH1 0 SSTORE PUSH1 2 PUSH1 64 MSTORE
+                              PUSH1 32 PUSH1 64 LOG0 PUSH1 3").unwrap();
+        let second = assemble("PUSH1 5 PUSH1 0 MSTORE8 MSIZE").unwrap();
+
+        let mut machine = SeqMachine::new(context(owner, origin, origin, first),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        machine.enable_trace();
+        run(&mut machine);
+        assert_eq!(machine.state().logs.len(), 1);
+
+        machine.reset(context(address(3), origin, origin, second.clone()),
+                      block(), Patch::new(Fork::Cancun));
+        match machine.status() {
+            MachineStatus::Running => (),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().stack.len(), 0);
+        assert_eq!(machine.memory_dump().len(), 0);
+        assert_eq!(machine.state().used_gas, Gas::zero());
+        assert_eq!(machine.state().logs.len(), 0);
+        assert_eq!(machine.state().account_state.accounts().count(), 0);
+        assert_eq!(machine.trace().len(), 0);
+        run(&mut machine);
+
+        let mut fresh = SeqMachine::new(context(address(3), origin, origin, second),
+                                        block(), Patch::new(Fork::Cancun), 1);
+        run(&mut fresh);
+        assert_eq!(machine.state().used_gas, fresh.state().used_gas);
+        assert_eq!(machine.state().memory_cost, fresh.state().memory_cost);
+        assert_eq!(machine.memory_dump(), fresh.memory_dump());
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::from(32u64));
+        assert_eq!(machine.trace().len(), 4);
+
+        // Return data of a call is not kept either.
+        let code = call_code(0x30);
+        let mut machine = SeqMachine::new(context(owner, origin, origin, code.clone()),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        commit(&mut machine, owner, code);
+        commit(&mut machine, address(0x30), assemble("PUSH1 32 PUSH1 0 RETURN").unwrap());
+        run(&mut machine);
+        let mut sub = machine.derive(machine.pending_invoke().unwrap().clone());
+        run(&mut sub);
+        machine.apply_sub(sub).unwrap();
+        assert_eq!(machine.state().return_data.len(), 32);
+        machine.reset(context(address(3), origin, origin, Vec::new()),
+                      block(), Patch::new(Fork::Frontier));
+        assert_eq!(machine.state().return_data.len(), 0);
+    }
+
+    #[test]
+    fn call_memory_covers_output() {
+        for &(opcode, value) in &[(0xf1, true), (0xf2, true), (0xf4, false), (0xfa, false)] {
+            // Output of 32 bytes at 0x100, input of 32 bytes at 0.
+            let mut code = assemble("PUSH1 32 PUSH2 0x100 PUSH1 32 PUSH1 0").unwrap();
+            if value {
+                code.extend(assemble("PUSH1 0").unwrap());
+            }
+            code.extend(vec![0x60, 0x03, 0x60, 0x00, opcode]);
+
+            let mut machine = SeqMachine::new(context(address(2), address(1), address(1), code),
+                                              block(), Patch::new(Fork::Byzantium), 1);
+            run(&mut machine);
+            match machine.status() {
+                MachineStatus::InvokeCall(_, _) => (),
+                _ => panic!(),
+            }
+            // The output region ends at 0x120, which is nine words.
+            assert_eq!(machine.state().memory_cost, Gas::from(9u64));
+        }
+    }
+
+    #[test]
+    fn is_contract() {
+        let mut state: AccountState<HashMapStorage> = AccountState::default();
+        state.commit(AccountCommitment::Full {
+            nonce: M256::zero(),
+            address: address(0x2000),
+            balance: U256::from(1u64),
+            storage: HashMapStorage::default(),
+            code: Vec::new(),
+        }).unwrap();
+        state.commit(AccountCommitment::Code {
+            address: address(0x3000),
+            code: assemble("STOP").unwrap(),
+        }).unwrap();
+
+        assert_eq!(state.is_contract(address(0x2000)).unwrap(), false);
+        assert_eq!(state.is_contract(address(0x3000)).unwrap(), true);
+        match state.is_contract(address(0x1000)) {
+            Err(RequireError::AccountCode(missing)) => assert_eq!(missing, address(0x1000)),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn step_at_callstack_limit() {
+        let patch = Patch::new(Fork::Frontier);
+        let limit = patch.callstack_limit;
+        let code = assemble("PUSH1 1 PUSH1 0 SSTORE").unwrap();
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1), code),
+                                          block(), patch, limit);
+        machine.enable_trace();
+        machine.step().unwrap();
+        match machine.status() {
+            MachineStatus::ExitedErr(MachineError::CallstackOverflow) => (),
+            _ => panic!(),
+        }
+        assert_eq!(machine.pc().position(), 0);
+        assert_eq!(machine.state().stack.len(), 0);
+        assert_eq!(machine.state().used_gas, Gas::zero());
+        assert_eq!(machine.trace().len(), 0);
+
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1), Vec::new()),
+                                          block(), Patch::new(Fork::Frontier), usize::max_value());
+        assert_eq!(machine.derive(context(address(3), address(2), address(1), Vec::new())).state().depth,
+                   usize::max_value());
+        machine.step().unwrap();
+        match machine.status() {
+            MachineStatus::ExitedErr(MachineError::CallstackOverflow) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn pending_invoke() {
+        let origin = address(1);
+        let owner = address(2);
+        let target = address(0x1000);
+        let code = assemble("PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 7 PUSH2 0x1000 PUSH2 0xc000 CALL").unwrap();
+        let target_code = assemble("PUSH1 1 STOP").unwrap();
+
+        let mut machine = SeqMachine::new(context(owner, origin, origin, code),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        assert!(machine.pending_invoke().is_none());
+        commit(&mut machine, target, target_code.clone());
+        run(&mut machine);
+
+        {
+            let context = machine.pending_invoke().unwrap();
+            assert_eq!(context.address, target);
+            assert_eq!(context.code, target_code);
+            assert_eq!(context.value, U256::from(7u64));
+        }
+        let sub = machine.derive(machine.pending_invoke().unwrap().clone());
+        assert_eq!(sub.state().context.caller, owner);
+    }
+
+    #[test]
+    fn frame_returns() {
+        let origin = address(1);
+        let owner = address(2);
+        let code = assemble("PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH2 0x1000 PUSH3 0x10000 CALL").unwrap();
+        let child = assemble("PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH2 0x2000 PUSH2 0xc000 CALL
+                              INVALID").unwrap();
+        // RETURN "boom"
+        let grandchild = assemble("PUSH4 0x626f6f6d PUSH1 0 MSTORE PUSH1 4 PUSH1 28 RETURN").unwrap();
+
+        let mut vm = SeqVM::new(context(owner, origin, origin, code),
+                                block(), Patch::new(Fork::Frontier));
+        fire(&mut vm, &[(address(0x1000), child), (address(0x2000), grandchild)]);
+        assert_eq!(vm.0[0].state().frame_returns, vec![
+            FrameReturn { address: address(0x2000), data: b"boom".to_vec(), reverted: false },
+            FrameReturn { address: address(0x1000), data: Vec::new(), reverted: true },
+        ]);
+    }
+
+    #[test]
+    fn msize_rounds_up_and_saturates() {
+        let code = assemble("PUSH1 1 PUSH1 5 MSTORE8 MSIZE").unwrap();
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1), code),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        run(&mut machine);
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::from(32u64));
+
+        // About 2^132.5 words is the most memory 2^256 gas can pay for.
+        let mut ctx = context(address(2), address(1), address(1), assemble("MSIZE").unwrap());
+        ctx.gas_limit = Gas::from(U256::max_value());
+        let mut machine = SeqMachine::new(ctx, block(), Patch::new(Fork::Frontier), 1);
+        machine.state.memory_cost = Gas::from(U256::one() << 132);
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::one() << 137);
+
+        // Without gas, memory can grow past what MSIZE can represent.
+        let mut patch = Patch::new(Fork::Frontier);
+        patch.ignore_gas = true;
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1), assemble("MSIZE").unwrap()),
+                                          block(), patch, 1);
+        machine.state.memory_cost = Gas::from(U256::one() << 251);
+        run(&mut machine);
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::max_value());
+    }
+
+    #[test]
+    fn sload_after_sstore() {
+        let owner = address(2);
+        let code = assemble("PUSH1 42 PUSH1 3 SSTORE PUSH1 3 SLOAD").unwrap();
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        let mut committed = Map::new();
+        committed.insert(M256::from(3u64), M256::from(7u64));
+        machine.commit_account(AccountCommitment::Full {
+            nonce: M256::zero(),
+            address: owner,
+            balance: U256::zero(),
+            storage: HashMapStorage::from(committed),
+            code: Vec::new(),
+        }).unwrap();
+
+        while let MachineStatus::Running = machine.status() {
+            machine.step().unwrap();
+        }
+        match machine.status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::from(42u64));
+    }
+
+    #[test]
+    fn sub_call_placeholder() {
+        let origin = address(1);
+        let owner = address(2);
+        let code = assemble("PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH2 0x1000 PUSH2 0x1000 CALL
+                             PUSH1 0 PUSH1 0 PUSH1 0 CREATE").unwrap();
+
+        for &placeholder in [M256::zero(), M256::one()].iter() {
+            let mut patch = Patch::new(Fork::Frontier);
+            patch.sub_call_placeholder = Some(placeholder);
+            let mut machine = SeqMachine::new(context(owner, origin, origin, code.clone()),
+                                              block(), patch, 1);
+            // The callee would return 1 if it ran.
+            commit(&mut machine, address(0x1000), assemble("PUSH1 1 PUSH1 0 MSTORE
+                                                            PUSH1 32 PUSH1 0 RETURN").unwrap());
+            run(&mut machine);
+            match machine.status() {
+                MachineStatus::ExitedOk => (),
+                _ => panic!(),
+            }
+            assert_eq!(machine.state().stack.len(), 2);
+            assert_eq!(machine.state().stack.peek(0).unwrap(), placeholder);
+            assert_eq!(machine.state().stack.peek(1).unwrap(), placeholder);
+            assert!(machine.state().sub_calls.is_empty());
+        }
+    }
+
+    #[test]
+    fn first_sstore_requires_account() {
+        let owner = address(2);
+        let code = assemble("PUSH1 42 PUSH1 3 SSTORE").unwrap();
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        machine.step().unwrap();
+        machine.step().unwrap();
+        let used_gas = machine.state().used_gas;
+
+        // The storage of an account is committed together with the
+        // account, which gives the original value of every slot.
+        match machine.step() {
+            Err(RequireError::Account(address)) => assert_eq!(address, owner),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().used_gas, used_gas);
+        commit(&mut machine, owner, Vec::new());
+        machine.step().unwrap();
+
+        assert_eq!(machine.state().used_gas - used_gas, Gas::from(20000u64));
+        assert_eq!(machine.state().account_state.storage(owner).unwrap().read(M256::from(3u64)),
+                   M256::from(42u64));
+    }
+
+    #[test]
+    fn net_used_gas() {
+        let owner = address(2);
+        let run_with_slot = |code: Vec<u8>| {
+            let mut storage = HashMapStorage::default();
+            storage.write(M256::from(1u64), M256::from(1u64)).unwrap();
+            let mut machine = SeqMachine::new(context(owner, address(1), address(1), code.clone()),
+                                              block(), Patch::new(Fork::Berlin), 1);
+            machine.commit_account(AccountCommitment::Full {
+                nonce: M256::zero(),
+                address: owner,
+                balance: U256::zero(),
+                storage: storage,
+                code: code,
+            }).unwrap();
+            run(&mut machine);
+            machine
+        };
+
+        let machine = run_with_slot(assemble("PUSH1 1 PUSH1 2 SSTORE PUSH1 1 PUSH1 3 SSTORE
+                                              PUSH1 0 PUSH1 1 SSTORE").unwrap());
+        let used_gas = machine.state().total_used_gas();
+        assert_eq!(machine.refunded_gas(), Gas::from(15000u64));
+        assert!(machine.refunded_gas() < used_gas / Gas::from(2u64));
+        assert_eq!(machine.net_used_gas(), used_gas - Gas::from(15000u64));
+
+        let machine = run_with_slot(assemble("PUSH1 0 PUSH1 1 SSTORE").unwrap());
+        let used_gas = machine.state().total_used_gas();
+        assert_eq!(machine.refunded_gas(), Gas::from(15000u64));
+        assert!(machine.refunded_gas() > used_gas / Gas::from(2u64));
+        assert_eq!(machine.net_used_gas(), used_gas - used_gas / Gas::from(2u64));
+    }
+
+    #[test]
+    fn instruction_gas_cost() {
+        let code = assemble("PUSH1 64 PUSH1 0 SHA3").unwrap();
+        let mut machine = SeqMachine::new(context(address(2), address(1), address(1), code),
+                                          block(), Patch::new(Fork::Frontier), 1);
+        machine.step().unwrap();
+        machine.step().unwrap();
+
+        let cost = machine.instruction_gas_cost().unwrap();
+        let used_gas = machine.state().total_used_gas();
+        machine.step().unwrap();
+        assert_eq!(machine.state().total_used_gas() - used_gas, cost);
+        // 30 + 6 per word, and 3 per word of memory expansion.
+        assert_eq!(cost, Gas::from(48u64));
+
+        // Nothing left to price once the code ends.
+        assert!(machine.instruction_gas_cost().is_err());
+    }
+
+    #[test]
+    fn created_contract_nonce() {
+        let creator = address(2);
+        let created = create_address(creator, M256::zero());
+        let code = assemble("PUSH1 0 PUSH1 0 PUSH1 0 CREATE").unwrap();
+        let nonce = |fork: Fork| {
+            let mut vm = SeqVM::new(context(creator, address(1), address(1), code.clone()),
+                                    block(), Patch::new(fork));
+            fire(&mut vm, &[]);
+            let mut ret = None;
+            for account in vm.accounts() {
+                match account {
+                    &Account::Full { address, nonce, .. } if address == created => ret = Some(nonce),
+                    _ => (),
+                }
+            }
+            ret.unwrap()
+        };
+
+        assert_eq!(nonce(Fork::EIP160), M256::zero());
+        assert_eq!(nonce(Fork::EIP161), M256::from(1u64));
+    }
+
+    #[test]
+    fn call_to_empty_code() {
+        let owner = address(2);
+        let target = address(0x1000);
+        // PUSH1 0xff, PUSH1 0, MSTORE8, then CALL the target with
+        // value 100 and no gas, writing up to 32 bytes of output at 0
+        let code = assemble("PUSH1 255 PUSH1 0 MSTORE8
+                             PUSH1 32 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 100 PUSH2 0x1000 PUSH1 0 CALL").unwrap();
+        let mut vm = SeqVM::new(context(owner, address(1), address(1), code),
+                                block(), Patch::new(Fork::Frontier));
+        fire(&mut vm, &[]);
+
+        let machine = &vm.0[0];
+        match machine.status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::one());
+        assert_eq!(machine.state().account_state.balance(owner).unwrap(), U256::from(1000000u64 - 100));
+        assert_eq!(machine.state().account_state.balance(target).unwrap(), U256::from(1000000u64 + 100));
+        // The output is empty, so the output region is left as it is.
+        assert_eq!(machine.memory_dump()[0], 0xff);
+        // Nothing is used beyond the cost of the instructions; the
+        // stipend is returned.
+        assert_eq!(machine.state().total_used_gas(), Gas::from(3u64 * 9 + 3 + 3 + 40 + 9000 - 2300));
+        assert_eq!(machine.state().frame_returns[0].data, Vec::<u8>::new());
+    }
+
+    #[test]
+    fn apply_sub_misuse() {
+        let owner = address(2);
+        let code = assemble("PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH2 0x1000 PUSH2 0x1000 CALL").unwrap();
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code),
+                                          block(), Patch::new(Fork::Frontier), 1);
+
+        // Nothing is invoked yet.
+        let stray = SeqMachine::new(context(address(3), address(1), address(1), Vec::new()),
+                                    block(), Patch::new(Fork::Frontier), 2);
+        match machine.apply_sub(stray) {
+            Err(ApplyError::NotInvoking) => (),
+            _ => panic!(),
+        }
+        match machine.status() {
+            MachineStatus::Running => (),
+            _ => panic!(),
+        }
+
+        commit(&mut machine, address(0x1000), assemble("STOP").unwrap());
+        run(&mut machine);
+        let invoked = machine.pending_invoke().unwrap().clone();
+        // The sub runtime has not run yet.
+        match machine.apply_sub(machine.derive(invoked.clone())) {
+            Err(ApplyError::SubNotExited) => (),
+            _ => panic!(),
+        }
+        match machine.status() {
+            MachineStatus::InvokeCall(_, _) => (),
+            _ => panic!(),
+        }
+
+        let mut sub = machine.derive(invoked);
+        run(&mut sub);
+        machine.apply_sub(sub).unwrap();
+        match machine.status() {
+            MachineStatus::Running => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn balance_overflow() {
+        let near_max = U256::max_value() - U256::from(1u64);
+        let mut state: AccountState<HashMapStorage> = AccountState::default();
+        for &(addr, balance) in [(address(0x1000), near_max), (address(0x1001), U256::from(5u64))].iter() {
+            state.commit(AccountCommitment::Full {
+                nonce: M256::zero(),
+                address: addr,
+                balance: balance,
+                storage: HashMapStorage::default(),
+                code: Vec::new(),
+            }).unwrap();
+        }
+
+        match state.increase_balance(address(0x1000), U256::from(2u64)) {
+            Err(MachineError::BalanceOverflow) => (),
+            _ => panic!(),
+        }
+        assert_eq!(state.balance(address(0x1000)).unwrap(), near_max);
+        state.increase_balance(address(0x1000), U256::from(1u64)).unwrap();
+        assert_eq!(state.balance(address(0x1000)).unwrap(), U256::max_value());
+
+        match state.decrease_balance(address(0x1001), U256::from(6u64)) {
+            Err(MachineError::BalanceOverflow) => (),
+            _ => panic!(),
+        }
+        assert_eq!(state.balance(address(0x1001)).unwrap(), U256::from(5u64));
+        state.decrease_balance(address(0x1001), U256::from(5u64)).unwrap();
+        assert_eq!(state.balance(address(0x1001)).unwrap(), U256::zero());
+
+        // An uncommitted account accumulates its decrease.
+        state.decrease_balance(address(0x1002), U256::max_value()).unwrap();
+        match state.decrease_balance(address(0x1002), U256::from(1u64)) {
+            Err(MachineError::BalanceOverflow) => (),
+            _ => panic!(),
+        }
+
+        // SUICIDE into a near-max beneficiary fails before removing
+        // the account.
+        let contract = address(0x1003);
+        let code = assemble("PUSH20 0x1000 SUICIDE").unwrap();
+        let mut machine = SeqMachine::new(context(contract, contract, contract, code.clone()),
+                                          block(), Patch::new(Fork::Homestead), 0);
+        commit(&mut machine, contract, code);
+        machine.commit_account(AccountCommitment::Full {
+            nonce: M256::zero(),
+            address: address(0x1000),
+            balance: near_max,
+            storage: HashMapStorage::default(),
+            code: Vec::new(),
+        }).unwrap();
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedErr(MachineError::BalanceOverflow) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn call_outcome_matrix() {
+        let owner = address(2);
+        let target = address(0x1000);
+        // PUSH1 0xff, PUSH1 0, MSTORE8, then CALL the target with
+        // 0x1000 gas, writing up to 32 bytes of output at 0
+        let code = assemble("PUSH1 255 PUSH1 0 MSTORE8
+                             PUSH1 32 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH2 0x1000 PUSH2 0x1000 CALL").unwrap();
+        let returning = assemble("PUSH1 170 PUSH1 0 MSTORE8 PUSH1 1 PUSH1 0 RETURN").unwrap();
+        // The same, but ending with an invalid opcode.
+        let failing = assemble("PUSH1 170 PUSH1 0 MSTORE8 INVALID").unwrap();
+
+        // There is no REVERT yet, so a call either succeeds, pushing 1
+        // and copying the output, or fails, pushing 0 and leaving the
+        // output region as it is.
+        for &(ref child, flag, byte) in [(returning, 1u64, 0xaa), (failing, 0u64, 0xff)].iter() {
+            let mut machine = SeqMachine::new(context(owner, address(1), address(1), code.clone()),
+                                              block(), Patch::new(Fork::Frontier), 1);
+            commit(&mut machine, target, child.clone());
+            run(&mut machine);
+            let mut sub = machine.derive(machine.pending_invoke().unwrap().clone());
+            run(&mut sub);
+            machine.apply_sub(sub).unwrap();
+            run(&mut machine);
+
+            match machine.status() {
+                MachineStatus::ExitedOk => (),
+                _ => panic!(),
+            }
+            assert_eq!(machine.state().stack.peek(0).unwrap(), M256::from(flag));
+            assert_eq!(machine.memory_dump()[0], byte);
+            assert_eq!(machine.state().frame_returns[0].reverted, flag == 0);
+        }
+    }
+
+    #[test]
+    fn mload_expands_memory() {
+        let owner = address(2);
+        let code = assemble("PUSH1 255 PUSH1 0 MSTORE MSIZE PUSH1 1 MLOAD MSIZE PUSH1 0 MLOAD").unwrap();
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code.clone()),
+                                          block(), Patch::new(Fork::Frontier), 0);
+        commit(&mut machine, owner, code);
+        run(&mut machine);
+        match machine.status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+
+        let stack = &machine.state().stack;
+        // The word stored is read back.
+        assert_eq!(stack.peek(0).unwrap(), M256::from(0xffu64));
+        // Reading one byte past the end expanded memory by a word.
+        assert_eq!(stack.peek(1).unwrap(), M256::from(64u64));
+        // The byte past the end reads as zero.
+        assert_eq!(stack.peek(2).unwrap(), M256::from(0xff00u64));
+        assert_eq!(stack.peek(3).unwrap(), M256::from(32u64));
+        // The expansion by the read is paid for like one by a write.
+        assert_eq!(machine.state().total_used_gas(), Gas::from(3u64 * 4 + (3 + 3) * 2 + 3 + 2 * 2));
+    }
+
+    struct PushConstant;
+
+    impl CustomOpcode<SeqMemory, HashMapStorage> for PushConstant {
+        fn handles(&self, opcode: u8) -> bool {
+            opcode == 0x0c
+        }
+
+        fn check(&self, _: u8, state: &State<SeqMemory, HashMapStorage>) -> Result<(), EvalError> {
+            state.stack.check_pop_push(0, 1)?;
+            Ok(())
+        }
+
+        fn gas_cost(&self, _: u8, _: &State<SeqMemory, HashMapStorage>) -> Gas {
+            Gas::from(2u64)
+        }
+
+        fn run(&self, _: u8, state: &mut State<SeqMemory, HashMapStorage>,
+               _: Gas, _: Gas) -> Option<Control> {
+            state.stack.push(M256::from(0x2au64)).unwrap();
+            None
+        }
+    }
+
+    #[test]
+    fn custom_opcode() {
+        let owner = address(2);
+        // 0x0c, PUSH1 1, ADD, 0x0d
+        let code = vec![0x0c, 0x60, 0x01, 0x01, 0x0d];
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code.clone()),
+                                          block(), Patch::new(Fork::Frontier), 0);
+        machine.set_custom_opcodes(Arc::new(PushConstant));
+        commit(&mut machine, owner, code);
+        run(&mut machine);
+
+        // Bytes the handler does not take are still invalid.
+        match machine.status() {
+            MachineStatus::ExitedErr(MachineError::PC(PCError::InvalidOpcode)) => (),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::from(0x2bu64));
+        assert_eq!(machine.state().total_used_gas(), Gas::from(2u64 + 3 + 3));
+    }
+
+    #[test]
+    fn return_data() {
+        let owner = address(2);
+        let target = address(0x1000);
+        // CALL the target with 0x1000 gas, writing up to 32 bytes of
+        // output at 0, then CREATE with init code PUSH1 1, PUSH1 0,
+        // RETURN stored by PUSH5 and MSTORE
+        let code = assemble("PUSH1 32 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH2 0x1000 PUSH2 0x1000 CALL
+                             PUSH5 0x60016000f3 PUSH1 0 MSTORE PUSH1 5 PUSH1 27 PUSH1 0 CREATE").unwrap();
+        let returning = assemble("PUSH1 170 PUSH1 0 MSTORE8 PUSH1 1 PUSH1 0 RETURN").unwrap();
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code.clone()),
+                                          block(), Patch::new(Fork::Homestead), 1);
+        commit(&mut machine, owner, code);
+        commit(&mut machine, target, returning);
+
+        // A successful call sets the return data to its output.
+        run(&mut machine);
+        let mut sub = machine.derive(machine.pending_invoke().unwrap().clone());
+        run(&mut sub);
+        machine.apply_sub(sub).unwrap();
+        assert_eq!(machine.state().return_data, vec![0xaa]);
+
+        // A new frame starts with no return data, and a successful
+        // creation clears it.
+        run(&mut machine);
+        let mut sub = machine.derive(machine.pending_invoke().unwrap().clone());
+        assert_eq!(sub.state().return_data, Vec::<u8>::new());
+        run(&mut sub);
+        assert_eq!(sub.state().out, vec![0x00]);
+        machine.apply_sub(sub).unwrap();
+        assert!(machine.state().stack.peek(0).unwrap() != M256::zero());
+        assert_eq!(machine.state().return_data, Vec::<u8>::new());
+    }
+
+    #[test]
+    fn empty_stack_exits_cleanly() {
+        let owner = address(2);
+        for &fork in [Fork::Frontier, Fork::Cancun].iter() {
+            for opcode in 0..256usize {
+                let code = vec![opcode as u8];
+                let mut machine = SeqMachine::new(context(owner, address(1), address(1), code.clone()),
+                                                  block(), Patch::new(fork), 0);
+                commit(&mut machine, owner, code);
+                run(&mut machine);
+                match machine.status() {
+                    MachineStatus::ExitedOk | MachineStatus::ExitedErr(_) => (),
+                    _ => panic!("opcode {:#x} did not exit", opcode),
+                }
+            }
+        }
+
+        // Checked on its own, an instruction popping from an empty
+        // stack is a stack error rather than a panic.
+        let code = assemble("CALL").unwrap();
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code.clone()),
+                                          block(), Patch::new(Fork::Cancun), 0);
+        commit(&mut machine, owner, code);
+        match machine.check() {
+            Err(EvalError::Machine(MachineError::Stack(StackError::Underflow))) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn blockhash_commit_range() {
+        let owner = address(2);
+        let mut block = block();
+        block.number = M256::from(300u64);
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), Vec::new()),
+                                          block, Patch::new(Fork::Frontier), 0);
+
+        for &number in [300u64, 301, 43].iter() {
+            match machine.commit_blockhash(M256::from(number), M256::from(1u64)) {
+                Err(CommitError::InvalidBlockhash) => (),
+                _ => panic!(),
+            }
+        }
+        machine.commit_blockhash(M256::from(44u64), M256::from(1u64)).unwrap();
+        machine.commit_blockhash(M256::from(299u64), M256::from(1u64)).unwrap();
+    }
+
+    #[test]
+    fn push_immediates() {
+        for width in 1..33 {
+            let immediate: Vec<u8> = (0..width).map(|i| 0xff - i as u8).collect();
+            for len in 0..(width + 1) {
+                let mut code = vec![0x5f + width as u8];
+                code.extend_from_slice(&immediate[..len]);
+                let mut padded = immediate[..len].to_vec();
+                padded.resize(width, 0);
+
+                let mut pc = PC::new(&code);
+                assert_eq!(pc.read().unwrap(), Instruction::PUSH(width, M256::from(padded.as_slice())));
+                assert!(pc.is_end());
+            }
+        }
+    }
+
+    #[test]
+    fn indexed_logs() {
+        let owner = address(2);
+        let child = address(3);
+        // LOG0 of byte 0xa1, CALL 3, then LOG0 of byte 0xa3
+        let code = assemble("PUSH1 161 PUSH1 0 MSTORE8 PUSH1 1 PUSH1 0 LOG0
+                             PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 3 PUSH2 0x1000 CALL POP
+                             PUSH1 163 PUSH1 0 MSTORE8 PUSH1 1 PUSH1 0 LOG0").unwrap();
+        // LOG0 of byte 0xa2
+        let child_code = assemble("PUSH1 162 PUSH1 0 MSTORE8 PUSH1 1 PUSH1 0 LOG0").unwrap();
+        let mut vm = SeqVM::new(context(owner, address(1), address(1), code),
+                                block(), Patch::new(Fork::Homestead));
+        fire(&mut vm, &[(child, child_code)]);
+        match vm.status() {
+            VMStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+
+        let logs: Vec<(usize, Address, Vec<u8>)> = vm.indexed_logs()
+            .map(|(index, log)| (index, log.address, log.data.clone())).collect();
+        assert_eq!(logs, vec![(0, owner, vec![0xa1]), (1, child, vec![0xa2]), (2, owner, vec![0xa3])]);
+    }
+
+    #[test]
+    fn precompiled_call_gas() {
+        let owner = address(0x1000);
+        // SHA256 of 32 zero bytes costs 60 + 12. Passing 71 gas fails
+        // the call and consumes the gas, and passing 72 succeeds.
+        for &(gas, flag) in [(71u8, 0u64), (72u8, 1u64)].iter() {
+            // CALL 2 with the gas, 32 bytes of input at 0 and 32 bytes
+            // of output at 0
+            let code = vec![0x60, 0x20, 0x60, 0x00, 0x60, 0x20, 0x60, 0x00, 0x60, 0x00,
+                            0x60, 0x02, 0x60, gas, 0xf1];
+            let mut machine = SeqMachine::new(context(owner, address(1), address(1), code.clone()),
+                                              block(), Patch::new(Fork::Frontier), 0);
+            commit(&mut machine, owner, code);
+            run(&mut machine);
+            let mut sub = machine.derive(machine.pending_invoke().unwrap().clone());
+            run(&mut sub);
+            machine.apply_sub(sub).unwrap();
+            run(&mut machine);
+
+            assert_eq!(machine.state().stack.peek(0).unwrap(), M256::from(flag));
+            // Seven pushes, the CALL with a word of memory, and the
+            // forwarded gas either way.
+            assert_eq!(machine.state().total_used_gas(), Gas::from(7u64 * 3 + 40 + 3 + gas as u64));
+            if flag == 1 {
+                assert_eq!(machine.memory_dump()[0..32],
+                           read_hex("0x66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925")
+                           .unwrap()[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn unimplemented_precompiled_call() {
+        let owner = address(0x1000);
+        let code = call_code(0x05);
+        let call = |fork| {
+            let mut machine = SeqMachine::new(context(owner, address(1), address(1), code.clone()),
+                                              block(), Patch::new(fork), 0);
+            commit(&mut machine, owner, code.clone());
+            run(&mut machine);
+            let mut sub = machine.derive(machine.pending_invoke().unwrap().clone());
+            run(&mut sub);
+            machine.apply_sub(sub).unwrap();
+            run(&mut machine);
+            machine
+        };
+
+        // 0x05 is a plain account before Byzantium.
+        let machine = call(Fork::Homestead);
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::from(1u64));
+        assert_eq!(machine.state().total_used_gas(), Gas::from(7u64 * 3 + 40));
+
+        // MODEXP is not implemented, so the call fails and consumes
+        // the forwarded gas.
+        let machine = call(Fork::Byzantium);
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::zero());
+        assert_eq!(machine.state().total_used_gas(), Gas::from(7u64 * 3 + 700 + 0x1000));
+    }
+
+    #[test]
+    fn run_to_invoke() {
+        let owner = address(0x1000);
+        let target = address(0x0a);
+        let mut code = assemble("PUSH1 1").unwrap();
+        code.extend(call_code(0x0a));
+        code.extend(assemble("PUSH1 2").unwrap());
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code.clone()),
+                                          block(), Patch::new(Fork::Frontier), 0);
+
+        let mut required = Vec::new();
+        while let Err(RequireError::Account(address)) = machine.run_to_invoke() {
+            required.push(address);
+            commit(&mut machine, address, if address == owner { code.clone() } else { Vec::new() });
+        }
+        assert_eq!(required, vec![owner, target]);
+
+        // Stopped right after the CALL, with its placeholder pushed.
+        match machine.status() {
+            MachineStatus::InvokeCall(ref context, _) => assert_eq!(context.address, target),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().stack.len(), 2);
+
+        let mut sub = machine.derive(machine.pending_invoke().unwrap().clone());
+        sub.run_to_invoke().unwrap();
+        machine.apply_sub(sub).unwrap();
+        machine.run_to_invoke().unwrap();
+        match machine.status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::from(2u64));
+    }
+
+    #[test]
+    fn gas_is_not_clamped() {
+        let owner = address(2);
+        let code = assemble("GAS
+                             PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH2 0x1000 PUSH4 0xffffffff CALL").unwrap();
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code.clone()),
+                                          block(), Patch::new(Fork::EIP150), 0);
+        commit(&mut machine, owner, code);
+        run(&mut machine);
+
+        // GAS pushes everything left after paying for itself.
+        assert_eq!(machine.state().stack.peek(1).unwrap(), M256::from(100000u64 - 2));
+        // The CALL forwards all but one 64th of what is left after
+        // the seven pushes and the CALL itself.
+        let available = 100000u64 - 2 - 7 * 3 - 700;
+        match machine.status() {
+            MachineStatus::InvokeCall(ref context, _) =>
+                assert_eq!(context.gas_limit, Gas::from(available - available / 64)),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn memory_growths() {
+        let owner = address(2);
+        // MSTORE at 0, 0 again, 32, then 96
+        let code = assemble("PUSH1 1 PUSH1 0 MSTORE PUSH1 1 PUSH1 0 MSTORE PUSH1 1 PUSH1 32 MSTORE
+                             PUSH1 1 PUSH1 96 MSTORE").unwrap();
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code.clone()),
+                                          block(), Patch::new(Fork::Frontier), 0);
+        commit(&mut machine, owner, code.clone());
+        run(&mut machine);
+        assert_eq!(machine.memory_growths(), &[]);
+
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code.clone()),
+                                          block(), Patch::new(Fork::Frontier), 0);
+        machine.enable_memory_growths();
+        commit(&mut machine, owner, code);
+        run(&mut machine);
+        let growth = |position, old_size, new_size| MemoryGrowth {
+            position: position, old_size: Gas::from(old_size), new_size: Gas::from(new_size),
+        };
+        assert_eq!(machine.memory_growths(),
+                   &[growth(4, 0u64, 32u64), growth(14, 32, 64), growth(19, 64, 128)]);
+    }
+
+    #[test]
+    fn call_overflowing_callstack() {
+        let owner = address(0x1000);
+        let code = call_code(0x0a);
+        let invoking = || {
+            let mut patch = Patch::new(Fork::Frontier);
+            patch.callstack_limit = 2;
+            let mut machine = SeqMachine::new(context(owner, address(1), address(1), code.clone()),
+                                              block(), patch, 1);
+            commit(&mut machine, owner, code.clone());
+            run(&mut machine);
+            machine
+        };
+
+        let mut machine = invoking();
+
+        let mut sub = machine.derive(machine.pending_invoke().unwrap().clone());
+        sub.step().unwrap();
+        match sub.status() {
+            MachineStatus::ExitedErr(MachineError::CallstackOverflow) => (),
+            _ => panic!(),
+        }
+        machine.apply_sub(sub).unwrap();
+        // The call fails, but the gas given to it is returned.
+        assert_eq!(machine.state().stack.peek(0).unwrap(), M256::zero());
+        assert_eq!(machine.state().total_used_gas(), Gas::from(7u64 * 3 + 40));
+
+        let mut skipped = invoking();
+        skipped.skip_invoke();
+        match skipped.status() {
+            MachineStatus::Running => (),
+            _ => panic!(),
+        }
+        assert_eq!(skipped.state().stack.peek(0).unwrap(), M256::from(1u64));
+        assert_eq!(skipped.state().total_used_gas(), Gas::from(7u64 * 3 + 40));
+    }
+
+    #[test]
+    fn custom_opcode_recorded() {
+        let owner = address(2);
+        // 0x0c, PUSH1 1, ADD
+        let code = vec![0x0c, 0x60, 0x01, 0x01];
+        let mut machine = SeqMachine::new(context(owner, address(1), address(1), code.clone()),
+                                          block(), Patch::new(Fork::Frontier), 0);
+        machine.set_custom_opcodes(Arc::new(PushConstant));
+        machine.enable_trace();
+        machine.enable_opcode_counts();
+        commit(&mut machine, owner, code);
+        run(&mut machine);
+
+        let trace = machine.trace();
+        assert_eq!(trace.len(), 3);
+        assert_eq!(trace[0].position, 0);
+        assert_eq!(trace[0].opcode, 0x0c);
+        assert_eq!(trace[0].gas, Gas::from(100000u64));
+        assert_eq!(trace[0].stack_len, 0);
+        assert_eq!(trace[1].position, 1);
+        assert_eq!(trace[1].gas, Gas::from(100000u64 - 2));
+        assert_eq!(trace[1].stack_len, 1);
+        assert_eq!(machine.opcode_count(Opcode::INVALID), 1);
+        assert_eq!(machine.opcode_count(Opcode::PUSH(1)), 1);
+        assert_eq!(machine.opcode_count(Opcode::ADD), 1);
+    }
 }
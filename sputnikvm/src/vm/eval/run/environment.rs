@@ -1,21 +1,26 @@
 //! Environment instructions
 
+use utils::bigint::M256;
 use vm::{Memory, Storage};
+use vm::eval::utils::checked_usize;
 use super::State;
 
 pub fn calldataload<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State<M, S>) {
     pop!(state, index);
-    let index: Option<usize> = if index > usize::max_value().into() {
-        None
-    } else {
-        Some(index.into())
-    };
-    let data = state.context.data.as_slice();
     let mut load: [u8; 32] = [0u8; 32];
-    for i in 0..32 {
-        if index.is_some() && index.unwrap() + i < data.len() {
-            load[i] = data[index.unwrap() + i];
-        }
+    match checked_usize(index) {
+        Some(index) => load.copy_from_slice(&state.context.calldata_slice(index, 32)),
+        None => (),
     }
     push!(state, load.into());
 }
+
+pub fn blobhash<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State<M, S>) {
+    pop!(state, index);
+    let hash = match checked_usize(index) {
+        Some(index) if index < state.context.blob_versioned_hashes.len() =>
+            state.context.blob_versioned_hashes[index],
+        _ => M256::zero(),
+    };
+    push!(state, hash);
+}
@@ -1,19 +1,36 @@
 //! Flow control instructions.
 
+use utils::bigint::M256;
 use vm::{Memory, Storage};
 use super::State;
 
 pub fn sload<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State<M, S>) {
     pop!(state, index);
+    state.account_state.touch_storage(state.context.address, index);
     let value = state.account_state.storage(state.context.address).unwrap().read(index);
     push!(state, value);
 }
 
 pub fn sstore<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State<M, S>) {
     pop!(state, index, value);
+    state.account_state.touch_storage(state.context.address, index);
     state.account_state.storage_mut(state.context.address).unwrap().write(index, value).unwrap();
 }
 
+pub fn tload<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State<M, S>) {
+    pop!(state, index);
+    let value = match state.transient_storage.get(&(state.context.address, index)) {
+        Some(&value) => value,
+        None => M256::zero(),
+    };
+    push!(state, value);
+}
+
+pub fn tstore<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State<M, S>) {
+    pop!(state, index, value);
+    state.transient_storage.insert((state.context.address, index), value);
+}
+
 pub fn mload<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State<M, S>) {
     pop!(state, index);
     let value = state.memory.read(index);
@@ -49,13 +49,15 @@ mod flow;
 mod environment;
 mod system;
 
+pub use self::system::{EMPTY_CODE_HASH, create_address};
+
 use utils::gas::Gas;
 use utils::bigint::{M256, MI256};
 use utils::address::Address;
 use std::ops::{Add, Sub, Mul, Div, Rem, BitAnd, BitOr, BitXor};
 use vm::{Memory, Storage, Instruction};
 use super::{State, Control};
-use super::utils::{copy_from_memory, copy_into_memory};
+use super::utils::{copy_from_memory, copy_into_memory, saturating_m256};
 
 #[allow(unused_variables)]
 /// Run an instruction.
@@ -108,17 +110,18 @@ pub fn run_opcode<M: Memory + Default, S: Storage + Default + Clone>(pc: (Instru
                                                     state.context.code.as_slice(),
                                                     memory_index, code_index, len);
                                    None },
-        Instruction::GASPRICE => { push!(state, state.context.gas_price.into()); None },
+        Instruction::GASPRICE => { push!(state, saturating_m256(state.context.gas_price)); None },
         Instruction::EXTCODESIZE => { pop!(state, address: Address);
-                                      push!(state,
-                                            state.account_state.code(address).unwrap().len().into());
+                                      let len = state.external_code(address).unwrap().len();
+                                      push!(state, len.into());
                                       None },
         Instruction::EXTCODECOPY => { pop!(state, address: Address);
                                       pop!(state, memory_index, code_index, len);
-                                      copy_into_memory(&mut state.memory,
-                                                       state.account_state.code(address).unwrap(),
+                                      let code = state.external_code(address).unwrap().to_vec();
+                                      copy_into_memory(&mut state.memory, &code,
                                                        memory_index, code_index, len);
                                       None },
+        Instruction::EXTCODEHASH => { system::extcodehash(state); None },
 
         Instruction::BLOCKHASH => { pop!(state, number);
                                     let current_number = state.block.number;
@@ -131,8 +134,17 @@ pub fn run_opcode<M: Memory + Default, S: Storage + Default + Clone>(pc: (Instru
         Instruction::COINBASE => { push!(state, state.block.coinbase.into()); None },
         Instruction::TIMESTAMP => { push!(state, state.block.timestamp); None },
         Instruction::NUMBER => { push!(state, state.block.number); None },
+        // Post-merge this is PREVRANDAO, which the client stores in the
+        // same header field.
         Instruction::DIFFICULTY => { push!(state, state.block.difficulty); None },
-        Instruction::GASLIMIT => { push!(state, state.block.gas_limit.into()); None },
+        Instruction::GASLIMIT => { push!(state, saturating_m256(state.block.gas_limit)); None },
+        Instruction::CHAINID => { push!(state, state.patch.chain_id.into()); None },
+        Instruction::SELFBALANCE => { let address = state.context.address;
+                                      push!(state, state.account_state.balance(address).unwrap().into());
+                                      None },
+        Instruction::BASEFEE => { push!(state, saturating_m256(state.block.base_fee)); None },
+        Instruction::BLOBHASH => { environment::blobhash(state); None },
+        Instruction::BLOBBASEFEE => { push!(state, saturating_m256(state.block.blob_base_fee)); None },
 
         Instruction::POP => { state.stack.pop().unwrap(); None },
         Instruction::MLOAD => { flow::mload(state); None },
@@ -148,11 +160,17 @@ pub fn run_opcode<M: Memory + Default, S: Storage + Default + Clone>(pc: (Instru
                                     None
                                 } },
         Instruction::PC => { push!(state, pc.1.into()); None },
-        Instruction::MSIZE => { push!(state, (state.memory_cost * Gas::from(32u64)).into()); None },
-        Instruction::GAS => { push!(state, after_gas.into()); None },
+        Instruction::MSIZE => { push!(state, saturating_m256(state.memory_cost * Gas::from(32u64))); None },
+        // All the gas left once GAS is paid for. After EIP150 a CALL
+        // forwards at most 63/64 of what is left at that point, but
+        // the clamp is applied by the call, not reflected here.
+        Instruction::GAS => { push!(state, saturating_m256(after_gas)); None },
         Instruction::JUMPDEST => None,
+        Instruction::TLOAD => { flow::tload(state); None },
+        Instruction::TSTORE => { flow::tstore(state); None },
 
-        Instruction::PUSH(v) => { push!(state, v); None }
+        Instruction::PUSH0 => { push!(state, M256::zero()); None },
+        Instruction::PUSH(_, v) => { push!(state, v); None }
 
         Instruction::DUP(v) => { let val = state.stack.peek(v-1).unwrap();
                                  push!(state, val);
@@ -173,7 +191,10 @@ pub fn run_opcode<M: Memory + Default, S: Storage + Default + Clone>(pc: (Instru
         Instruction::RETURN => { pop!(state, start, len);
                                  state.out = copy_from_memory(&mut state.memory, start, len);
                                  Some(Control::Stop) },
-        Instruction::DELEGATECALL => unimplemented!(),
+        Instruction::DELEGATECALL => { system::delegatecall(state, after_gas)
+                                       .and_then(|ret| Some(Control::InvokeCall(ret.0, ret.1))) },
+        Instruction::STATICCALL => { system::staticcall(state, after_gas)
+                                     .and_then(|ret| Some(Control::InvokeCall(ret.0, ret.1))) },
         Instruction::SUICIDE => { system::suicide(state); Some(Control::Stop) },
     }
 }
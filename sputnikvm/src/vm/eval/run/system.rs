@@ -5,19 +5,72 @@ use utils::bigint::{U256, M256};
 use utils::gas::Gas;
 use vm::{Memory, Storage, Log, Context};
 use super::State;
-use rlp::RlpStream;
 
-use crypto::sha3::Sha3;
-use crypto::digest::Digest;
-use vm::eval::utils::copy_from_memory;
+use std::cmp::min;
+use tiny_keccak::keccak256;
+use vm::eval::utils::{copy_from_memory, memory_slice, l64};
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+/// Keccak256 hash of empty code, returned by `EXTCODEHASH` for an
+/// existing account without code.
+pub const EMPTY_CODE_HASH: [u8; 32] = [
+    0xc5, 0xd2, 0x46, 0x01, 0x86, 0xf7, 0x23, 0x3c,
+    0x92, 0x7e, 0x7d, 0xb2, 0xdc, 0xc7, 0x03, 0xc0,
+    0xe5, 0x00, 0xb6, 0x53, 0xca, 0x82, 0x27, 0x3b,
+    0x7b, 0xfa, 0xd8, 0x04, 0x5d, 0x85, 0xa4, 0x70,
+];
+
+/// Address of the contract created by `creator` with the given
+/// nonce.
+pub fn create_address(creator: Address, nonce: M256) -> Address {
+    // The RLP list of the creator and the nonce, both encoded without
+    // leading zero bytes. It is always shorter than 56 bytes.
+    let creator: [u8; 20] = creator.into();
+    let nonce: [u8; 32] = nonce.into();
+    let mut items = Vec::new();
+    for value in [&creator[..], &nonce[..]].iter() {
+        let value = &value[value.iter().take_while(|&&v| v == 0).count()..];
+        if value.len() != 1 || value[0] >= 0x80 {
+            items.push(0x80 + value.len() as u8);
+        }
+        items.extend_from_slice(value);
+    }
+    let mut rlp = vec![0xc0 + items.len() as u8];
+    rlp.extend(items);
+    Address::from(M256::from(keccak256(&rlp)))
+}
+
+/// Gas forwarded to a sub call after EIP150. This is the requested
+/// gas, but at most all but one 64th of the available gas, so it is
+/// zero when nothing is available.
+fn call_gas(requested: Gas, available: Gas) -> Gas {
+    min(requested, l64(available))
+}
 
 pub fn suicide<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State<M, S>) {
     pop!(state, address: Address);
     let balance = state.account_state.balance(state.context.address).unwrap();
-    state.account_state.increase_balance(address, balance);
+    // Checked in `check_opcode`.
+    state.account_state.increase_balance(address, balance).unwrap();
     state.account_state.remove(state.context.address).unwrap();
 }
 
+pub fn extcodehash<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State<M, S>) {
+    pop!(state, address: Address);
+    let code = state.account_state.code(address).unwrap();
+    // An empty account (EIP161) is considered not existing.
+    if code.len() == 0 && state.account_state.nonce(address).unwrap() == M256::zero() &&
+        state.account_state.balance(address).unwrap() == U256::zero()
+    {
+        push!(state, M256::zero());
+    } else if code.len() == 0 {
+        push!(state, M256::from(EMPTY_CODE_HASH.as_ref()));
+    } else {
+        push!(state, M256::from(keccak256(code).as_ref()));
+    }
+}
+
 pub fn log<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State<M, S>, topic_len: usize) {
     pop!(state, index, len);
     let data = copy_from_memory(&state.memory, index, len);
@@ -35,11 +88,7 @@ pub fn log<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State<
 
 pub fn sha3<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State<M, S>) {
     pop!(state, from, len);
-    let data = copy_from_memory(&state.memory, from, len);
-    let mut sha3 = Sha3::keccak256();
-    sha3.input(data.as_slice());
-    let mut ret = [0u8; 32];
-    sha3.result(&mut ret);
+    let ret = keccak256(&memory_slice(&state.memory, from, len));
     push!(state, M256::from(ret.as_ref()));
 }
 
@@ -53,30 +102,41 @@ pub fn create<M: Memory + Default, S: Storage + Default + Clone>(state: &mut Sta
 
     let init = copy_from_memory(&state.memory, init_start, init_len);
     let nonce = state.account_state.nonce(state.context.address).unwrap();
-    let mut rlp = RlpStream::new();
-    rlp.begin_list(2);
-    rlp.append(&state.context.address);
-    rlp.append(&nonce);
-    let mut address_array = [0u8; 32];
-    let mut sha3 = Sha3::keccak256();
-    sha3.input(rlp.out().as_slice());
-    sha3.result(&mut address_array);
-    let address = Address::from(M256::from(address_array));
+    let address = create_address(state.context.address, nonce);
+    // The nonce is bumped even if the creation later fails.
+    state.account_state.set_nonce(state.context.address, nonce + M256::from(1u64)).unwrap();
+    let gas_limit = if state.patch.eip150() {
+        l64(after_gas)
+    } else {
+        after_gas
+    };
+    // Creation fails without running the init code if an account
+    // with nonce or code already exists at the target address, and
+    // consumes the gas it would have been given (EIP-684).
+    if state.account_state.nonce(address).unwrap() != M256::zero() ||
+        state.account_state.code(address).unwrap().len() != 0
+    {
+        state.used_gas = state.used_gas + gas_limit;
+        push!(state, M256::zero());
+        return None;
+    }
     let context = Context {
         address: address,
         caller: state.context.address,
         code: init,
         data: Vec::new(),
-        gas_limit: after_gas,
+        gas_limit: gas_limit,
         gas_price: state.context.gas_price,
         origin: state.context.origin,
         value: value,
+        is_static: false,
+        is_create: true,
+        blob_versioned_hashes: state.context.blob_versioned_hashes.clone(),
     };
     push!(state, address.into());
     Some(context)
 }
 
-#[allow(unused_variables)]
 pub fn call<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State<M, S>, stipend_gas: Gas, after_gas: Gas) -> Option<(Context, (M256, M256))> {
     pop!(state, gas: Gas, to: Address, value: U256);
     pop!(state, in_start, in_len, out_start, out_len);
@@ -86,6 +146,12 @@ pub fn call<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State
     }
 
     let input = copy_from_memory(&state.memory, in_start, in_len);
+    let gas = if state.patch.eip150() {
+        call_gas(gas, after_gas - stipend_gas)
+    } else {
+        gas
+    };
+    // The stipend is only non-zero for value transfers.
     let gas_limit = gas + stipend_gas;
     let context = Context {
         address: to,
@@ -96,12 +162,15 @@ pub fn call<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State
         gas_price: state.context.gas_price,
         origin: state.context.origin,
         value: value,
+        is_static: state.context.is_static,
+        is_create: false,
+        blob_versioned_hashes: state.context.blob_versioned_hashes.clone(),
     };
-    push!(state, M256::zero());
+    // Replaced by zero in `apply_call` if the sub runtime fails.
+    push!(state, M256::from(1u64));
     Some((context, (out_start, out_len)))
 }
 
-#[allow(unused_variables)]
 pub fn callcode<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State<M, S>, stipend_gas: Gas, after_gas: Gas) -> Option<(Context, (M256, M256))> {
     pop!(state, gas: Gas, to: Address, value: U256);
     pop!(state, in_start, in_len, out_start, out_len);
@@ -111,6 +180,12 @@ pub fn callcode<M: Memory + Default, S: Storage + Default + Clone>(state: &mut S
     }
 
     let input = copy_from_memory(&state.memory, in_start, in_len);
+    let gas = if state.patch.eip150() {
+        call_gas(gas, after_gas - stipend_gas)
+    } else {
+        gas
+    };
+    // The stipend is only non-zero for value transfers.
     let gas_limit = gas + stipend_gas;
     let context = Context {
         address: state.context.address,
@@ -121,7 +196,157 @@ pub fn callcode<M: Memory + Default, S: Storage + Default + Clone>(state: &mut S
         gas_price: state.context.gas_price,
         origin: state.context.origin,
         value: value,
+        is_static: state.context.is_static,
+        is_create: false,
+        blob_versioned_hashes: state.context.blob_versioned_hashes.clone(),
+    };
+    // Replaced by zero in `apply_call` if the sub runtime fails.
+    push!(state, M256::from(1u64));
+    Some((context, (out_start, out_len)))
+}
+
+pub fn delegatecall<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State<M, S>, after_gas: Gas) -> Option<(Context, (M256, M256))> {
+    pop!(state, gas: Gas, to: Address);
+    pop!(state, in_start, in_len, out_start, out_len);
+
+    let input = copy_from_memory(&state.memory, in_start, in_len);
+    let gas = if state.patch.eip150() {
+        call_gas(gas, after_gas)
+    } else {
+        gas
+    };
+    // The code of `to` runs in the current context, keeping its
+    // caller and value. No value is transferred.
+    let context = Context {
+        address: state.context.address,
+        caller: state.context.caller,
+        code: state.account_state.code(to).unwrap().into(),
+        data: input,
+        gas_limit: gas,
+        gas_price: state.context.gas_price,
+        origin: state.context.origin,
+        value: state.context.value,
+        is_static: state.context.is_static,
+        is_create: false,
+        blob_versioned_hashes: state.context.blob_versioned_hashes.clone(),
+    };
+    // Replaced by zero in `apply_call` if the sub runtime fails.
+    push!(state, M256::from(1u64));
+    Some((context, (out_start, out_len)))
+}
+
+pub fn staticcall<M: Memory + Default, S: Storage + Default + Clone>(state: &mut State<M, S>, after_gas: Gas) -> Option<(Context, (M256, M256))> {
+    pop!(state, gas: Gas, to: Address);
+    pop!(state, in_start, in_len, out_start, out_len);
+
+    let input = copy_from_memory(&state.memory, in_start, in_len);
+    let context = Context {
+        address: to,
+        caller: state.context.address,
+        code: state.account_state.code(to).unwrap().into(),
+        data: input,
+        gas_limit: call_gas(gas, after_gas),
+        gas_price: state.context.gas_price,
+        origin: state.context.origin,
+        value: U256::zero(),
+        is_static: true,
+        is_create: false,
+        blob_versioned_hashes: state.context.blob_versioned_hashes.clone(),
     };
-    push!(state, M256::zero());
+    // Replaced by zero in `apply_call` if the sub runtime fails.
+    push!(state, M256::from(1u64));
     Some((context, (out_start, out_len)))
 }
+
+#[cfg(test)]
+mod tests {
+    use utils::bigint::{M256, U256};
+    use utils::gas::Gas;
+    use utils::address::Address;
+    use utils::asm::assemble;
+    use vm::{Machine, Context, BlockHeader, Patch, Fork, AccountCommitment, SeqMemory, HashMapStorage};
+    use vm::errors::RequireError;
+    use crypto::sha3::Sha3;
+    use crypto::digest::Digest;
+    use super::{call_gas, EMPTY_CODE_HASH};
+
+    /// Gas forwarded by a CALL of 1000 gas with the given value.
+    fn call_forwarded_gas(fork: Fork, value: u64) -> Gas {
+        let owner = Address::from(M256::from(2u64));
+        let code = assemble(&format!("
+            PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0
+            PUSH {}
+            PUSH1 0x30
+            PUSH2 1000
+            CALL
+        ", value)).unwrap();
+        let context = Context {
+            address: owner,
+            caller: owner,
+            code: code,
+            data: Vec::new(),
+            gas_limit: Gas::from(100000u64),
+            gas_price: Gas::zero(),
+            origin: owner,
+            value: U256::zero(),
+            is_static: false,
+            is_create: false,
+            blob_versioned_hashes: Vec::new(),
+        };
+        let block = BlockHeader {
+            coinbase: Address::default(),
+            timestamp: M256::zero(),
+            number: M256::zero(),
+            difficulty: M256::zero(),
+            gas_limit: Gas::from(1000000u64),
+            base_fee: Gas::zero(),
+            blob_base_fee: Gas::zero(),
+        };
+        let mut machine: Machine<SeqMemory, HashMapStorage> =
+            Machine::new(context, block, Patch::new(fork), 0);
+        while machine.pending_invoke().is_none() {
+            match machine.step() {
+                Ok(()) => (),
+                Err(RequireError::Account(address)) |
+                Err(RequireError::AccountCode(address)) => {
+                    machine.commit_account(AccountCommitment::Full {
+                        nonce: M256::zero(),
+                        address: address,
+                        balance: U256::from(1000000u64),
+                        storage: HashMapStorage::default(),
+                        code: Vec::new(),
+                    }).unwrap();
+                },
+                Err(error) => panic!("unexpected require: {:?}", error),
+            }
+        }
+        machine.pending_invoke().unwrap().gas_limit
+    }
+
+    #[test]
+    fn empty_code_hash() {
+        let mut hash = [0u8; 32];
+        let mut sha3 = Sha3::keccak256();
+        sha3.input(&[]);
+        sha3.result(&mut hash);
+        assert_eq!(hash, EMPTY_CODE_HASH);
+    }
+
+    #[test]
+    fn call_gas_all_but_one_64th() {
+        let requested = Gas::from(1000u64);
+        assert_eq!(call_gas(requested, Gas::from(0u64)), Gas::from(0u64));
+        assert_eq!(call_gas(requested, Gas::from(63u64)), Gas::from(63u64));
+        assert_eq!(call_gas(requested, Gas::from(64u64)), Gas::from(63u64));
+        assert_eq!(call_gas(requested, Gas::from(65u64)), Gas::from(64u64));
+        assert_eq!(call_gas(Gas::from(10u64), Gas::from(65u64)), Gas::from(10u64));
+    }
+
+    #[test]
+    fn call_stipend_only_with_value() {
+        for &fork in [Fork::Frontier, Fork::EIP150].iter() {
+            assert_eq!(call_forwarded_gas(fork, 0), Gas::from(1000u64));
+            assert_eq!(call_forwarded_gas(fork, 1), Gas::from(1000u64 + 2300));
+        }
+    }
+}
@@ -1,14 +1,37 @@
 //! Eval utilities
 
-use utils::bigint::M256;
+use std::borrow::Cow;
+use utils::bigint::{M256, U256};
 use utils::gas::Gas;
 use vm::Memory;
 use vm::errors::MachineError;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
 
 pub fn l64(gas: Gas) -> Gas {
     gas - gas / Gas::from(64u64)
 }
 
+/// Convert an index to `usize`, returning `None` instead of
+/// truncating when it does not fit.
+pub fn checked_usize(value: M256) -> Option<usize> {
+    if value > M256::from(usize::max_value()) {
+        None
+    } else {
+        Some(value.into())
+    }
+}
+
+/// Convert gas to a stack word, saturating instead of panicking when
+/// it does not fit into 256 bits.
+pub fn saturating_m256(gas: Gas) -> M256 {
+    if gas > Gas::from(U256::max_value()) {
+        M256::from(U256::max_value())
+    } else {
+        gas.into()
+    }
+}
+
 pub fn check_range(start: M256, len: M256) -> Result<(), MachineError> {
     if start + len < start {
         Err(MachineError::InvalidRange)
@@ -17,25 +40,38 @@ pub fn check_range(start: M256, len: M256) -> Result<(), MachineError> {
     }
 }
 
+pub fn check_memory_read_range(start: M256, len: M256) -> Result<(), MachineError> {
+    check_range(start, len)?;
+    // Memory can never be expanded past `usize`, so such a read
+    // always runs out of gas.
+    if len != M256::zero() && checked_usize(start + len).is_none() {
+        return Err(MachineError::EmptyMemoryGas);
+    }
+    Ok(())
+}
+
 pub fn check_memory_write_range<M: Memory>(memory: &M, start: M256, len: M256) -> Result<(), MachineError> {
     check_range(start, len)?;
-    let mut i = start;
-    while i < start + len {
-        memory.check_write(i)?;
-        i = i + M256::from(1u64);
+    // Memory can never be expanded past `usize`, so such a write
+    // always runs out of gas.
+    if len != M256::zero() && checked_usize(start + len).is_none() {
+        return Err(MachineError::EmptyMemoryGas);
     }
+    memory.check_write_range(start, len)?;
     Ok(())
 }
 
-pub fn copy_from_memory<M: Memory>(memory: &M, start: M256, len: M256) -> Vec<u8> {
-    let mut result: Vec<u8> = Vec::new();
-    let mut i = start;
-    while i < start + len {
-        result.push(memory.read_raw(i));
-        i = i + M256::from(1u64);
+/// Read a memory range whose bounds were validated by
+/// `check_memory_read_range`.
+pub fn memory_slice<M: Memory>(memory: &M, start: M256, len: M256) -> Cow<[u8]> {
+    if len == M256::zero() {
+        return Cow::Borrowed(&[]);
     }
+    memory.slice(start.into(), len.into())
+}
 
-    result
+pub fn copy_from_memory<M: Memory>(memory: &M, start: M256, len: M256) -> Vec<u8> {
+    memory_slice(memory, start, len).into_owned()
 }
 
 pub fn copy_into_memory<M: Memory>(memory: &mut M, values: &[u8], start: M256, value_start: M256, len: M256) {
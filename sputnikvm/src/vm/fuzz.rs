@@ -0,0 +1,80 @@
+//! Self-contained entry point for fuzzing
+
+use utils::address::Address;
+use utils::bigint::{M256, U256};
+use utils::gas::Gas;
+use super::{SeqVM, VMStatus, Context, BlockHeader, Patch, Fork, AccountCommitment,
+            HashMapStorage};
+use super::errors::RequireError;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone)]
+/// Result of `run_bytecode`.
+pub struct RunResult {
+    /// Final status, either `ExitedOk` or `ExitedErr`.
+    pub status: VMStatus,
+    /// Gas left after execution, before refunds.
+    pub available_gas: Gas,
+    /// Returned data.
+    pub out: Vec<u8>,
+}
+
+/// Run `code` with the given call data and gas limit under the latest
+/// patch, until it exits. Every other account is empty and every
+/// block hash is zero.
+///
+/// This never panics, whatever the input is, so it is the entry
+/// point used by the fuzz targets. A panic here is a bug.
+pub fn run_bytecode(code: &[u8], calldata: &[u8], gas: u64) -> RunResult {
+    let address = Address::from(M256::from(0x1000u64));
+    let caller = Address::from(M256::from(0x2000u64));
+    let context = Context {
+        address: address,
+        caller: caller,
+        code: code.into(),
+        data: calldata.into(),
+        gas_limit: Gas::from(gas),
+        gas_price: Gas::zero(),
+        origin: caller,
+        value: U256::zero(),
+        is_static: false,
+        is_create: false,
+        blob_versioned_hashes: Vec::new(),
+    };
+    let block = BlockHeader {
+        coinbase: Address::default(),
+        timestamp: M256::zero(),
+        number: M256::zero(),
+        difficulty: M256::zero(),
+        gas_limit: Gas::from(gas),
+        base_fee: Gas::zero(),
+        blob_base_fee: Gas::zero(),
+    };
+
+    let mut vm = SeqVM::new(context, block, Patch::new(Fork::Cancun));
+    loop {
+        match vm.fire() {
+            Ok(()) => break,
+            Err(RequireError::Account(required)) |
+            Err(RequireError::AccountCode(required)) => {
+                vm.commit_account(AccountCommitment::Full {
+                    nonce: M256::zero(),
+                    address: required,
+                    balance: U256::zero(),
+                    storage: HashMapStorage::default(),
+                    code: if required == address { code.into() } else { Vec::new() },
+                }).unwrap();
+            },
+            Err(RequireError::Blockhash(number)) => {
+                vm.commit_blockhash(number, M256::zero()).unwrap();
+            },
+        }
+    }
+
+    RunResult {
+        status: vm.status(),
+        available_gas: vm.available_gas(),
+        out: vm.out().into(),
+    }
+}
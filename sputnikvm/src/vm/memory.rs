@@ -1,8 +1,11 @@
 //! VM memory representation
 
+use std::borrow::Cow;
 use utils::bigint::M256;
 
 use super::errors::MemoryError;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
 
 /// Represent a memory in EVM. Read should always succeed. Write can
 /// fall.
@@ -12,6 +15,18 @@ pub trait Memory {
     /// on this index should succeed.
     fn check_write(&self, index: M256) -> Result<(), MemoryError>;
 
+    /// Check whether writes on every index of `start..(start + len)`
+    /// would succeed. Implementations should override this if the
+    /// check can be done without visiting every index.
+    fn check_write_range(&self, start: M256, len: M256) -> Result<(), MemoryError> {
+        let mut i = start;
+        while i < start + len {
+            self.check_write(i)?;
+            i = i + M256::from(1u64);
+        }
+        Ok(())
+    }
+
     /// Write value into the index.
     fn write(&mut self, index: M256, value: M256) -> Result<(), MemoryError>;
     /// Write only one byte value into the index.
@@ -20,6 +35,19 @@ pub trait Memory {
     fn read(&self, index: M256) -> M256;
     /// Read only one byte value from the index.
     fn read_raw(&self, index: M256) -> u8;
+    /// Read `len` bytes starting at `offset`. Implementations backed by
+    /// contiguous bytes should override this to return a borrowed
+    /// slice.
+    fn slice(&self, offset: usize, len: usize) -> Cow<[u8]> {
+        Cow::Owned((offset..(offset + len)).map(|i| self.read_raw(M256::from(i))).collect())
+    }
+
+    /// Clear the memory so that every byte reads as zero.
+    /// Implementations should override this to keep their
+    /// allocation.
+    fn clear(&mut self) where Self: Sized + Default {
+        *self = Self::default();
+    }
 }
 
 /// A sequencial memory. It uses Rust's `Vec` for internal
@@ -46,6 +74,13 @@ impl Memory for SeqMemory {
         }
     }
 
+    fn check_write_range(&self, start: M256, len: M256) -> Result<(), MemoryError> {
+        if len == M256::zero() {
+            return Ok(());
+        }
+        self.check_write(start + len - M256::from(1u64))
+    }
+
     fn write(&mut self, index: M256, value: M256) -> Result<(), MemoryError> {
         let end = index + 32.into();
         if end > M256::from(usize::max_value()) {
@@ -96,4 +131,22 @@ impl Memory for SeqMemory {
 
         self.memory[index]
     }
+
+    fn slice(&self, offset: usize, len: usize) -> Cow<[u8]> {
+        let end = offset + len;
+        if end <= self.memory.len() {
+            Cow::Borrowed(&self.memory[offset..end])
+        } else {
+            let mut ret = vec![0u8; len];
+            if offset < self.memory.len() {
+                let available = self.memory.len() - offset;
+                ret[..available].copy_from_slice(&self.memory[offset..]);
+            }
+            Cow::Owned(ret)
+        }
+    }
+
+    fn clear(&mut self) {
+        self.memory.clear();
+    }
 }
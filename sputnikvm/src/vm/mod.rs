@@ -18,28 +18,44 @@ mod storage;
 mod params;
 mod eval;
 mod commit;
+mod trace;
+mod structlog;
+mod precompiled;
+mod fuzz;
 pub mod errors;
 
 pub use self::memory::{Memory, SeqMemory};
 pub use self::stack::Stack;
 pub use self::pc::{PC, Instruction};
 pub use self::storage::{Storage, HashMapStorage};
-pub use self::params::{Context, BlockHeader, Log, Patch};
-pub use self::eval::{State, Machine, MachineStatus};
-pub use self::commit::{AccountCommitment, Account};
+pub use self::params::{Context, BlockHeader, BlockHeaderBuilder, Log, Patch, Fork, GasSchedule};
+pub use self::eval::{State, StateSnapshot, SubCall, FrameReturn, Machine, MachineStatus, Steps,
+                     Control, CustomOpcode, MemoryGrowth,
+                     intrinsic_gas, EMPTY_CODE_HASH};
+pub use self::commit::{AccountCommitment, Account, AccountChange, StateDiff};
+pub use self::trace::{TraceStep, TRACE_STEP_LEN, serialize_trace};
+pub use self::structlog::{StructLog, StructLogConfig, StructLogger};
+pub use self::fuzz::{run_bytecode, RunResult};
+pub use self::precompiled::ecrecover;
 
-use std::collections::hash_map;
+#[cfg(feature = "std")]
+use utils::collections::Map;
+use utils::collections::MapValues;
+use std::cmp::min;
+use std::{iter, slice};
 use utils::bigint::M256;
 use utils::gas::Gas;
 use utils::address::Address;
-use self::errors::{RequireError, CommitError, VMError};
+use self::errors::{RequireError, CommitError, MachineError, VMError};
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
 
 /// A sequencial VM. It uses sequencial memory representation and hash
 /// map storage for accounts.
 pub type SeqVM = VM<SeqMemory, HashMapStorage>;
 
 /// A VM that executes using a context and block information.
-pub struct VM<M, S>(Vec<Machine<M, S>>, Vec<Context>);
+pub struct VM<M, S>(Vec<Machine<M, S>>, Vec<Context>, Option<VMError>);
 
 #[derive(Debug, Clone)]
 /// VM Status
@@ -58,7 +74,15 @@ impl<M: Memory + Default, S: Storage + Default + Clone> VM<M, S> {
     pub fn new(context: Context, block: BlockHeader, patch: Patch) -> VM<M, S> {
         let mut machines = Vec::new();
         machines.push(Machine::new(context, block, patch, 1));
-        VM(machines, Vec::new())
+        VM(machines, Vec::new(), None)
+    }
+
+    /// Pre-warm the accounts and storage slots of the transaction's
+    /// access list (EIP-2930). Only has an effect on gas costs with
+    /// the Berlin patch.
+    pub fn with_access_list(mut self, access_list: &[(Address, Vec<M256>)]) -> VM<M, S> {
+        self.0[0].warm_access_list(access_list);
+        self
     }
 
     /// Commit an account information to this VM. This should only be
@@ -81,6 +105,9 @@ impl<M: Memory + Default, S: Storage + Default + Clone> VM<M, S> {
 
     /// Returns the current status of the VM.
     pub fn status(&self) -> VMStatus {
+        if let Some(ref err) = self.2 {
+            return VMStatus::ExitedErr(err.clone());
+        }
         match self.0[0].status() {
             MachineStatus::Running | MachineStatus::InvokeCreate(_) | MachineStatus::InvokeCall(_, _) => VMStatus::Running,
             MachineStatus::ExitedOk => VMStatus::ExitedOk,
@@ -91,10 +118,15 @@ impl<M: Memory + Default, S: Storage + Default + Clone> VM<M, S> {
     /// Run one instruction and return. If it succeeds, VM status can
     /// still be `Running`. If the call stack has more than one items,
     /// this will only executes the last items' one single
-    /// instruction.
+    /// instruction. If the call stack is inconsistent, the VM exits
+    /// with an error instead.
     pub fn step(&mut self) -> Result<(), RequireError> {
-        if self.0.len() > 1024 {
-            panic!();
+        if self.2.is_some() {
+            return Ok(());
+        }
+        if self.0.len() > self.0[0].state().patch.callstack_limit {
+            self.2 = Some(MachineError::CallstackOverflow.into());
+            return Ok(());
         }
         match self.0.last().unwrap().status().clone() {
             MachineStatus::Running => {
@@ -105,7 +137,9 @@ impl<M: Memory + Default, S: Storage + Default + Clone> VM<M, S> {
                     Ok(())
                 } else {
                     let finished = self.0.pop().unwrap();
-                    self.0.last_mut().unwrap().apply_sub(finished);
+                    if let Err(err) = self.0.last_mut().unwrap().apply_sub(finished) {
+                        self.2 = Some(err.into());
+                    }
                     Ok(())
                 }
             },
@@ -118,6 +152,21 @@ impl<M: Memory + Default, S: Storage + Default + Clone> VM<M, S> {
         }
     }
 
+    /// Get the context of the sub runtime the innermost runtime is
+    /// about to invoke. See `Machine::pending_invoke`.
+    pub fn pending_invoke(&self) -> Option<&Context> {
+        self.0.last().unwrap().pending_invoke()
+    }
+
+    /// Record the pending sub runtime in the history without running
+    /// it. See `Machine::skip_invoke`.
+    pub fn skip_invoke(&mut self) {
+        if let Some(context) = self.pending_invoke().cloned() {
+            self.1.push(context);
+            self.0.last_mut().unwrap().skip_invoke();
+        }
+    }
+
     /// Run instructions until it reaches a `RequireError` or
     /// exits. If this function succeeds, the VM status can only be
     /// either `ExitedOk` or `ExitedErr`.
@@ -132,10 +181,18 @@ impl<M: Memory + Default, S: Storage + Default + Clone> VM<M, S> {
 
     /// Returns the changed or committed accounts information up to
     /// current execution status.
-    pub fn accounts(&self) -> hash_map::Values<Address, Account<S>> {
+    pub fn accounts(&self) -> MapValues<Address, Account<S>> {
         self.0[0].state().account_state.accounts()
     }
 
+    /// Returns the world state changes up to current execution
+    /// status, to be applied to the client's database if the user
+    /// decided to accept the running status of this VM.
+    pub fn state_diff(&self) -> StateDiff {
+        let state = self.0[0].state();
+        state.account_state.diff(state.patch.eip161())
+    }
+
     /// Returns the out value, if any.
     pub fn out(&self) -> &[u8] {
         self.0[0].state().out.as_slice()
@@ -146,6 +203,25 @@ impl<M: Memory + Default, S: Storage + Default + Clone> VM<M, S> {
         self.0[0].state().available_gas()
     }
 
+    /// Returns the gas to be returned to the sender once the
+    /// transaction finishes. The context gas limit is expected to be
+    /// the transaction gas limit minus its intrinsic gas. The refund
+    /// is capped at the total gas used, including the intrinsic gas,
+    /// divided by the patch's `max_refund_quotient`. If the VM exited
+    /// with an error, all gas is consumed.
+    pub fn gas_remaining(&self) -> Gas {
+        let state = self.0[0].state();
+        match self.status() {
+            VMStatus::ExitedErr(_) => return Gas::zero(),
+            _ => (),
+        }
+
+        let available_gas = state.available_gas();
+        let used_gas = intrinsic_gas(&state.patch.gas, state.context.data.as_slice()) +
+            state.context.gas_limit - available_gas;
+        available_gas + min(state.refunded_gas, used_gas / Gas::from(state.patch.gas.max_refund_quotient))
+    }
+
     /// Returns the refunded gas of this VM.
     pub fn refunded_gas(&self) -> Gas {
         self.0[0].state().refunded_gas
@@ -157,8 +233,84 @@ impl<M: Memory + Default, S: Storage + Default + Clone> VM<M, S> {
         self.0[0].state().logs.as_slice()
     }
 
+    /// Returns the logs paired with their index in the transaction,
+    /// in the order they were emitted across all sub runtimes.
+    pub fn indexed_logs(&self) -> iter::Enumerate<slice::Iter<Log>> {
+        self.logs().iter().enumerate()
+    }
+
     /// Returns the call create history. Only used in testing.
     pub fn history(&self) -> &[Context] {
         self.1.as_slice()
     }
 }
+
+#[cfg(feature = "std")]
+impl<M: Memory + Default, S: Storage + Default + Clone + Into<Map<M256, M256>>> VM<M, S> {
+    /// Returns the state root of the world after execution, computed
+    /// from the committed accounts. Every account of the world must
+    /// have been committed as a full account for this to be the post
+    /// state root of the block.
+    pub fn state_root(&self) -> Result<M256, RequireError> {
+        let state = self.0[0].state();
+        state.account_state.state_root(state.patch.eip161())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::gas::Gas;
+    use utils::address::Address;
+    use utils::bigint::U256;
+    use super::*;
+    use super::errors::{ApplyError, MachineError, VMError};
+
+    fn machine(patch: Patch, depth: usize) -> Machine<SeqMemory, HashMapStorage> {
+        let context = Context {
+            address: Address::default(),
+            caller: Address::default(),
+            code: Vec::new(),
+            data: Vec::new(),
+            gas_limit: Gas::from(100000u64),
+            gas_price: Gas::zero(),
+            origin: Address::default(),
+            value: U256::zero(),
+            is_static: false,
+            is_create: false,
+            blob_versioned_hashes: Vec::new(),
+        };
+        Machine::new(context, BlockHeaderBuilder::new(patch.fork).build(), patch, depth)
+    }
+
+    #[test]
+    fn apply_sub_to_running_parent() {
+        let patch = Patch::new(Fork::Frontier);
+        let mut sub = machine(patch, 2);
+        sub.step().unwrap();
+        match sub.status() {
+            MachineStatus::ExitedOk => (),
+            _ => panic!(),
+        }
+
+        let mut vm: SeqVM = VM(vec![machine(patch, 1), sub], Vec::new(), None);
+        vm.step().unwrap();
+        match vm.status() {
+            VMStatus::ExitedErr(VMError::Apply(ApplyError::NotInvoking)) => (),
+            _ => panic!(),
+        }
+        assert!(vm.fire().is_ok());
+        assert_eq!(vm.gas_remaining(), Gas::zero());
+    }
+
+    #[test]
+    fn call_stack_over_limit() {
+        let mut patch = Patch::new(Fork::Frontier);
+        patch.callstack_limit = 1;
+        let mut vm: SeqVM = VM(vec![machine(patch, 1), machine(patch, 2)], Vec::new(), None);
+        vm.step().unwrap();
+        match vm.status() {
+            VMStatus::ExitedErr(VMError::Machine(MachineError::CallstackOverflow)) => (),
+            _ => panic!(),
+        }
+    }
+}
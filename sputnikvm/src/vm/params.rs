@@ -1,8 +1,11 @@
 //! Parameters used by the VM.
 
+use std::borrow::Cow;
 use utils::gas::Gas;
 use utils::address::Address;
 use utils::bigint::{M256, U256};
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
 
 #[derive(Debug, Clone)]
 /// Block header.
@@ -10,8 +13,165 @@ pub struct BlockHeader {
     pub coinbase: Address,
     pub timestamp: M256,
     pub number: M256,
+    /// Block difficulty. After the merge, opcode `0x44` reads the
+    /// beacon chain `prevrandao` value from this same field, so the
+    /// client should set it accordingly for post-merge blocks.
     pub difficulty: M256,
-    pub gas_limit: Gas
+    pub gas_limit: Gas,
+    /// Base fee per gas of the block (EIP-1559), read by opcode
+    /// `BASEFEE`. Ignored before London.
+    pub base_fee: Gas,
+    /// Base fee per blob gas of the block (EIP-4844), read by opcode
+    /// `BLOBBASEFEE`. Ignored before Cancun.
+    pub blob_base_fee: Gas,
+}
+
+impl BlockHeader {
+    /// Returns this header with the coinbase overridden.
+    pub fn with_coinbase(mut self, coinbase: Address) -> BlockHeader {
+        self.coinbase = coinbase;
+        self
+    }
+
+    /// Returns this header with the timestamp overridden.
+    pub fn with_timestamp(mut self, timestamp: M256) -> BlockHeader {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Returns this header with the block number overridden.
+    pub fn with_number(mut self, number: M256) -> BlockHeader {
+        self.number = number;
+        self
+    }
+
+    /// Returns this header with the difficulty overridden.
+    pub fn with_difficulty(mut self, difficulty: M256) -> BlockHeader {
+        self.difficulty = difficulty;
+        self
+    }
+
+    /// Returns this header with `prevrandao` set. This is an alias of
+    /// `with_difficulty` for post-merge blocks.
+    pub fn with_prevrandao(self, prevrandao: M256) -> BlockHeader {
+        self.with_difficulty(prevrandao)
+    }
+
+    /// The `prevrandao` value of a post-merge block. This is an alias
+    /// of `difficulty`.
+    pub fn prevrandao(&self) -> M256 {
+        self.difficulty
+    }
+
+    /// Returns this header with the block gas limit overridden.
+    pub fn with_gas_limit(mut self, gas_limit: Gas) -> BlockHeader {
+        self.gas_limit = gas_limit;
+        self
+    }
+
+    /// Returns this header with the base fee overridden.
+    pub fn with_base_fee(mut self, base_fee: Gas) -> BlockHeader {
+        self.base_fee = base_fee;
+        self
+    }
+
+    /// Returns this header with the blob base fee overridden.
+    pub fn with_blob_base_fee(mut self, blob_base_fee: Gas) -> BlockHeader {
+        self.blob_base_fee = blob_base_fee;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Builder of `BlockHeader`, starting from defaults for a fork.
+///
+/// ```
+/// use sputnikvm::{Gas, M256};
+/// use sputnikvm::vm::{BlockHeaderBuilder, Fork};
+///
+/// let block = BlockHeaderBuilder::new(Fork::London)
+///     .number(M256::from(12965000u64))
+///     .gas_limit(Gas::from(15000000u64))
+///     .build();
+/// assert_eq!(block.number, M256::from(12965000u64));
+/// assert_eq!(block.gas_limit, Gas::from(15000000u64));
+/// assert_eq!(block.base_fee, Gas::from(1000000000u64));
+/// ```
+pub struct BlockHeaderBuilder {
+    header: BlockHeader,
+}
+
+impl BlockHeaderBuilder {
+    /// Start from a block of `fork` with zero coinbase, timestamp and
+    /// number. The difficulty is the minimum one of the proof-of-work
+    /// chain, and zero after the merge. The gas limit is 8 million,
+    /// and 30 million from London, which also sets the initial base
+    /// fee of 1 gwei. From Cancun the blob base fee is the minimum of
+    /// 1 wei.
+    pub fn new(fork: Fork) -> BlockHeaderBuilder {
+        let difficulty = if fork >= Fork::Merge { M256::zero() } else { M256::from(131072u64) };
+        let gas_limit = if fork >= Fork::London { 30000000u64 } else { 8000000u64 };
+        let base_fee = if fork >= Fork::London { Gas::from(1000000000u64) } else { Gas::zero() };
+        let blob_base_fee = if fork >= Fork::Cancun { Gas::from(1u64) } else { Gas::zero() };
+        BlockHeaderBuilder {
+            header: BlockHeader {
+                coinbase: Address::default(),
+                timestamp: M256::zero(),
+                number: M256::zero(),
+                difficulty,
+                gas_limit: Gas::from(gas_limit),
+                base_fee,
+                blob_base_fee,
+            },
+        }
+    }
+
+    /// Set the coinbase.
+    pub fn coinbase(mut self, coinbase: Address) -> BlockHeaderBuilder {
+        self.header = self.header.with_coinbase(coinbase);
+        self
+    }
+
+    /// Set the timestamp.
+    pub fn timestamp(mut self, timestamp: M256) -> BlockHeaderBuilder {
+        self.header = self.header.with_timestamp(timestamp);
+        self
+    }
+
+    /// Set the block number.
+    pub fn number(mut self, number: M256) -> BlockHeaderBuilder {
+        self.header = self.header.with_number(number);
+        self
+    }
+
+    /// Set the difficulty, or `prevrandao` after the merge.
+    pub fn difficulty(mut self, difficulty: M256) -> BlockHeaderBuilder {
+        self.header = self.header.with_difficulty(difficulty);
+        self
+    }
+
+    /// Set the block gas limit.
+    pub fn gas_limit(mut self, gas_limit: Gas) -> BlockHeaderBuilder {
+        self.header = self.header.with_gas_limit(gas_limit);
+        self
+    }
+
+    /// Set the base fee.
+    pub fn base_fee(mut self, base_fee: Gas) -> BlockHeaderBuilder {
+        self.header = self.header.with_base_fee(base_fee);
+        self
+    }
+
+    /// Set the blob base fee.
+    pub fn blob_base_fee(mut self, blob_base_fee: Gas) -> BlockHeaderBuilder {
+        self.header = self.header.with_blob_base_fee(blob_base_fee);
+        self
+    }
+
+    /// The built header.
+    pub fn build(self) -> BlockHeader {
+        self.header
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -25,6 +185,34 @@ pub struct Context {
     pub gas_price: Gas,
     pub origin: Address,
     pub value: U256,
+    /// Whether state modifications are disallowed, because this
+    /// runtime was invoked by `STATICCALL` (EIP-214).
+    pub is_static: bool,
+    /// Whether this runtime runs the init code of a contract being
+    /// created. The new account has no code until the runtime exits.
+    pub is_create: bool,
+    /// Versioned hashes of the blobs of the transaction (EIP-4844),
+    /// read by opcode `BLOBHASH`. Shared by every runtime of the
+    /// transaction.
+    pub blob_versioned_hashes: Vec<M256>,
+}
+
+impl Context {
+    /// Read `len` bytes of call data starting at `offset`. Bytes past
+    /// the end of the call data are zero.
+    pub fn calldata_slice(&self, offset: usize, len: usize) -> Cow<[u8]> {
+        let end = offset.saturating_add(len);
+        if end <= self.data.len() {
+            Cow::Borrowed(&self.data[offset..end])
+        } else {
+            let mut ret = vec![0u8; len];
+            if offset < self.data.len() {
+                let available = self.data.len() - offset;
+                ret[..available].copy_from_slice(&self.data[offset..]);
+            }
+            Cow::Owned(ret)
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -37,37 +225,304 @@ pub struct Log {
     pub topics: Vec<M256>,
 }
 
-#[derive(Debug, Clone, Copy)]
-/// Patches applied to the current blockchain.
-pub enum Patch {
-    None,
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+/// Hard forks of the blockchain, in the order they were activated.
+pub enum Fork {
+    Frontier,
     Homestead,
     EIP150,
-    EIP160
+    EIP160,
+    EIP161,
+    Byzantium,
+    Constantinople,
+    Istanbul,
+    Berlin,
+    London,
+    Merge,
+    Shanghai,
+    Cancun,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+/// Gas costs charged by the VM. Fork-dependent costs are resolved
+/// when the schedule is created, so each hard fork is a different
+/// schedule.
+pub struct GasSchedule {
+    pub zero: usize,
+    pub base: usize,
+    pub verylow: usize,
+    pub low: usize,
+    pub mid: usize,
+    pub high: usize,
+    pub jumpdest: usize,
+    pub extcode: usize,
+    pub extcodehash: usize,
+    pub balance: usize,
+    pub sload: usize,
+    pub sset: usize,
+    pub sreset: usize,
+    pub sclear_refund: usize,
+    pub suicide: usize,
+    pub suicide_refund: usize,
+    /// The refund is capped at the used gas divided by this quotient.
+    pub max_refund_quotient: usize,
+    pub create: usize,
+    pub code_deposit: usize,
+    pub call: usize,
+    pub call_value: usize,
+    pub call_stipend: usize,
+    pub new_account: usize,
+    pub exp: usize,
+    pub exp_byte: usize,
+    pub memory: usize,
+    pub tx_create: usize,
+    pub tx_data_zero: usize,
+    pub tx_data_non_zero: usize,
+    pub transaction: usize,
+    pub log: usize,
+    pub log_data: usize,
+    pub log_topic: usize,
+    pub sha3: usize,
+    pub sha3_word: usize,
+    pub copy: usize,
+    pub blockhash: usize,
+    pub tload: usize,
+    pub tstore: usize,
+    /// Cost of the first access to a storage slot (EIP-2929).
+    pub cold_sload: usize,
+    /// Cost of the first access to an account (EIP-2929).
+    pub cold_account_access: usize,
+    /// Cost of any later access to an account or storage slot
+    /// (EIP-2929).
+    pub warm_storage_read: usize,
+    /// Cost of the precompiled contract `ECRECOVER`.
+    pub ecrecover: usize,
+    pub sha256: usize,
+    pub sha256_word: usize,
+    pub ripemd160: usize,
+    pub ripemd160_word: usize,
+    pub identity: usize,
+    pub identity_word: usize,
+}
+
+impl GasSchedule {
+    /// The gas schedule of the given hard fork.
+    pub fn new(fork: Fork) -> GasSchedule {
+        let eip150 = fork >= Fork::EIP150;
+        let eip160 = fork >= Fork::EIP160;
+        let istanbul = fork >= Fork::Istanbul;
+        let berlin = fork >= Fork::Berlin;
+        let london = fork >= Fork::London;
+
+        GasSchedule {
+            zero: 0,
+            base: 2,
+            verylow: 3,
+            low: 5,
+            mid: 8,
+            high: 10,
+            jumpdest: 1,
+            extcode: if eip150 { 700 } else { 20 },
+            extcodehash: if istanbul { 700 } else { 400 },
+            balance: if istanbul { 700 } else if eip150 { 400 } else { 20 },
+            sload: if istanbul { 800 } else if eip150 { 200 } else { 50 },
+            sset: 20000,
+            // The cold surcharge is added on top from Berlin.
+            sreset: if berlin { 2900 } else { 5000 },
+            sclear_refund: if london { 4800 } else { 15000 },
+            suicide: if eip150 { 5000 } else { 0 },
+            suicide_refund: if london { 0 } else { 24000 },
+            max_refund_quotient: if london { 5 } else { 2 },
+            create: 32000,
+            code_deposit: 200,
+            call: if eip150 { 700 } else { 40 },
+            call_value: 9000,
+            call_stipend: 2300,
+            new_account: 25000,
+            exp: 10,
+            exp_byte: if eip160 { 50 } else { 10 },
+            memory: 3,
+            tx_create: 32000,
+            tx_data_zero: 4,
+            tx_data_non_zero: if istanbul { 16 } else { 68 },
+            transaction: 21000,
+            log: 375,
+            log_data: 8,
+            log_topic: 375,
+            sha3: 30,
+            sha3_word: 6,
+            copy: 3,
+            blockhash: 20,
+            tload: 100,
+            tstore: 100,
+            cold_sload: 2100,
+            cold_account_access: 2600,
+            warm_storage_read: 100,
+            ecrecover: 3000,
+            sha256: 60,
+            sha256_word: 12,
+            ripemd160: 600,
+            ripemd160_word: 120,
+            identity: 15,
+            identity_word: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Patches applied to the current blockchain, together with optional
+/// resource limits of the VM.
+pub struct Patch {
+    /// The latest hard fork applied.
+    pub fork: Fork,
+    /// Maximum total bytes of log data emitted across the whole call
+    /// tree.
+    pub log_data_limit: Option<usize>,
+    /// Maximum number of logs emitted across the whole call tree.
+    pub log_limit: Option<usize>,
+    /// Maximum total bytes of memory allocated across all frames of
+    /// the call tree.
+    pub memory_limit: Option<usize>,
+    /// Maximum depth of the call stack. A runtime whose depth is
+    /// equal to or larger than this exits with `CallstackOverflow`.
+    pub callstack_limit: usize,
+    /// Disable gas metering. Gas is still tracked in `used_gas`, but
+    /// the runtime never runs out of gas.
+    pub ignore_gas: bool,
+    /// Make `SSTORE` fail with `EmptyGas` if no more than the call
+    /// stipend is left (EIP-2200). `Patch::new` enables it from
+    /// Istanbul. The net gas metering of EIP-2200 is not implemented.
+    pub sstore_gas_sentinel: bool,
+    /// Do not run sub runtimes. If set, `CREATE` and the `CALL`
+    /// family push this value instead of invoking a sub runtime, and
+    /// transfer no value, so that the control flow of a contract can
+    /// be explored on its own. Unlike static mode, the contract can
+    /// still modify its own state.
+    pub sub_call_placeholder: Option<M256>,
+    /// Chain ID returned by opcode `CHAINID` (EIP-1344). `Patch::new`
+    /// uses the one of the main network.
+    pub chain_id: U256,
+    /// Gas costs charged by the VM. `Patch::new` uses the schedule of
+    /// the given fork; override it for custom chains.
+    pub gas: GasSchedule,
 }
 
 impl Patch {
+    /// Create a patch for the given hard fork, without any resource
+    /// limits.
+    pub fn new(fork: Fork) -> Patch {
+        Patch {
+            fork: fork,
+            log_data_limit: None,
+            log_limit: None,
+            memory_limit: None,
+            callstack_limit: 1024,
+            ignore_gas: false,
+            sstore_gas_sentinel: fork >= Fork::Istanbul,
+            sub_call_placeholder: None,
+            chain_id: U256::from(1u64),
+            gas: GasSchedule::new(fork),
+        }
+    }
+
     /// The homestead patch.
     pub fn homestead(&self) -> bool {
-        match self {
-            &Patch::None => false,
-            _ => true,
-        }
+        self.fork >= Fork::Homestead
     }
 
     /// The homestead and EIP150 patch.
     pub fn eip150(&self) -> bool {
-        match self {
-            &Patch::None | &Patch::Homestead => false,
-            _ => true,
-        }
+        self.fork >= Fork::EIP150
     }
 
     /// The homestead, EIP150 and EIP160 patch.
     pub fn eip160(&self) -> bool {
-        match self {
-            &Patch::None | &Patch::Homestead | &Patch::EIP150 => false,
-            _ => true,
-        }
+        self.fork >= Fork::EIP160
+    }
+
+    /// The homestead, EIP150, EIP160 and EIP161 patch. Empty
+    /// accounts touched by a transaction are deleted.
+    pub fn eip161(&self) -> bool {
+        self.fork >= Fork::EIP161
+    }
+
+    /// The Byzantium patch. Adds the `STATICCALL` opcode.
+    pub fn byzantium(&self) -> bool {
+        self.fork >= Fork::Byzantium
+    }
+
+    /// The Constantinople patch. Adds the `EXTCODEHASH` opcode.
+    pub fn constantinople(&self) -> bool {
+        self.fork >= Fork::Constantinople
+    }
+
+    /// The Istanbul patch. Adds the `CHAINID` and `SELFBALANCE`
+    /// opcodes, and `BALANCE`, `EXTCODEHASH` and `SLOAD` are repriced
+    /// (EIP-1884).
+    pub fn istanbul(&self) -> bool {
+        self.fork >= Fork::Istanbul
+    }
+
+    /// The Berlin patch. Accounts and storage slots are charged a
+    /// cold or warm access cost (EIP-2929).
+    pub fn berlin(&self) -> bool {
+        self.fork >= Fork::Berlin
+    }
+
+    /// The London patch. Adds the `BASEFEE` opcode and reduces gas
+    /// refunds (EIP-3529).
+    pub fn london(&self) -> bool {
+        self.fork >= Fork::London
+    }
+
+    /// The merge patch. Opcode `0x44` returns `prevrandao` instead of
+    /// the difficulty.
+    pub fn merge(&self) -> bool {
+        self.fork >= Fork::Merge
+    }
+
+    /// The Shanghai patch. Adds the `PUSH0` opcode.
+    pub fn shanghai(&self) -> bool {
+        self.fork >= Fork::Shanghai
+    }
+
+    /// The Cancun patch. Adds the transient storage opcodes `TLOAD`
+    /// and `TSTORE`, and the `BLOBHASH` and `BLOBBASEFEE` opcodes.
+    pub fn cancun(&self) -> bool {
+        self.fork >= Fork::Cancun
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::gas::Gas;
+    use utils::address::Address;
+    use utils::bigint::M256;
+    use super::{BlockHeaderBuilder, Fork};
+
+    #[test]
+    fn block_header_overrides() {
+        let coinbase = Address::from(M256::from(3u64));
+        let header = BlockHeaderBuilder::new(Fork::Frontier).build()
+            .with_number(M256::from(100u64))
+            .with_timestamp(M256::from(200u64))
+            .with_coinbase(coinbase)
+            .with_difficulty(M256::from(400u64))
+            .with_gas_limit(Gas::from(500u64))
+            .with_base_fee(Gas::from(600u64))
+            .with_blob_base_fee(Gas::from(700u64));
+        assert_eq!(header.number, M256::from(100u64));
+        assert_eq!(header.timestamp, M256::from(200u64));
+        assert_eq!(header.coinbase, coinbase);
+        assert_eq!(header.difficulty, M256::from(400u64));
+        assert_eq!(header.prevrandao(), M256::from(400u64));
+        assert_eq!(header.gas_limit, Gas::from(500u64));
+        assert_eq!(header.base_fee, Gas::from(600u64));
+        assert_eq!(header.blob_base_fee, Gas::from(700u64));
+
+        let header = BlockHeaderBuilder::new(Fork::Cancun).difficulty(M256::from(400u64)).build();
+        assert_eq!(header.difficulty, M256::from(400u64));
+        assert_eq!(header.base_fee, Gas::from(1000000000u64));
+        assert_eq!(header.blob_base_fee, Gas::from(1u64));
     }
 }
@@ -3,7 +3,10 @@
 use utils::bigint::M256;
 use utils::opcode::Opcode;
 use std::cmp::min;
+use std::fmt;
 use super::errors::PCError;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 /// Instructions for the program counter. This is the same as `Opcode`
@@ -13,17 +16,34 @@ pub enum Instruction {
     SIGNEXTEND, LT, GT, SLT, SGT, EQ, ISZERO, AND, OR, XOR, NOT, BYTE,
     SHA3, ADDRESS, BALANCE, ORIGIN, CALLER, CALLVALUE, CALLDATALOAD,
     CALLDATASIZE, CALLDATACOPY, CODESIZE, CODECOPY, GASPRICE,
-    EXTCODESIZE, EXTCODECOPY, BLOCKHASH, COINBASE, TIMESTAMP, NUMBER,
-    DIFFICULTY, GASLIMIT, POP, MLOAD, MSTORE, MSTORE8, SLOAD, SSTORE,
-    JUMP, JUMPI, PC, MSIZE, GAS, JUMPDEST, CREATE, CALL, CALLCODE,
-    RETURN, DELEGATECALL, SUICIDE,
+    EXTCODESIZE, EXTCODECOPY, EXTCODEHASH, BLOCKHASH, COINBASE, TIMESTAMP, NUMBER,
+    DIFFICULTY, GASLIMIT, CHAINID, SELFBALANCE, BASEFEE, BLOBHASH, BLOBBASEFEE, POP, MLOAD, MSTORE, MSTORE8, SLOAD, SSTORE,
+    JUMP, JUMPI, PC, MSIZE, GAS, JUMPDEST, TLOAD, TSTORE, CREATE, CALL, CALLCODE,
+    RETURN, DELEGATECALL, STATICCALL, SUICIDE,
 
-    PUSH(M256),
+    PUSH0,
+    /// The width of the immediate in bytes, and its value.
+    PUSH(usize, M256),
     DUP(usize),
     SWAP(usize),
     LOG(usize),
 }
 
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::PUSH(n, v) => {
+                let hex = format!("{:x}", v);
+                write!(f, "PUSH{} 0x{:0>width$}", n, hex.trim_start_matches('0'), width = n * 2)
+            },
+            Instruction::DUP(v) => write!(f, "DUP{}", v),
+            Instruction::SWAP(v) => write!(f, "SWAP{}", v),
+            Instruction::LOG(v) => write!(f, "LOG{}", v),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
 /// Represents a program counter in EVM.
 pub struct PC {
     position: usize,
@@ -80,8 +100,21 @@ impl PC {
         if position.checked_add(byte_count).is_none() {
             return Err(PCError::IndexNotSupported);
         }
+        // Bytes past the end of the code are read as zeros, so a
+        // truncated immediate is padded on the right.
+        if byte_count <= 8 {
+            // Most immediates fit into a `u64`, which avoids the byte
+            // buffer.
+            let mut value = 0u64;
+            for i in 0..byte_count {
+                value = (value << 8) | (*self.code.get(position + i).unwrap_or(&0) as u64);
+            }
+            return Ok(M256::from(value));
+        }
         let max = min(position + byte_count, self.code.len());
-        Ok(M256::from(&self.code[position..max]))
+        let mut bytes = self.code[position..max].to_vec();
+        bytes.resize(byte_count, 0);
+        Ok(M256::from(bytes.as_slice()))
     }
 
     /// Jump to a position in the code. The destination must be valid
@@ -99,6 +132,17 @@ impl PC {
         Ok(())
     }
 
+    /// Step over the current byte without decoding it, as an opcode
+    /// without immediates.
+    pub fn skip(&mut self) -> Result<(), PCError> {
+        if self.position >= self.code.len() {
+            return Err(PCError::Overflow);
+        }
+
+        self.position = self.position + 1;
+        Ok(())
+    }
+
     /// Get the current program counter position.
     pub fn position(&self) -> usize {
         self.position
@@ -134,6 +178,14 @@ impl PC {
         self.position == self.code.len()
     }
 
+    /// Peek the raw opcode of the next instruction.
+    pub fn peek_opcode(&self) -> Result<u8, PCError> {
+        if self.position >= self.code.len() {
+            return Err(PCError::Overflow);
+        }
+        Ok(self.code[self.position])
+    }
+
     /// Peek the next instruction.
     pub fn peek(&self) -> Result<Instruction, PCError> {
         let position = self.position;
@@ -182,6 +234,7 @@ impl PC {
             Opcode::GASPRICE => Instruction::GASPRICE,
             Opcode::EXTCODESIZE => Instruction::EXTCODESIZE,
             Opcode::EXTCODECOPY => Instruction::EXTCODECOPY,
+            Opcode::EXTCODEHASH => Instruction::EXTCODEHASH,
 
             Opcode::BLOCKHASH => Instruction::BLOCKHASH,
             Opcode::COINBASE => Instruction::COINBASE,
@@ -189,6 +242,11 @@ impl PC {
             Opcode::NUMBER => Instruction::NUMBER,
             Opcode::DIFFICULTY => Instruction::DIFFICULTY,
             Opcode::GASLIMIT => Instruction::GASLIMIT,
+            Opcode::CHAINID => Instruction::CHAINID,
+            Opcode::SELFBALANCE => Instruction::SELFBALANCE,
+            Opcode::BASEFEE => Instruction::BASEFEE,
+            Opcode::BLOBHASH => Instruction::BLOBHASH,
+            Opcode::BLOBBASEFEE => Instruction::BLOBBASEFEE,
 
             Opcode::POP => Instruction::POP,
             Opcode::MLOAD => Instruction::MLOAD,
@@ -202,10 +260,13 @@ impl PC {
             Opcode::MSIZE => Instruction::MSIZE,
             Opcode::GAS => Instruction::GAS,
             Opcode::JUMPDEST => Instruction::JUMPDEST,
+            Opcode::TLOAD => Instruction::TLOAD,
+            Opcode::TSTORE => Instruction::TSTORE,
 
+            Opcode::PUSH0 => Instruction::PUSH0,
             Opcode::PUSH(v) => {
                 let param = self.read_bytes(position + 1, v)?;
-                Instruction::PUSH(param)
+                Instruction::PUSH(v, param)
             },
 
             Opcode::DUP(v) => Instruction::DUP(v),
@@ -217,6 +278,7 @@ impl PC {
             Opcode::CALLCODE => Instruction::CALLCODE,
             Opcode::RETURN => Instruction::RETURN,
             Opcode::DELEGATECALL => Instruction::DELEGATECALL,
+            Opcode::STATICCALL => Instruction::STATICCALL,
 
             Opcode::INVALID => {
                 return Err(PCError::InvalidOpcode);
@@ -240,3 +302,24 @@ impl PC {
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use utils::bigint::M256;
+    use super::{PC, Instruction};
+
+    #[test]
+    fn instruction_mnemonics() {
+        assert_eq!(format!("{}", Instruction::PUSH(1, M256::from(0x60u64))), "PUSH1 0x60");
+        assert_eq!(format!("{}", Instruction::PUSH(2, M256::from(0x1234u64))), "PUSH2 0x1234");
+        assert_eq!(format!("{}", Instruction::PUSH(2, M256::from(0x100u64))), "PUSH2 0x0100");
+        assert_eq!(format!("{}", Instruction::PUSH(1, M256::zero())), "PUSH1 0x00");
+        // The width comes from the opcode, not the value.
+        let mut pc = PC::new(&[0x62, 0x00, 0x00, 0x2a]);
+        assert_eq!(format!("{}", pc.read().unwrap()), "PUSH3 0x00002a");
+        assert_eq!(format!("{}", Instruction::DUP(3)), "DUP3");
+        assert_eq!(format!("{}", Instruction::SWAP(1)), "SWAP1");
+        assert_eq!(format!("{}", Instruction::LOG(2)), "LOG2");
+        assert_eq!(format!("{}", Instruction::SSTORE), "SSTORE");
+    }
+}
@@ -0,0 +1,204 @@
+//! Precompiled contracts
+
+use utils::address::Address;
+use utils::bigint::U256;
+use utils::gas::Gas;
+use vm::{Patch, GasSchedule};
+use tiny_keccak::keccak256;
+use sha2::{Sha256, Digest};
+use ripemd160::Ripemd160;
+use libsecp256k1::{recover, Message, Signature, RecoveryId};
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+/// The last byte of the address, if all other bytes are zero.
+fn precompiled_index(address: Address) -> Option<u8> {
+    let address: [u8; 20] = address.into();
+    if address[0..19].iter().all(|&v| v == 0) {
+        Some(address[19])
+    } else {
+        None
+    }
+}
+
+/// Returns whether the address is one of the precompiled contracts of
+/// the patch: `0x01` to `0x04` from Frontier, up to `0x08` from
+/// Byzantium and up to `0x09` from Istanbul. Precompiled contracts
+/// have no bytecode.
+pub fn is_precompiled(patch: &Patch, address: Address) -> bool {
+    let last = if patch.istanbul() {
+        0x09
+    } else if patch.byzantium() {
+        0x08
+    } else {
+        0x04
+    };
+    match precompiled_index(address) {
+        Some(index) => index >= 0x01 && index <= last,
+        None => false,
+    }
+}
+
+/// Gas cost of running the precompiled contract at `address` on
+/// `input_len` bytes of input. `None` if the address is not one of
+/// the implemented contracts, `0x01` to `0x04`.
+pub fn precompiled_gas(gas: &GasSchedule, address: Address, input_len: usize) -> Option<Gas> {
+    let (base, word) = match precompiled_index(address) {
+        Some(0x01) => (gas.ecrecover, 0),
+        Some(0x02) => (gas.sha256, gas.sha256_word),
+        Some(0x03) => (gas.ripemd160, gas.ripemd160_word),
+        Some(0x04) => (gas.identity, gas.identity_word),
+        _ => return None,
+    };
+    let words = Gas::from(input_len / 32 + if input_len % 32 == 0 { 0 } else { 1 });
+    Some(Gas::from(base) + Gas::from(word) * words)
+}
+
+/// Run the precompiled contract at `address`, which must be one for
+/// which `precompiled_gas` returns a cost.
+pub fn run_precompiled(address: Address, input: &[u8]) -> Vec<u8> {
+    let address: [u8; 20] = address.into();
+    match address[19] {
+        0x01 => ecrecover(input),
+        0x02 => Sha256::digest(input).to_vec(),
+        0x03 => {
+            let mut ret = vec![0u8; 12];
+            ret.extend_from_slice(&Ripemd160::digest(input));
+            ret
+        },
+        0x04 => input.to_vec(),
+        _ => panic!(),
+    }
+}
+
+/// Order of the secp256k1 generator.
+const N: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+    0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b,
+    0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41,
+];
+
+/// Run the `ECRECOVER` precompiled contract (`0x01`). The input is
+/// `hash`, `v`, `r` and `s` as 32-byte words, zero-padded if
+/// shorter. The output is the address of the signer left-padded to
+/// 32 bytes, or empty if `v` is not 27 or 28, if `r` or `s` is not
+/// in `[1, N)`, or if no key recovers from the signature.
+///
+/// The low-`s` rule of Homestead only applies to transaction
+/// signatures, so signatures with `s` in the upper half of the range
+/// still recover here.
+pub fn ecrecover(input: &[u8]) -> Vec<u8> {
+    let mut data = [0u8; 128];
+    let len = if input.len() > 128 { 128 } else { input.len() };
+    data[0..len].copy_from_slice(&input[0..len]);
+
+    if data[32..63].iter().any(|&v| v != 0) || (data[63] != 27 && data[63] != 28) {
+        return Vec::new();
+    }
+    let r = U256::from(&data[64..96]);
+    let s = U256::from(&data[96..128]);
+    let n = U256::from(N);
+    if r == U256::zero() || r >= n || s == U256::zero() || s >= n {
+        return Vec::new();
+    }
+
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&data[0..32]);
+    let mut signature = [0u8; 64];
+    signature.copy_from_slice(&data[64..128]);
+    let signature = match Signature::parse_standard(&signature) {
+        Ok(signature) => signature,
+        Err(_) => return Vec::new(),
+    };
+    let recovery_id = match RecoveryId::parse(data[63] - 27) {
+        Ok(recovery_id) => recovery_id,
+        Err(_) => return Vec::new(),
+    };
+
+    match recover(&Message::parse(&hash), &signature, &recovery_id) {
+        Ok(public) => {
+            let digest = keccak256(&public.serialize()[1..65]);
+            let mut ret = vec![0u8; 12];
+            ret.extend_from_slice(&digest[12..32]);
+            ret
+        },
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::read_hex;
+    use utils::gas::Gas;
+    use utils::address::Address;
+    use utils::bigint::M256;
+    use vm::{GasSchedule, Fork};
+    use super::{ecrecover, precompiled_gas, run_precompiled};
+
+    fn address(v: u64) -> Address {
+        Address::from(M256::from(v))
+    }
+
+    fn input(hash: &str, v: u8, r: &str, s: &str) -> Vec<u8> {
+        let mut ret = read_hex(hash).unwrap();
+        ret.extend_from_slice(&[0u8; 31]);
+        ret.push(v);
+        ret.extend(read_hex(r).unwrap());
+        ret.extend(read_hex(s).unwrap());
+        ret
+    }
+
+    const HASH: &'static str = "0x456e9aea5e197a1f1af7a3e85a3212fa4049a3ba34c2289b4c860fc0b0c64ef3";
+    const R: &'static str = "0x9242685bf161793cc25603c231bc2f568eb630ea16aa137d2664ac8038825608";
+    const S: &'static str = "0x4f8ae3bd7535248d0bd448298cc2e2071e56992d0774dc340c368ae950852ada";
+
+    #[test]
+    fn recovers_signer() {
+        assert_eq!(ecrecover(&input(HASH, 28, R, S)),
+                   read_hex("0x0000000000000000000000007156526fbd7a3c72969b54f64e42c10fbb768c8a").unwrap());
+        // A signature with `s` in the upper half.
+        assert_eq!(ecrecover(&input("0xdf7bb5e6aab327a85435f8e860a7b294e32cc9400df5916a993536dba851488e", 28,
+                                    "0xf973a0b87062c389d125d8199e803b832b6ac6bf7867a4f6cd87506060fc4c58",
+                                    "0xbcd5909197e21920056cd5c944b6670b8bd8195318f6674a37112b3e8bf7e9e1")),
+                   read_hex("0x0000000000000000000000002c7536e3605d9c16a7a3d7b1898e529396a65c23").unwrap());
+    }
+
+    #[test]
+    fn rejects_invalid_v() {
+        assert_eq!(ecrecover(&input(HASH, 29, R, S)), Vec::<u8>::new());
+        assert_eq!(ecrecover(&input(HASH, 1, R, S)), Vec::<u8>::new());
+        let mut high_v = input(HASH, 28, R, S);
+        high_v[32] = 1;
+        assert_eq!(ecrecover(&high_v), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn rejects_out_of_range_signature() {
+        let n = "0xfffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141";
+        let zero = "0x0000000000000000000000000000000000000000000000000000000000000000";
+        assert_eq!(ecrecover(&input(HASH, 28, R, n)), Vec::<u8>::new());
+        assert_eq!(ecrecover(&input(HASH, 28, n, S)), Vec::<u8>::new());
+        assert_eq!(ecrecover(&input(HASH, 28, R, zero)), Vec::<u8>::new());
+        assert_eq!(ecrecover(&input(HASH, 28, zero, S)), Vec::<u8>::new());
+        assert_eq!(ecrecover(&[]), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn hashes_and_identity() {
+        let gas = GasSchedule::new(Fork::Frontier);
+        let input = b"abc";
+        assert_eq!(run_precompiled(address(2), input),
+                   read_hex("0xba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad").unwrap());
+        assert_eq!(run_precompiled(address(3), input),
+                   read_hex("0x0000000000000000000000008eb208f7e05d987a9b044a8e98c6b087f15a0bfc").unwrap());
+        assert_eq!(run_precompiled(address(4), input), input.to_vec());
+
+        assert_eq!(precompiled_gas(&gas, address(1), 128), Some(Gas::from(3000u64)));
+        assert_eq!(precompiled_gas(&gas, address(2), 33), Some(Gas::from(60u64 + 12 * 2)));
+        assert_eq!(precompiled_gas(&gas, address(3), 0), Some(Gas::from(600u64)));
+        assert_eq!(precompiled_gas(&gas, address(4), 64), Some(Gas::from(15u64 + 3 * 2)));
+        assert_eq!(precompiled_gas(&gas, address(5), 0), None);
+        assert_eq!(precompiled_gas(&gas, address(0x0a), 0), None);
+    }
+}
@@ -2,6 +2,8 @@
 
 use utils::bigint::M256;
 use super::errors::StackError;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
 
 /// Represents an EVM stack.
 pub struct Stack {
@@ -76,4 +78,9 @@ impl Stack {
     pub fn len(&self) -> usize {
         self.stack.len()
     }
+
+    /// Remove all values from the stack, keeping its allocation.
+    pub fn clear(&mut self) {
+        self.stack.clear();
+    }
 }
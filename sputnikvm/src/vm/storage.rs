@@ -1,7 +1,7 @@
 //! EVM account storage
 
 use utils::bigint::M256;
-use std::collections::hash_map::HashMap;
+use utils::collections::Map;
 use super::errors::StorageError;
 
 /// Represents an account storage. All values in the storage are
@@ -19,24 +19,25 @@ pub trait Storage {
 }
 
 #[derive(Debug, Clone)]
-/// An account storage representation that uses `HashMap`.
-pub struct HashMapStorage(HashMap<M256, M256>);
+/// An account storage representation that uses a `Map`, which is a
+/// `HashMap` with the `std` feature.
+pub struct HashMapStorage(Map<M256, M256>);
 
-impl From<HashMap<M256, M256>> for HashMapStorage {
-    fn from(val: HashMap<M256, M256>) -> HashMapStorage {
+impl From<Map<M256, M256>> for HashMapStorage {
+    fn from(val: Map<M256, M256>) -> HashMapStorage {
         HashMapStorage(val)
     }
 }
 
-impl Into<HashMap<M256, M256>> for HashMapStorage {
-    fn into(self) -> HashMap<M256, M256> {
+impl Into<Map<M256, M256>> for HashMapStorage {
+    fn into(self) -> Map<M256, M256> {
         self.0
     }
 }
 
 impl Default for HashMapStorage {
     fn default() -> HashMapStorage {
-        HashMapStorage(HashMap::new())
+        HashMapStorage(Map::new())
     }
 }
 
@@ -0,0 +1,301 @@
+//! Execution trace in the `StructLog` format of go-ethereum
+
+use std::collections::BTreeMap;
+use utils::collections::Map;
+use utils::address::Address;
+use utils::bigint::M256;
+use utils::gas::Gas;
+use utils::opcode::Opcode;
+use super::{Memory, Storage, Machine, MachineStatus};
+use super::errors::RequireError;
+#[cfg(not(feature = "std"))]
+use alloc::string::{String, ToString};
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+/// What a `StructLogger` records besides the stack. Memory and
+/// storage can make traces very large, so both can be left out.
+pub struct StructLogConfig {
+    /// Record the active memory before each instruction.
+    pub memory: bool,
+    /// Record the storage slots of the current contract accessed so
+    /// far by `SLOAD` and `SSTORE`.
+    pub storage: bool,
+}
+
+impl Default for StructLogConfig {
+    fn default() -> StructLogConfig {
+        StructLogConfig {
+            memory: true,
+            storage: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+/// A single executed instruction, as in the `structLogs` of
+/// `debug_traceTransaction`.
+pub struct StructLog {
+    /// Position of the instruction in the code.
+    pub pc: usize,
+    /// Name of the instruction.
+    pub op: String,
+    /// Available gas before the instruction is executed.
+    pub gas: Gas,
+    /// Gas charged by the instruction, including memory expansion
+    /// and the gas forwarded to a sub runtime.
+    pub gas_cost: Gas,
+    /// Call depth, starting at 1.
+    pub depth: usize,
+    /// Stack before the instruction is executed, from the bottom.
+    pub stack: Vec<M256>,
+    /// Active memory before the instruction is executed, if recorded.
+    pub memory: Option<Vec<u8>>,
+    /// Accessed storage slots of the current contract, if recorded.
+    pub storage: Option<BTreeMap<M256, M256>>,
+    /// Error the instruction failed with, if any.
+    pub error: Option<String>,
+}
+
+fn op_name(opcode: u8) -> String {
+    match Opcode::from(opcode) {
+        Opcode::PUSH(v) => format!("PUSH{}", v),
+        Opcode::DUP(v) => format!("DUP{}", v),
+        Opcode::SWAP(v) => format!("SWAP{}", v),
+        Opcode::LOG(v) => format!("LOG{}", v),
+        Opcode::SUICIDE => "SELFDESTRUCT".to_string(),
+        opcode => format!("{:?}", opcode),
+    }
+}
+
+fn word_hex(value: M256) -> String {
+    format!("{:064x}", value)
+}
+
+fn short_hex(value: M256) -> String {
+    let hex = format!("{:x}", value);
+    match hex.trim_start_matches('0') {
+        "" => "0x0".to_string(),
+        hex => format!("0x{}", hex),
+    }
+}
+
+impl StructLog {
+    /// Serialize the entry into a JSON object, with the field names
+    /// and number formats used by go-ethereum.
+    pub fn to_json(&self) -> String {
+        let stack: Vec<String> = self.stack.iter()
+            .map(|&v| format!("\"{}\"", short_hex(v))).collect();
+        let mut out = format!("{{\"pc\":{},\"op\":\"{}\",\"gas\":{},\"gasCost\":{},\"depth\":{},\"stack\":[{}]",
+                              self.pc, self.op, self.gas.as_u64_saturating(), self.gas_cost.as_u64_saturating(),
+                              self.depth, stack.join(","));
+        match self.memory {
+            Some(ref memory) => {
+                let words: Vec<String> = memory.chunks(32).map(|word| {
+                    let hex: Vec<String> = word.iter().map(|b| format!("{:02x}", b)).collect();
+                    format!("\"{}\"", hex.concat())
+                }).collect();
+                out.push_str(&format!(",\"memory\":[{}]", words.join(",")));
+            },
+            None => (),
+        }
+        match self.storage {
+            Some(ref storage) => {
+                let slots: Vec<String> = storage.iter()
+                    .map(|(&k, &v)| format!("\"{}\":\"{}\"", word_hex(k), word_hex(v))).collect();
+                out.push_str(&format!(",\"storage\":{{{}}}", slots.join(",")));
+            },
+            None => (),
+        }
+        match self.error {
+            Some(ref error) => out.push_str(&format!(",\"error\":\"{}\"", error)),
+            None => (),
+        }
+        out.push('}');
+        out
+    }
+}
+
+/// Records a `StructLog` for every instruction executed through
+/// `StructLogger::step`, so that a run can be diffed against the
+/// trace of go-ethereum.
+pub struct StructLogger {
+    config: StructLogConfig,
+    logs: Vec<StructLog>,
+    storage: Map<Address, BTreeMap<M256, M256>>,
+}
+
+impl StructLogger {
+    /// Create a new logger with the given configuration.
+    pub fn new(config: StructLogConfig) -> Self {
+        StructLogger {
+            config: config,
+            logs: Vec::new(),
+            storage: Map::new(),
+        }
+    }
+
+    /// Step the runtime, and record the instruction it executes. The
+    /// runtime is stepped exactly as with `Machine::step`, and nothing
+    /// is recorded if it requires more information.
+    pub fn step<M: Memory + Default, S: Storage + Default + Clone>(
+        &mut self, machine: &mut Machine<M, S>
+    ) -> Result<(), RequireError> {
+        let opcode = match machine.status() {
+            MachineStatus::Running => machine.pc().peek_opcode().ok(),
+            _ => None,
+        };
+        let opcode = match opcode {
+            Some(opcode) => opcode,
+            None => return machine.step(),
+        };
+
+        let state = machine.state();
+        let address = state.context.address;
+        let len = state.stack.len();
+        let mut log = StructLog {
+            pc: machine.pc().position(),
+            op: op_name(opcode),
+            gas: state.available_gas(),
+            gas_cost: Gas::zero(),
+            depth: state.depth + 1,
+            stack: (0..len).map(|i| state.stack.peek(len - i - 1).unwrap()).collect(),
+            memory: if self.config.memory { Some(machine.memory_dump()) } else { None },
+            storage: None,
+            error: None,
+        };
+
+        let slot = match Opcode::from(opcode) {
+            Opcode::SLOAD => state.stack.peek(0).ok().and_then(|index| {
+                state.account_state.storage(address).ok().map(|storage| (index, storage.read(index)))
+            }),
+            Opcode::SSTORE => match (state.stack.peek(0), state.stack.peek(1)) {
+                (Ok(index), Ok(value)) => Some((index, value)),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        machine.step()?;
+
+        if self.config.storage {
+            let storage = self.storage.entry(address).or_insert(BTreeMap::new());
+            match slot {
+                Some((index, value)) => { storage.insert(index, value); },
+                None => (),
+            }
+            log.storage = Some(storage.clone());
+        }
+        match machine.status() {
+            MachineStatus::ExitedErr(error) => log.error = Some(format!("{:?}", error)),
+            _ => log.gas_cost = log.gas - machine.state().available_gas(),
+        }
+        self.logs.push(log);
+        Ok(())
+    }
+
+    /// Get the recorded entries.
+    pub fn logs(&self) -> &[StructLog] {
+        self.logs.as_slice()
+    }
+
+    /// Serialize the recorded entries into a JSON array.
+    pub fn to_json(&self) -> String {
+        let logs: Vec<String> = self.logs.iter().map(|log| log.to_json()).collect();
+        format!("[{}]", logs.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::address::Address;
+    use utils::bigint::{M256, U256};
+    use utils::gas::Gas;
+    use utils::asm::assemble;
+    use vm::{Context, BlockHeaderBuilder, Patch, Fork, AccountCommitment, SeqMemory, HashMapStorage};
+    use vm::errors::RequireError;
+    use super::{StructLogger, StructLogConfig, Machine, MachineStatus};
+
+    type SeqMachine = Machine<SeqMemory, HashMapStorage>;
+
+    fn address(v: u64) -> Address {
+        Address::from(M256::from(v))
+    }
+
+    fn new_machine(address: Address, origin: Address, code: Vec<u8>) -> SeqMachine {
+        let context = Context {
+            address: address,
+            caller: origin,
+            code: code,
+            data: Vec::new(),
+            gas_limit: Gas::from(100000u64),
+            gas_price: Gas::zero(),
+            origin: origin,
+            value: U256::zero(),
+            is_static: false,
+            is_create: false,
+            blob_versioned_hashes: Vec::new(),
+        };
+        SeqMachine::new(context, BlockHeaderBuilder::new(Fork::Frontier).build(), Patch::new(Fork::Frontier), 0)
+    }
+
+    fn commit(machine: &mut SeqMachine, address: Address) {
+        machine.commit_account(AccountCommitment::Full {
+            nonce: M256::zero(),
+            address: address,
+            balance: U256::zero(),
+            storage: HashMapStorage::default(),
+            code: Vec::new(),
+        }).unwrap();
+    }
+
+    #[test]
+    fn struct_log_json() {
+        let origin = address(1);
+        let owner = address(2);
+        let code = assemble("PUSH1 3 PUSH1 1 SSTORE PUSH1 1 SLOAD PUSH1 0 MSTORE STOP").unwrap();
+
+        let mut machine = new_machine(owner, origin, code.clone());
+        let mut logger = StructLogger::new(StructLogConfig::default());
+        loop {
+            match machine.status() {
+                MachineStatus::Running => (),
+                _ => break,
+            }
+            match logger.step(&mut machine) {
+                Ok(()) => (),
+                Err(RequireError::Account(address)) |
+                Err(RequireError::AccountCode(address)) => commit(&mut machine, address),
+                Err(RequireError::Blockhash(_)) => panic!(),
+            }
+        }
+
+        let logs = logger.logs();
+        assert_eq!(logs.len(), 8);
+        assert_eq!(logs[2].op, "SSTORE");
+        assert_eq!(logs[2].gas_cost, Gas::from(20000u64));
+        assert_eq!(logs[3].gas, Gas::from(100000u64 - 20006));
+        assert_eq!(logs[4].depth, 1);
+        assert_eq!(logs[7].gas_cost, Gas::zero());
+        assert_eq!(logger.to_json().matches("\"pc\"").count(), 8);
+        assert_eq!(logs[2].to_json(),
+                   "{\"pc\":4,\"op\":\"SSTORE\",\"gas\":99994,\"gasCost\":20000,\"depth\":1,\
+                    \"stack\":[\"0x3\",\"0x1\"],\"memory\":[],\"storage\":{\"\
+                    0000000000000000000000000000000000000000000000000000000000000001\":\"\
+                    0000000000000000000000000000000000000000000000000000000000000003\"}}");
+        assert_eq!(logs[7].memory.as_ref().unwrap().len(), 32);
+        assert!(logs[7].to_json().contains(
+            "\"memory\":[\"0000000000000000000000000000000000000000000000000000000000000003\"]"));
+
+        let mut machine = new_machine(owner, origin, code);
+        commit(&mut machine, owner);
+        let mut logger = StructLogger::new(StructLogConfig { memory: false, storage: false });
+        while let MachineStatus::Running = machine.status() {
+            logger.step(&mut machine).unwrap();
+        }
+        assert_eq!(logger.logs().len(), 8);
+        assert_eq!(logger.logs()[4].to_json(),
+                   "{\"pc\":7,\"op\":\"SLOAD\",\"gas\":79991,\"gasCost\":50,\"depth\":1,\"stack\":[\"0x1\"]}");
+    }
+}
@@ -0,0 +1,47 @@
+//! Compact execution trace
+
+use utils::gas::Gas;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+/// A single step recorded by the compact trace of a `Machine`.
+pub struct TraceStep {
+    /// Position of the instruction in the code.
+    pub position: usize,
+    /// The raw opcode byte.
+    pub opcode: u8,
+    /// Available gas before the instruction is executed.
+    pub gas: Gas,
+    /// Stack length before the instruction is executed.
+    pub stack_len: usize,
+    /// Active memory size in words before the instruction is
+    /// executed.
+    pub memory_cost: Gas,
+}
+
+/// Length in bytes of one serialized `TraceStep`.
+pub const TRACE_STEP_LEN: usize = 27;
+
+fn write_u64(out: &mut Vec<u8>, value: u64) {
+    for i in 0..8 {
+        out.push((value >> (8 * (7 - i))) as u8);
+    }
+}
+
+/// Serialize a trace into bytes. Each step takes `TRACE_STEP_LEN`
+/// bytes: big-endian `u64` position, the opcode byte, big-endian
+/// `u64` gas, big-endian `u16` stack length and big-endian `u64`
+/// memory cost. Gas and memory cost saturate at `u64::max_value()`.
+pub fn serialize_trace(trace: &[TraceStep]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(trace.len() * TRACE_STEP_LEN);
+    for step in trace {
+        write_u64(&mut out, step.position as u64);
+        out.push(step.opcode);
+        write_u64(&mut out, step.gas.as_u64_saturating());
+        out.push((step.stack_len >> 8) as u8);
+        out.push(step.stack_len as u8);
+        write_u64(&mut out, step.memory_cost.as_u64_saturating());
+    }
+    out
+}